@@ -0,0 +1,154 @@
+-- The schema as it stood when migrations were introduced. Every statement is
+-- guarded so this also applies cleanly to databases created before then.
+
+CREATE TABLE IF NOT EXISTS "customers" (
+	"cust_id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"address"	TEXT NOT NULL,
+	"email"	TEXT NOT NULL,
+	"phone_number"	TEXT,
+	PRIMARY KEY("cust_id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "orders" (
+	"order_id"	INTEGER NOT NULL UNIQUE,
+	"cust_id"	INTEGER NOT NULL,
+	PRIMARY KEY("order_id" AUTOINCREMENT),
+	FOREIGN KEY("cust_id") REFERENCES "customers"("cust_id") ON UPDATE CASCADE ON DELETE RESTRICT
+);
+
+CREATE TABLE IF NOT EXISTS "products" (
+	"product_id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"desc"	TEXT,
+	"image"	BLOB,
+	"price"	float,
+	"quantity"	float,
+	PRIMARY KEY("product_id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "product_variants" (
+	"var_id"	INTEGER NOT NULL UNIQUE,
+	"quantity"	INTEGER NOT NULL,
+	"tag_name"	TEXT NOT NULL,
+	"product_id"	INTEGER NOT NULL,
+	"image"	BLOB,
+	PRIMARY KEY("var_id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "ordered_products" (
+	"oproduct_id"	INTEGER NOT NULL UNIQUE,
+	"product_id"	INTEGER NOT NULL,
+	"var_id"	INTEGER,
+	"order_id"	INTEGER NOT NULL,
+	"quantity"	INTEGER NOT NULL,
+	PRIMARY KEY("oproduct_id" AUTOINCREMENT),
+	FOREIGN KEY("order_id") REFERENCES "orders"("order_id") ON UPDATE CASCADE ON DELETE RESTRICT,
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE RESTRICT
+);
+
+CREATE TABLE IF NOT EXISTS "website_information" (
+	"name"	TEXT UNIQUE,
+	"desc"	TEXT
+);
+
+INSERT OR IGNORE INTO "website_information" ("name", "desc") VALUES
+	('aboutClub1', ''),
+	('aboutClub2', ''),
+	('clubHistory', ''),
+	('clubActivities', ''),
+	('joinClub', ''),
+	('contact_email', ''),
+	('contact_address', '');
+
+CREATE TABLE IF NOT EXISTS "admins" (
+	"username"	TEXT NOT NULL UNIQUE,
+	"salt"	TEXT NOT NULL UNIQUE,
+	"password"	TEXT NOT NULL,
+	"expiration"	TEXT NOT NULL,
+	"status"	TEXT NOT NULL DEFAULT 'active' CHECK ("status" IN ('active', 'expiring', 'disabled')),
+	PRIMARY KEY("username")
+);
+
+CREATE TABLE IF NOT EXISTS "permissions" (
+	"username"	TEXT NOT NULL,
+	"permission"	TEXT NOT NULL,
+	FOREIGN KEY("username") REFERENCES "admins"("username") ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "permissions_username_permission" ON "permissions" ("username", "permission");
+
+CREATE TABLE IF NOT EXISTS "sessions" (
+	"session_id"	INTEGER NOT NULL UNIQUE,
+	"username"	TEXT NOT NULL,
+	"token_hash"	TEXT NOT NULL UNIQUE,
+	"created_at"	TEXT NOT NULL,
+	"last_seen"	TEXT NOT NULL,
+	"expires_at"	TEXT NOT NULL,
+	"ip"	TEXT,
+	"user_agent"	TEXT,
+	PRIMARY KEY("session_id" AUTOINCREMENT),
+	FOREIGN KEY("username") REFERENCES "admins"("username") ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sessions_username" ON "sessions" ("username");
+
+CREATE TABLE IF NOT EXISTS "login_attempts" (
+	"scope"	TEXT NOT NULL,
+	"key"	TEXT NOT NULL,
+	"failures"	INTEGER NOT NULL DEFAULT 0,
+	"last_failure"	TEXT NOT NULL,
+	"locked_until"	TEXT,
+	PRIMARY KEY("scope", "key")
+);
+
+CREATE TABLE IF NOT EXISTS "admin_totp" (
+	"username"	TEXT NOT NULL UNIQUE,
+	"secret"	TEXT NOT NULL,
+	"enabled"	INTEGER NOT NULL DEFAULT 0,
+	"last_used_step"	INTEGER,
+	PRIMARY KEY("username"),
+	FOREIGN KEY("username") REFERENCES "admins"("username") ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "recovery_codes" (
+	"code_id"	INTEGER NOT NULL UNIQUE,
+	"username"	TEXT NOT NULL,
+	"code_hash"	TEXT NOT NULL,
+	"used_at"	TEXT,
+	PRIMARY KEY("code_id" AUTOINCREMENT),
+	FOREIGN KEY("username") REFERENCES "admins"("username") ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "password_resets" (
+	"reset_id"	INTEGER NOT NULL UNIQUE,
+	"username"	TEXT NOT NULL,
+	"token_hash"	TEXT NOT NULL UNIQUE,
+	"created_by"	TEXT,
+	"created_at"	TEXT NOT NULL,
+	"expires_at"	TEXT NOT NULL,
+	"used_at"	TEXT,
+	PRIMARY KEY("reset_id" AUTOINCREMENT),
+	FOREIGN KEY("username") REFERENCES "admins"("username") ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+-- Deliberately no foreign key on "actor", so entries outlive deleted admins
+CREATE TABLE IF NOT EXISTS "audit_log" (
+	"audit_id"	INTEGER NOT NULL,
+	"actor"	TEXT NOT NULL,
+	"action"	TEXT NOT NULL,
+	"target"	TEXT NOT NULL,
+	"before"	TEXT,
+	"after"	TEXT,
+	"created_at"	TEXT NOT NULL,
+	"ip"	TEXT,
+	PRIMARY KEY("audit_id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "audit_log_created_at" ON "audit_log" ("created_at");
+CREATE INDEX IF NOT EXISTS "audit_log_actor" ON "audit_log" ("actor");
+CREATE TRIGGER IF NOT EXISTS "audit_log_no_update" BEFORE UPDATE ON "audit_log"
+BEGIN
+	SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+CREATE TRIGGER IF NOT EXISTS "audit_log_no_delete" BEFORE DELETE ON "audit_log"
+BEGIN
+	SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
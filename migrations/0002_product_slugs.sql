@@ -0,0 +1,17 @@
+-- Products are now addressed by id or by a unique, URL-friendly slug instead
+-- of by name. Existing products get a slug from their name; any that clash
+-- get their id appended.
+
+ALTER TABLE "products" ADD COLUMN "slug" TEXT;
+
+UPDATE "products" SET "slug" = trim(
+	replace(replace(replace(replace(replace(replace(replace(
+		lower(trim("name")), ' ', '-'), '''', ''), '"', ''), '/', '-'), '?', ''), '#', ''), '&', 'and'),
+	'-');
+-- Slugs share a route with numeric ids, so each needs at least one letter
+UPDATE "products" SET "slug" = 'product' WHERE "slug" = '';
+UPDATE "products" SET "slug" = 'product-' || "slug" WHERE "slug" NOT GLOB '*[a-z]*';
+UPDATE "products" SET "slug" = "slug" || '-' || "product_id"
+WHERE "product_id" NOT IN (SELECT MIN("product_id") FROM "products" GROUP BY "slug");
+
+CREATE UNIQUE INDEX IF NOT EXISTS "products_slug" ON "products" ("slug");
@@ -0,0 +1,43 @@
+-- Prices are stored as whole cents and stock as whole units, so totals add up
+-- exactly. SQLite can't change a column's type, so products is rebuilt.
+
+CREATE TABLE "products_new" (
+	"product_id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL,
+	"desc"	TEXT,
+	"image"	BLOB,
+	"price_cents"	INTEGER NOT NULL DEFAULT 0 CHECK ("price_cents" >= 0),
+	"quantity"	INTEGER NOT NULL DEFAULT 0 CHECK ("quantity" >= 0),
+	"slug"	TEXT,
+	PRIMARY KEY("product_id" AUTOINCREMENT)
+);
+
+INSERT INTO "products_new" ("product_id", "name", "desc", "image", "price_cents", "quantity", "slug")
+SELECT "product_id", "name", "desc", "image",
+	MAX(CAST(ROUND(COALESCE("price", 0) * 100) AS INTEGER), 0),
+	MAX(CAST(ROUND(COALESCE("quantity", 0)) AS INTEGER), 0),
+	"slug"
+FROM "products";
+
+-- Keep ids of deleted products from being handed out again
+INSERT INTO "sqlite_sequence" ("name", "seq")
+SELECT 'products_new', "seq" FROM "sqlite_sequence" WHERE "name" = 'products'
+AND NOT EXISTS (SELECT 1 FROM "sqlite_sequence" WHERE "name" = 'products_new');
+UPDATE "sqlite_sequence"
+SET "seq" = MAX("seq", COALESCE((SELECT "seq" FROM "sqlite_sequence" WHERE "name" = 'products'), 0))
+WHERE "name" = 'products_new';
+
+DROP TABLE "products";
+ALTER TABLE "products_new" RENAME TO "products";
+CREATE UNIQUE INDEX IF NOT EXISTS "products_slug" ON "products" ("slug");
+
+-- Orders keep the price each item sold for, and their total
+ALTER TABLE "ordered_products" ADD COLUMN "unit_price_cents" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "orders" ADD COLUMN "total_cents" INTEGER NOT NULL DEFAULT 0;
+
+-- Older orders never recorded a price; today's price is the best estimate
+UPDATE "ordered_products" SET "unit_price_cents" = COALESCE(
+	(SELECT "price_cents" FROM "products" WHERE "products"."product_id" = "ordered_products"."product_id"), 0);
+UPDATE "orders" SET "total_cents" = COALESCE(
+	(SELECT SUM("unit_price_cents" * "quantity") FROM "ordered_products"
+	 WHERE "ordered_products"."order_id" = "orders"."order_id"), 0);
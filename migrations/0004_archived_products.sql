@@ -0,0 +1,40 @@
+-- Products that shouldn't be sold any more are archived rather than deleted,
+-- so past orders can still resolve them.
+
+ALTER TABLE "products" ADD COLUMN "archived_at" TEXT;
+
+-- Orders that point at a product deleted before this migration get an
+-- archived stand-in, so the foreign key below holds
+INSERT INTO "products" ("product_id", "name", "price_cents", "quantity", "slug", "archived_at")
+SELECT DISTINCT "product_id", 'Deleted product ' || "product_id", 0, 0,
+	'deleted-product-' || "product_id", strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+FROM "ordered_products"
+WHERE "product_id" NOT IN (SELECT "product_id" FROM "products");
+
+-- SQLite can't add a foreign key to an existing table, so ordered_products is rebuilt
+CREATE TABLE "ordered_products_new" (
+	"oproduct_id"	INTEGER NOT NULL UNIQUE,
+	"product_id"	INTEGER NOT NULL,
+	"var_id"	INTEGER,
+	"order_id"	INTEGER NOT NULL,
+	"quantity"	INTEGER NOT NULL,
+	"unit_price_cents"	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("oproduct_id" AUTOINCREMENT),
+	FOREIGN KEY("order_id") REFERENCES "orders"("order_id") ON UPDATE CASCADE ON DELETE RESTRICT,
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE RESTRICT,
+	FOREIGN KEY("product_id") REFERENCES "products"("product_id") ON UPDATE CASCADE ON DELETE RESTRICT
+);
+
+INSERT INTO "ordered_products_new" ("oproduct_id", "product_id", "var_id", "order_id", "quantity", "unit_price_cents")
+SELECT "oproduct_id", "product_id", "var_id", "order_id", "quantity", "unit_price_cents"
+FROM "ordered_products";
+
+INSERT INTO "sqlite_sequence" ("name", "seq")
+SELECT 'ordered_products_new', "seq" FROM "sqlite_sequence" WHERE "name" = 'ordered_products'
+AND NOT EXISTS (SELECT 1 FROM "sqlite_sequence" WHERE "name" = 'ordered_products_new');
+UPDATE "sqlite_sequence"
+SET "seq" = MAX("seq", COALESCE((SELECT "seq" FROM "sqlite_sequence" WHERE "name" = 'ordered_products'), 0))
+WHERE "name" = 'ordered_products_new';
+
+DROP TABLE "ordered_products";
+ALTER TABLE "ordered_products_new" RENAME TO "ordered_products";
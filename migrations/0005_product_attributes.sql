@@ -0,0 +1,79 @@
+-- Variants are described by admin-defined attributes (Size, Color, Material,
+-- ...) with a fixed list of allowed values, instead of a space-separated
+-- `tag_name` string.
+
+CREATE TABLE IF NOT EXISTS "attribute_types" (
+	"type_id"	INTEGER NOT NULL UNIQUE,
+	"name"	TEXT NOT NULL UNIQUE COLLATE NOCASE,
+	PRIMARY KEY("type_id" AUTOINCREMENT)
+);
+
+CREATE TABLE IF NOT EXISTS "attribute_values" (
+	"value_id"	INTEGER NOT NULL UNIQUE,
+	"type_id"	INTEGER NOT NULL,
+	"value"	TEXT NOT NULL COLLATE NOCASE,
+	PRIMARY KEY("value_id" AUTOINCREMENT),
+	UNIQUE("type_id", "value"),
+	UNIQUE("value_id", "type_id"),
+	FOREIGN KEY("type_id") REFERENCES "attribute_types"("type_id") ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+-- One value per attribute type per variant, and the value must belong to that type
+CREATE TABLE IF NOT EXISTS "variant_attributes" (
+	"var_id"	INTEGER NOT NULL,
+	"type_id"	INTEGER NOT NULL,
+	"value_id"	INTEGER NOT NULL,
+	PRIMARY KEY("var_id", "type_id"),
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("value_id", "type_id") REFERENCES "attribute_values"("value_id", "type_id") ON UPDATE CASCADE ON DELETE RESTRICT
+);
+
+CREATE INDEX IF NOT EXISTS "variant_attributes_value" ON "variant_attributes" ("value_id");
+
+-- The attributes the old tags knew about
+INSERT OR IGNORE INTO "attribute_types" ("name") VALUES ('Size'), ('Color'), ('Fit');
+INSERT OR IGNORE INTO "attribute_values" ("type_id", "value")
+SELECT "type_id", "value" FROM "attribute_types" JOIN (
+	SELECT 'Size' AS "type", 'small' AS "value" UNION ALL SELECT 'Size', 'medium'
+	UNION ALL SELECT 'Size', 'large' UNION ALL SELECT 'Color', 'white'
+	UNION ALL SELECT 'Color', 'red' UNION ALL SELECT 'Color', 'blue'
+	UNION ALL SELECT 'Fit', 'fitted' UNION ALL SELECT 'Fit', 'snap'
+) ON "name" = "type";
+
+-- Split each variant's tags, e.g. `sizesmall colorwhite fitted`. Tags the old
+-- code didn't understand are kept under a `Tag` attribute rather than lost.
+CREATE TEMP TABLE "variant_tags" AS
+WITH RECURSIVE "tokens" ("var_id", "token", "rest") AS (
+	SELECT "var_id", '', trim("tag_name") || ' ' FROM "product_variants"
+	UNION ALL
+	SELECT "var_id", substr("rest", 1, instr("rest", ' ') - 1), ltrim(substr("rest", instr("rest", ' ') + 1))
+	FROM "tokens" WHERE "rest" <> ''
+)
+SELECT "var_id",
+	CASE
+		WHEN lower("token") LIKE 'size_%' THEN 'Size'
+		WHEN lower("token") LIKE 'color_%' THEN 'Color'
+		WHEN lower("token") IN ('fitted', 'snap') THEN 'Fit'
+		ELSE 'Tag'
+	END AS "type",
+	CASE
+		WHEN lower("token") LIKE 'size_%' THEN lower(substr("token", 5))
+		WHEN lower("token") LIKE 'color_%' THEN lower(substr("token", 6))
+		ELSE lower("token")
+	END AS "value"
+FROM "tokens" WHERE "token" <> '';
+
+INSERT OR IGNORE INTO "attribute_types" ("name") SELECT DISTINCT "type" FROM "variant_tags";
+INSERT OR IGNORE INTO "attribute_values" ("type_id", "value")
+SELECT DISTINCT "type_id", "value" FROM "variant_tags" JOIN "attribute_types" ON "name" = "type";
+
+-- A variant with two tags of the same type keeps only one of them
+INSERT OR IGNORE INTO "variant_attributes" ("var_id", "type_id", "value_id")
+SELECT "var_id", "attribute_values"."type_id", "value_id" FROM "variant_tags"
+JOIN "attribute_types" ON "name" = "type"
+JOIN "attribute_values" ON "attribute_values"."type_id" = "attribute_types"."type_id"
+	AND "attribute_values"."value" = "variant_tags"."value";
+
+DROP TABLE "variant_tags";
+
+ALTER TABLE "product_variants" DROP COLUMN "tag_name";
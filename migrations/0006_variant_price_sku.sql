@@ -0,0 +1,7 @@
+-- A variant can cost more or less than its product, e.g. a 2XL shirt, and
+-- carries its own stock keeping unit. A null price means the product's price.
+
+ALTER TABLE "product_variants" ADD COLUMN "price_cents" INTEGER CHECK ("price_cents" >= 0);
+ALTER TABLE "product_variants" ADD COLUMN "sku" TEXT COLLATE NOCASE;
+
+CREATE UNIQUE INDEX IF NOT EXISTS "product_variants_sku" ON "product_variants" ("sku");
@@ -0,0 +1,14 @@
+-- Images are kept as files named by the SHA-256 of their contents, and
+-- products and variants point at them. The `image` BLOB columns stay until
+-- the startup import has moved their images into files.
+
+CREATE TABLE IF NOT EXISTS "images" (
+	"file"	TEXT NOT NULL,
+	"content_type"	TEXT NOT NULL,
+	"bytes"	INTEGER NOT NULL,
+	"uploaded_at"	TEXT NOT NULL,
+	PRIMARY KEY("file")
+);
+
+ALTER TABLE "products" ADD COLUMN "image_file" TEXT REFERENCES "images"("file") ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE "product_variants" ADD COLUMN "image_file" TEXT REFERENCES "images"("file") ON UPDATE CASCADE ON DELETE SET NULL;
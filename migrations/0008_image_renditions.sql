@@ -0,0 +1,6 @@
+-- Every stored image gets resized WebP and JPEG copies, made when it's stored.
+-- The size of the original is recorded once they exist; images stored before
+-- this have none yet and get their copies when the server starts.
+
+ALTER TABLE "images" ADD COLUMN "width" INTEGER;
+ALTER TABLE "images" ADD COLUMN "height" INTEGER;
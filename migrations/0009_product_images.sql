@@ -0,0 +1,45 @@
+-- Products and variants each have an ordered gallery of images instead of a
+-- single one. Every image needs alt text, and each gallery has at most one
+-- primary image, shown first and in listings.
+
+CREATE TABLE IF NOT EXISTS "product_images" (
+	"image_id"	INTEGER NOT NULL UNIQUE,
+	"product_id"	INTEGER NOT NULL,
+	-- Null for the product's own gallery
+	"var_id"	INTEGER,
+	"file"	TEXT NOT NULL,
+	"alt"	TEXT NOT NULL CHECK (trim("alt") <> ''),
+	"position"	INTEGER NOT NULL,
+	"is_primary"	INTEGER NOT NULL DEFAULT 0 CHECK ("is_primary" IN (0, 1)),
+	PRIMARY KEY("image_id" AUTOINCREMENT),
+	FOREIGN KEY("product_id") REFERENCES "products"("product_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("file") REFERENCES "images"("file") ON UPDATE CASCADE ON DELETE RESTRICT
+);
+
+CREATE INDEX IF NOT EXISTS "product_images_gallery" ON "product_images" ("product_id", "var_id", "position");
+CREATE UNIQUE INDEX IF NOT EXISTS "product_images_primary" ON "product_images" ("product_id", IFNULL("var_id", 0))
+	WHERE "is_primary" = 1;
+
+-- The single images become the primary image of each gallery. Their alt text
+-- is the product's name, with the variant's attribute values if it's a variant's.
+INSERT INTO "product_images" ("product_id", "var_id", "file", "alt", "position", "is_primary")
+SELECT "product_id", NULL, "image_file", "name", 0, 1 FROM "products" WHERE "image_file" IS NOT NULL;
+
+INSERT INTO "product_images" ("product_id", "var_id", "file", "alt", "position", "is_primary")
+SELECT "v"."product_id", "v"."var_id", "v"."image_file",
+	"p"."name" || COALESCE(' (' || (
+		SELECT group_concat("value", ', ') FROM (
+			SELECT "av"."value" FROM "variant_attributes" "va"
+			JOIN "attribute_values" "av" ON "av"."value_id" = "va"."value_id"
+			WHERE "va"."var_id" = "v"."var_id" ORDER BY "va"."type_id"
+		)
+	) || ')', ''),
+	0, 1
+FROM "product_variants" "v" JOIN "products" "p" ON "p"."product_id" = "v"."product_id"
+WHERE "v"."image_file" IS NOT NULL;
+
+-- SQLite can't drop a column that has a foreign key, so `image_file` stays,
+-- but nothing reads it any more
+UPDATE "products" SET "image_file" = NULL;
+UPDATE "product_variants" SET "image_file" = NULL;
@@ -0,0 +1,28 @@
+-- Carts are kept in the database instead of a cookie. The browser only holds
+-- an opaque token, stored here as a hash like session tokens, and items name
+-- what's in the cart but never its price.
+
+CREATE TABLE IF NOT EXISTS "carts" (
+	"cart_id"	INTEGER NOT NULL UNIQUE,
+	"token_hash"	TEXT NOT NULL UNIQUE,
+	"created_at"	TEXT NOT NULL,
+	"updated_at"	TEXT NOT NULL,
+	"expires_at"	TEXT NOT NULL,
+	PRIMARY KEY("cart_id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "carts_expires_at" ON "carts" ("expires_at");
+
+CREATE TABLE IF NOT EXISTS "cart_items" (
+	"item_id"	INTEGER NOT NULL UNIQUE,
+	"cart_id"	INTEGER NOT NULL,
+	"product_id"	INTEGER NOT NULL,
+	"var_id"	INTEGER,
+	"quantity"	INTEGER NOT NULL CHECK ("quantity" > 0),
+	"added_at"	TEXT NOT NULL,
+	PRIMARY KEY("item_id" AUTOINCREMENT),
+	FOREIGN KEY("cart_id") REFERENCES "carts"("cart_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("product_id") REFERENCES "products"("product_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE CASCADE
+);
+-- One line per product or variant in a cart
+CREATE UNIQUE INDEX IF NOT EXISTS "cart_items_line" ON "cart_items" ("cart_id", "product_id", IFNULL("var_id", 0));
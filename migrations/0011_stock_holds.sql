@@ -0,0 +1,17 @@
+-- Stock held for a cart while its owner is in checkout, so nobody else can buy
+-- it out from under them. A hold stops counting once it expires.
+
+CREATE TABLE IF NOT EXISTS "stock_holds" (
+	"hold_id"	INTEGER NOT NULL UNIQUE,
+	"cart_id"	INTEGER NOT NULL,
+	"product_id"	INTEGER NOT NULL,
+	"var_id"	INTEGER,
+	"quantity"	INTEGER NOT NULL CHECK ("quantity" > 0),
+	"expires_at"	TEXT NOT NULL,
+	PRIMARY KEY("hold_id" AUTOINCREMENT),
+	FOREIGN KEY("cart_id") REFERENCES "carts"("cart_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("product_id") REFERENCES "products"("product_id") ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY("var_id") REFERENCES "product_variants"("var_id") ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "stock_holds_item" ON "stock_holds" ("product_id", "var_id", "expires_at");
+CREATE INDEX IF NOT EXISTS "stock_holds_cart" ON "stock_holds" ("cart_id");
@@ -0,0 +1,23 @@
+// Sends the CSRF token cookie back in the X-CSRF-Token header on every
+// state-changing request, which the server requires for all /api calls.
+(function () {
+    const originalFetch = window.fetch;
+
+    function csrfToken() {
+        const match = document.cookie.match(/(?:^|;\s*)csrf_token=([^;]*)/);
+        return match ? decodeURIComponent(match[1]) : null;
+    }
+
+    window.fetch = function (resource, options = {}) {
+        const method = (options.method || 'GET').toUpperCase();
+        const token = csrfToken();
+
+        if (token && method !== 'GET' && method !== 'HEAD') {
+            const headers = new Headers(options.headers || {});
+            headers.set('X-CSRF-Token', token);
+            options = { ...options, headers };
+        }
+
+        return originalFetch(resource, options);
+    };
+})();
@@ -1,7 +1,7 @@
 use rocket::{
     fs::{FileServer, NamedFile},
     http::Method,
-    Route,
+    Build, Rocket, Route,
 };
 use rocket_db_pools::Database;
 
@@ -9,25 +9,42 @@ use rocket_db_pools::Database;
 extern crate rocket;
 
 mod api {
-    use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
-    use rand::{distributions::Alphanumeric, Rng};
+    use argon2::password_hash::{
+        rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    };
+    use argon2::{Argon2, Params};
+    use hmac::{Hmac, Mac};
+    use sha1::Sha1;
+    use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
     use rocket::form::Form;
     use rocket::fs::TempFile;
-    use crate::rocket::futures::TryFutureExt;
+    use rocket::data::{Data, ToByteUnit};
+    use rocket::fs::NamedFile;
+    use rocket::State;
+    use rand::{distributions::Alphanumeric, Rng};
+    use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+    use rocket::http::uri::Origin;
     use rocket::http::Cookie;
     use rocket::http::CookieJar;
+    use rocket::http::{Method, RawStr, SameSite};
     use rocket::http::Status;
+    use rocket::request::{FromRequest, Outcome, Request};
     use rocket::response::status::Custom;
+    use rocket::response::{self, Responder, Response};
     use rocket::tokio::io::AsyncReadExt;
     use rocket::{futures::StreamExt, serde::json::Json};
-    use rocket_db_pools::sqlx::sqlite::SqliteRow;
+    use rocket_db_pools::sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
     use rocket_db_pools::{
-        sqlx::{Row, SqlitePool},
+        sqlx::{Acquire, Column, Row, SqliteConnection, SqlitePool, TypeInfo, ValueRef},
         Connection, Database,
     };
     use serde::{Deserialize, Serialize, Deserializer};
     use serde::de::{self, Visitor};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
     use std::fmt;
+    use std::net::IpAddr;
+    use std::marker::PhantomData;
     use serde_json::Value;
     use sha2::{Digest, Sha256};
     use std::str::FromStr;
@@ -39,32 +56,252 @@ mod api {
     #[database("db")]
     pub(super) struct RoboDatabase(SqlitePool);
 
+    /// Schema migrations as `(version, name, sql)`, applied in order at launch.
+    /// Never edit one that has been committed; add a new file instead.
+    const MIGRATIONS: &[(i64, &str, &str)] = &[
+        (1, "initial", include_str!("../migrations/0001_initial.sql")),
+        (2, "product_slugs", include_str!("../migrations/0002_product_slugs.sql")),
+        (3, "money_cents", include_str!("../migrations/0003_money_cents.sql")),
+        (4, "archived_products", include_str!("../migrations/0004_archived_products.sql")),
+        (5, "product_attributes", include_str!("../migrations/0005_product_attributes.sql")),
+        (6, "variant_price_sku", include_str!("../migrations/0006_variant_price_sku.sql")),
+        (7, "image_files", include_str!("../migrations/0007_image_files.sql")),
+        (8, "image_renditions", include_str!("../migrations/0008_image_renditions.sql")),
+        (9, "product_images", include_str!("../migrations/0009_product_images.sql")),
+        (10, "carts", include_str!("../migrations/0010_carts.sql")),
+        (11, "stock_holds", include_str!("../migrations/0011_stock_holds.sql")),
+    ];
+
+    /// Brings the database up to the latest schema, recording each migration in
+    /// `schema_version`. Each migration runs in its own transaction, so a failed
+    /// one leaves the database at the previous version. Returns the versions applied.
+    async fn run_migrations(pool: &SqlitePool) -> Result<Vec<i64>, rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TEXT NOT NULL
+            )",
+        )
+        .execute(pool)
+        .await?;
+
+        let current: i64 =
+            rocket_db_pools::sqlx::query("SELECT COALESCE(MAX(version), 0) AS v FROM schema_version")
+                .fetch_one(pool)
+                .await?
+                .get("v");
+
+        let mut applied = vec![];
+        for &(version, name, sql) in MIGRATIONS.iter().filter(|(v, ..)| *v > current) {
+            let mut tx = pool.begin().await?;
+            rocket_db_pools::sqlx::Executor::execute(&mut *tx, sql).await?;
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO schema_version (version, name, applied_at) VALUES (?, ?, ?)",
+            )
+            .bind(version)
+            .bind(name)
+            .bind(timestamp(Utc::now()))
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            applied.push(version);
+        }
+        Ok(applied)
+    }
+
+    /// Runs [`run_migrations`] once the database pool is up, refusing to launch if
+    /// any migration fails. Attach it right after [`RoboDatabase::init`].
+    pub(super) fn migrations() -> AdHoc {
+        AdHoc::try_on_ignite("Database Migrations", |rocket| async {
+            let Some(db) = RoboDatabase::fetch(&rocket) else {
+                eprintln!("Can't run migrations: database is not attached.");
+                return Err(rocket);
+            };
+            match run_migrations(db).await {
+                Ok(applied) => {
+                    for version in applied {
+                        println!("Applied database migration {version:04}.");
+                    }
+                    Ok(rocket)
+                }
+                Err(e) => {
+                    eprintln!("Database migration failed: {e}");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
     #[derive(Serialize, Deserialize)]
     struct Description {
         name: String,
         desc: String,
     }
 
-    #[derive(Serialize, Deserialize, FromForm)]
+    /// An amount of money in whole cents, so sums and totals never drift.
+    /// JSON carries it as a number of dollars (`12.5`). Numbers are rounded to
+    /// the nearest cent; strings such as `"12.50"` are parsed exactly.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+    struct Money(i64);
+
+    impl Money {
+        fn from_cents(cents: i64) -> Self {
+            Money(cents)
+        }
+
+        fn cents(self) -> i64 {
+            self.0
+        }
+
+        fn from_dollars(dollars: f64) -> Option<Self> {
+            let cents = (dollars * 100.0).round();
+            (cents.is_finite() && cents.abs() < i64::MAX as f64).then_some(Money(cents as i64))
+        }
+    }
+
+    impl FromStr for Money {
+        type Err = String;
+
+        /// Parses dollars such as `12`, `12.5` or `$12.50`, refusing fractions of a cent.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || format!("`{s}` is not an amount of money.");
+            let amount = s.trim();
+            let (negative, amount) = match amount.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, amount),
+            };
+            let amount = amount.strip_prefix('$').unwrap_or(amount);
+            let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+            let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+            if (whole.is_empty() && fraction.is_empty())
+                || fraction.len() > 2
+                || !digits(whole)
+                || !digits(fraction)
+            {
+                return Err(invalid());
+            }
+
+            let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+            let fraction: i64 = format!("{fraction:0<2}").parse().map_err(|_| invalid())?;
+            let cents = whole
+                .checked_mul(100)
+                .and_then(|cents| cents.checked_add(fraction))
+                .ok_or_else(invalid)?;
+            Ok(Money(if negative { -cents } else { cents }))
+        }
+    }
+
+    impl fmt::Display for Money {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let sign = if self.0 < 0 { "-" } else { "" };
+            let cents = self.0.unsigned_abs();
+            write!(f, "{sign}{}.{:02}", cents / 100, cents % 100)
+        }
+    }
+
+    impl std::ops::Add for Money {
+        type Output = Money;
+
+        fn add(self, other: Money) -> Money {
+            Money(self.0 + other.0)
+        }
+    }
+
+    impl std::ops::Mul<u32> for Money {
+        type Output = Money;
+
+        fn mul(self, quantity: u32) -> Money {
+            Money(self.0 * i64::from(quantity))
+        }
+    }
+
+    impl std::iter::Sum for Money {
+        fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+            iter.fold(Money::default(), |total, amount| total + amount)
+        }
+    }
+
+    impl Serialize for Money {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_f64(self.0 as f64 / 100.0)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Money {
+        fn deserialize<D>(deserializer: D) -> Result<Money, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct MoneyVisitor;
+
+            impl<'de> Visitor<'de> for MoneyVisitor {
+                type Value = Money;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    write!(formatter, "an amount of money in dollars")
+                }
+
+                fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+                    value
+                        .checked_mul(100)
+                        .map(Money)
+                        .ok_or_else(|| E::custom("amount of money is too large"))
+                }
+
+                fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+                    i64::try_from(value)
+                        .map_err(|_| E::custom("amount of money is too large"))
+                        .and_then(|value| self.visit_i64(value))
+                }
+
+                fn visit_f64<E: de::Error>(self, value: f64) -> Result<Money, E> {
+                    Money::from_dollars(value).ok_or_else(|| E::custom("amount of money is out of range"))
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+                    value.parse().map_err(E::custom)
+                }
+            }
+
+            deserializer.deserialize_any(MoneyVisitor)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
     struct Product {
         id: Option<i32>,
         name: String,
         desc: String,
-        price: f32,
-        image: Option<std::string::String>, // Store the image as binary data
-        quantity: f32,
+        price: Money,
+        /// A new image, base64-encoded. Only read when adding a product, where
+        /// it becomes the primary image.
+        #[serde(default, skip_serializing)]
+        image: Option<std::string::String>,
+        /// The primary image's alt text. When writing, the alt text for `image`.
+        #[serde(default)]
+        image_alt: Option<String>,
+        /// Where the primary image is served from, as uploaded.
+        #[serde(default, skip_deserializing)]
+        image_url: Option<String>,
+        /// Smaller copies of the primary image, which listings should use instead.
+        #[serde(default, skip_deserializing)]
+        image_sizes: Option<ImageSizes>,
+        /// Every image of the product, in order. Filled in by `load_product_images`.
+        #[serde(default, skip_deserializing)]
+        images: Vec<GalleryImage>,
+        quantity: u32,
+        slug: Option<String>,
+        /// When the product stopped being sold. Archived products are hidden
+        /// from the shop but still resolve for past orders.
+        #[serde(default, skip_deserializing)]
+        archived_at: Option<String>,
     }
 
     impl TryFrom<SqliteRow> for Product {
         type Error = String;
     
         fn try_from(value: SqliteRow) -> Result<Self, Self::Error> {
-            // Attempt to fetch the image blob from the database
-            let image_blob: Option<Vec<u8>> = value.try_get("image").ok();
-            
-            // Convert the image blob to a Base64-encoded string
-            let image_base64 = image_blob.map(|blob| STANDARD.encode(&blob));
-    
             Ok(Self {
                 id: value
                     .try_get("product_id")
@@ -76,124 +313,92 @@ mod api {
                     .try_get("desc")
                     .map_err(|e| format!("Could not get `desc`: {e}"))?,
                 price: value
-                    .try_get("price")
-                    .map_err(|e| format!("Could not get `price`: {e}"))?,
-                image: image_base64,
+                    .try_get("price_cents")
+                    .map(Money::from_cents)
+                    .map_err(|e| format!("Could not get `price_cents`: {e}"))?,
+                image: None,
+                image_alt: None,
+                image_url: None,
+                image_sizes: None,
+                images: Vec::new(),
                 quantity: value
                     .try_get("quantity")
                     .map_err(|e| format!("Could not get `quantity`: {e}"))?,
+                slug: value.try_get("slug").unwrap_or_default(),
+                archived_at: value.try_get("archived_at").unwrap_or_default(),
             })
         }
     }
 
-    #[derive(Serialize, PartialEq)]
-    enum VarTag {
-        Size(String),
-        Color(String),
-        Fitted(bool),
-    }
-    impl FromStr for VarTag {
-        type Err = ();
-
-        fn from_str(s: &str) -> Result<Self, Self::Err> {
-            if s.starts_with("size") {
-                Ok(VarTag::Size(s[4..].to_owned()))
-            } else if s.starts_with("color") {
-                Ok(VarTag::Color(s[5..].to_owned()))
-            } else if s == "fitted" {
-                Ok(VarTag::Fitted(true))
-            } else if s == "snap" {
-                Ok(VarTag::Fitted(false))
-            } else {
-                eprintln!("Couldnt match `{s}`");
-                Err(())
-            }
-        }
-    }
-    impl ToString for VarTag {
-        fn to_string(&self) -> String {
-            match self {
-                VarTag::Size(s) => format!("{}", s.to_lowercase().capitalize()),
-                VarTag::Color(c) => format!("{}", c.to_lowercase().capitalize()),
-                VarTag::Fitted(f) => {
-                    if *f {
-                        "Fitted".to_string()
-                    } else {
-                        "Snap".to_string()
-                    }
-                }
-            }
-        }
-    }
-    impl<'de> Deserialize<'de> for VarTag {
-        fn deserialize<D>(deserializer: D) -> Result<VarTag, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            struct VarTagVisitor;
-    
-            impl<'de> Visitor<'de> for VarTagVisitor {
-                type Value = VarTag;
-    
-                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                    write!(formatter, "a string representing a valid tag")
-                }
-    
-                fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-                where
-                    E: de::Error,
-                {
-                    if value.starts_with("size") {
-                        Ok(VarTag::Size(value[4..].to_string()))
-                    } else if value.starts_with("color") {
-                        Ok(VarTag::Color(value[5..].to_string()))
-                    } else if value == "fitted" {
-                        Ok(VarTag::Fitted(true))
-                    } else if value == "snap" {
-                        Ok(VarTag::Fitted(false))
-                    } else {
-                        Err(de::Error::unknown_field(value, &["size", "color", "fitted"]))
-                    }
-                }
-            }
-    
-            deserializer.deserialize_str(VarTagVisitor)
-        }
-    }
+    /// The one JSON shape of a variant. Every read route returns it, and
+    /// `add_variant` and `modify_variant` accept it back unchanged.
     #[derive(Serialize, Deserialize)]
     struct ProductVariant {
         quantity: Option<u32>,
-        tag_name: Vec<VarTag>,
+        /// What this variant costs when it differs from the product's price.
+        /// Unlike `quantity`, it's written as given, so `null` clears it.
+        #[serde(default)]
+        price: Option<Money>,
+        /// Stock keeping unit, unique across all variants. Written as given.
+        #[serde(default)]
+        sku: Option<String>,
+        /// Attribute name to value, e.g. `{"Size": "small", "Color": "white"}`.
+        /// When changing a variant, leaving it out keeps the current ones and
+        /// `{}` removes them all.
+        #[serde(default)]
+        attributes: Option<BTreeMap<String, String>>,
+        /// The values in attribute order, for display, e.g. `Small White`.
+        /// Derived from `attributes`, so it's ignored when writing.
+        #[serde(default, skip_deserializing)]
+        tag_name: String,
         product: u32,
         varid: Option<u32>,
-        image: Option<std::string::String>
+        /// A new image, base64-encoded, which becomes the variant's primary
+        /// image. Leaving it out keeps the current one.
+        #[serde(default, skip_serializing)]
+        image: Option<std::string::String>,
+        /// The primary image's alt text. When writing, the alt text for `image`.
+        #[serde(default)]
+        image_alt: Option<String>,
+        /// Where the variant's own primary image is served from, if it has one.
+        #[serde(default, skip_deserializing)]
+        image_url: Option<String>,
+        #[serde(default, skip_deserializing)]
+        image_sizes: Option<ImageSizes>,
+        /// The variant's own images, in order.
+        #[serde(default, skip_deserializing)]
+        images: Vec<GalleryImage>,
     }
 
     impl TryFrom<SqliteRow> for ProductVariant {
         type Error = String;
     
         fn try_from(value: SqliteRow) -> Result<Self, Self::Error> {
-            let image_blob: Option<Vec<u8>> = value.try_get("image").ok();
-            
-            // Convert the image blob to a Base64-encoded string
-            let image_base64 = image_blob.map(|blob| STANDARD.encode(&blob));
             Ok(Self {
                 quantity: value
                     .try_get::<Option<u32>, _>("quantity")
                     .map_err(|e| format!("Could not get `quantity` {e}"))?,
-                tag_name: value
-                    .try_get::<String, _>("tag_name")
-                    .map_err(|e| format!("Could not get `tag_name` {e}"))?
-                    .split_whitespace()
-                    .filter_map(|s| s.parse().ok())
-                    .collect(),
+                price: value
+                    .try_get::<Option<i64>, _>("price_cents")
+                    .map_err(|e| format!("Could not get `price_cents` {e}"))?
+                    .map(Money::from_cents),
+                sku: value
+                    .try_get("sku")
+                    .map_err(|e| format!("Could not get `sku` {e}"))?,
+                // Filled in by `load_variant_attributes` and `load_variant_images`
+                attributes: Some(BTreeMap::new()),
+                tag_name: String::new(),
                 product: value
                     .try_get("product_id")
                     .map_err(|e| format!("Could not get `product_id` {e}"))?,
                 varid: value
                     .try_get("var_id")
                     .map_err(|e| format!("Could not get `var_id` {e}"))?,
-                image: image_base64,
+                image: None,
+                image_alt: None,
+                image_url: None,
+                image_sizes: None,
+                images: Vec::new(),
             })
         }
     }
@@ -228,6 +433,13 @@ mod api {
         username: String,
         password: String,
         expiration: String,
+        permissions: Vec<String>,
+    }
+
+    #[derive(FromForm)]
+    struct ExtendAdmins {
+        usernames: Vec<String>,
+        expiration: String,
     }
 
     #[derive(FromForm)]
@@ -236,48 +448,75 @@ mod api {
         password: String,
     }
 
-    #[derive(FromForm, Serialize, Deserialize)]
-    struct AdminUser {
-        id: i32,
-        username: String,
+    #[derive(FromForm)]
+    struct ChangePassword {
+        current_password: String,
+        new_password: String,
+    }
+
+    #[derive(FromForm)]
+    struct ResetPassword {
+        token: String,
+        new_password: String,
+    }
+
+    #[derive(FromForm)]
+    struct TotpCode {
+        code: String,
     }
 
     #[derive(Serialize)]
     struct CurrentUserResponse {
         username: String,
+        permissions: Vec<String>,
+        expiration: String,
+        status: String,
     }
 
     #[allow(private_interfaces)]
     #[get("/current_user")]
     pub async fn current_user(
-        jar: &CookieJar<'_>,
+        admin: AdminSession,
         mut db: Connection<RoboDatabase>,
-    ) -> Option<Json<CurrentUserResponse>> {
-        // Get the token from the cookie jar
-        if let Some(token_cookie) = jar.get("token") {
-            let token_value = token_cookie.value();
+    ) -> Result<Json<CurrentUserResponse>, Status> {
+        let row = rocket_db_pools::sqlx::query(
+            "SELECT expiration, status FROM admins WHERE username = ?",
+        )
+        .bind(&admin.username)
+        .fetch_one(&mut **db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(CurrentUserResponse {
+            username: admin.username,
+            permissions: admin.permissions,
+            expiration: row.get("expiration"),
+            status: row.get("status"),
+        }))
+    }
 
-            // Query the database to get the username for the token
-            if let Ok(row) =
-                rocket_db_pools::sqlx::query("SELECT username FROM admins WHERE token = ?")
-                    .bind(token_value)
-                    .fetch_one(&mut **db)
-                    .await
-            {
-                if let Ok(username) = row.try_get::<String, _>("username") {
-                    return Some(Json(CurrentUserResponse { username }));
-                }
-            }
-        }
+    /// How long a session may sit idle before it expires. Each request pushes the expiry forward.
+    const SESSION_IDLE_TIMEOUT: Duration = Duration::minutes(30);
+    /// How long a session may live in total, however active it is.
+    const SESSION_MAX_LIFETIME: Duration = Duration::hours(12);
 
-        // If no valid token or username found, return None (to trigger the redirect)
-        None
+    /// Formats a time the way every timestamp column is stored, so they compare correctly as text.
+    fn timestamp(time: DateTime<Utc>) -> String {
+        time.to_rfc3339_opts(SecondsFormat::Secs, true)
     }
 
-    fn generate_token_and_expiration() -> (String, chrono::DateTime<Utc>) {
-        let token = Uuid::new_v4().to_string(); // Generate a unique token
-        let expiration = Utc::now() + Duration::minutes(5); // Set expiration time to 5 minutes from now
-        (token, expiration) // Return both the token and its expiration time
+    /// The `User-Agent` header of a request, recorded against new sessions.
+    pub(super) struct UserAgent(Option<String>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UserAgent {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            Outcome::Success(UserAgent(
+                req.headers().get_one("User-Agent").map(str::to_string),
+            ))
+        }
     }
 
     #[derive(Serialize)]
@@ -286,1176 +525,7397 @@ mod api {
         message: String,
     }
 
-    #[allow(private_interfaces)]
-    #[post("/login", data = "<login_form>")]
-    pub async fn login(
-        login_form: Form<LoginCredentials>,
-        mut db: Connection<RoboDatabase>,
-        jar: &CookieJar<'_>,
-    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
-        // Fetch the admin from the database using the provided username
-        let row = rocket_db_pools::sqlx::query("SELECT * FROM admins WHERE username = ?")
-            .bind(&login_form.username)
-            .fetch_one(&mut **db)
-            .await
-            .map_err(|e| {
-                Custom(
-                    Status::InternalServerError,
-                    Json(ResponseData {
-                        success: false,
-                        message: format!("Database error: {}", e),
-                    }),
-                )
-            })?;
+    /// An authenticated admin, resolved from the `token` cookie.
+    ///
+    /// Every admin route takes this as a request guard, so a handler can't run
+    /// without a valid, unexpired session. Missing or stale tokens fail with
+    /// `401 Unauthorized`, which the `/api` catcher turns into a JSON body.
+    pub(super) struct AdminSession {
+        session_id: i64,
+        username: String,
+        permissions: Vec<String>,
+        ip: Option<IpAddr>,
+    }
 
-        let hashed_password = row.try_get::<String, _>("password").map_err(|e| {
-            Custom(
-                Status::InternalServerError,
-                Json(ResponseData {
-                    success: false,
-                    message: format!("Database error: {}", e),
-                }),
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for AdminSession {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let token = match req.cookies().get_private("token") {
+                Some(cookie) => cookie.value().to_string(),
+                None => return Outcome::Error((Status::Unauthorized, ())),
+            };
+            let db = match RoboDatabase::fetch(req.rocket()) {
+                Some(db) => db,
+                None => return Outcome::Error((Status::InternalServerError, ())),
+            };
+
+            // Only a hash of the token is stored, so a leaked database can't be used to log in
+            let token_hash = sha256_hex(&token);
+            let now = Utc::now();
+
+            // Sessions of disabled or expired accounts stop working straight away,
+            // without waiting for the expiry sweep to revoke them
+            let row = match rocket_db_pools::sqlx::query(
+                "SELECT s.session_id, s.username, s.created_at FROM sessions s
+                JOIN admins a ON a.username = s.username
+                WHERE s.token_hash = ? AND s.expires_at > ?
+                AND a.status != 'disabled' AND a.expiration >= ?",
             )
-        })?;
-        let salt: String = row.try_get("salt").map_err(|e| {
-            Custom(
-                Status::InternalServerError,
-                Json(ResponseData {
-                    success: false,
-                    message: format!("Database error: {}", e),
-                }),
-            )
-        })?;
-        let expiration_str: String = row.try_get("expiration").map_err(|e| {
-            Custom(
-                Status::InternalServerError,
-                Json(ResponseData {
-                    success: false,
-                    message: format!("Database error: {}", e),
-                }),
+            .bind(&token_hash)
+            .bind(timestamp(now))
+            .bind(now.date_naive().format("%Y-%m-%d").to_string())
+            .fetch_optional(&**db)
+            .await
+            {
+                Ok(Some(row)) => row,
+                Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+                Err(e) => {
+                    eprintln!("Failed to look up session: {e}");
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            };
+
+            let session_id: i64 = row.get("session_id");
+            let username: String = row.get("username");
+            let created_at = row
+                .try_get::<String, _>("created_at")
+                .ok()
+                .and_then(|c| DateTime::parse_from_rfc3339(&c).ok())
+                .map(|c| c.with_timezone(&Utc))
+                .unwrap_or(now);
+
+            // Slide the expiry forward, but never past the session's maximum lifetime
+            let expires_at = (now + SESSION_IDLE_TIMEOUT).min(created_at + SESSION_MAX_LIFETIME);
+            if let Err(e) = rocket_db_pools::sqlx::query(
+                "UPDATE sessions SET last_seen = ?, expires_at = ? WHERE session_id = ?",
             )
-        })?;
-
-        // Parse the expiration date from the string in "YYYY-MM-DD" format
-        let expiration_date =
-            NaiveDate::parse_from_str(&expiration_str, "%Y-%m-%d").map_err(|e| {
-                Custom(
-                    Status::BadRequest,
-                    Json(ResponseData {
-                        success: false,
-                        message: format!("Date parse error: {}", e),
-                    }),
-                )
-            })?;
-
-        // Get the current UTC date
-        let now = Utc::now().naive_utc().date();
+            .bind(timestamp(now))
+            .bind(timestamp(expires_at))
+            .bind(session_id)
+            .execute(&**db)
+            .await
+            {
+                eprintln!("Failed to extend session: {e}");
+            }
 
-        // Check if the admin's expiration date has passed
-        if now > expiration_date {
-            // Remove the expired admin account from the database
-            rocket_db_pools::sqlx::query("DELETE FROM admins WHERE username = ?")
-                .bind(&login_form.username)
-                .execute(&mut **db)
+            match rocket_db_pools::sqlx::query("SELECT permission FROM permissions WHERE username = ?")
+                .bind(&username)
+                .fetch_all(&**db)
                 .await
-                .map_err(|e| {
-                    Custom(
-                        Status::InternalServerError,
-                        Json(ResponseData {
-                            success: false,
-                            message: format!("Failed to remove expired admin: {}", e),
-                        }),
-                    )
-                })?;
-
-            return Err(Custom(
-                Status::Unauthorized,
-                Json(ResponseData {
-                    success: false,
-                    message: "Admin account has expired and has been removed.".into(),
+            {
+                Ok(rows) => Outcome::Success(AdminSession {
+                    session_id,
+                    username,
+                    permissions: rows.iter().map(|row| row.get("permission")).collect(),
+                    ip: req.client_ip(),
                 }),
-            ));
+                Err(e) => {
+                    eprintln!("Failed to load permissions: {e}");
+                    Outcome::Error((Status::InternalServerError, ()))
+                }
+            }
         }
+    }
 
-        // Combine the input password with the salt and hash it
-        let salted_input_password = format!("{}{}", login_form.password, salt);
-        let hashed_input_password = hash_password(&salted_input_password);
+    #[allow(private_interfaces)]
+    #[catch(401)]
+    pub(super) fn unauthorized() -> Json<ResponseData> {
+        Json(ResponseData {
+            success: false,
+            message: "Not logged in or session has expired.".into(),
+        })
+    }
 
-        // Check the hashed input password against the stored hashed password
-        if hashed_input_password == hashed_password {
-            // Generate a new token and its expiration
-            let (token, expiration) = generate_token_and_expiration();
-            let expiration_string = expiration.to_rfc3339();
+    #[allow(private_interfaces)]
+    #[catch(403)]
+    pub(super) fn forbidden() -> Json<ResponseData> {
+        Json(ResponseData {
+            success: false,
+            message: "You do not have permission to do that.".into(),
+        })
+    }
 
-            // Update the user's token and its expiration in the database
-            rocket_db_pools::sqlx::query(
-                "UPDATE admins SET token = ?, token_expiration = ? WHERE username = ?",
-            )
-            .bind(&token)
-            .bind(expiration_string)
-            .bind(&login_form.username)
-            .execute(&mut **db)
-            .await
-            .map_err(|e| {
-                Custom(
-                    Status::InternalServerError,
-                    Json(ResponseData {
-                        success: false,
-                        message: format!("Failed to update token: {}", e),
-                    }),
-                )
-            })?;
+    /// Builds the JSON error body used by the admin endpoints.
+    fn failure(status: Status, message: impl Into<String>) -> Custom<Json<ResponseData>> {
+        Custom(
+            status,
+            Json(ResponseData {
+                success: false,
+                message: message.into(),
+            }),
+        )
+    }
 
-            // Store the token in a cookie
-            jar.add(Cookie::new("token", token));
+    /// The error for a failed query.
+    fn db_error(e: rocket_db_pools::sqlx::Error) -> Custom<Json<ResponseData>> {
+        failure(Status::InternalServerError, format!("Database error: {e}"))
+    }
 
-            Ok(Json(ResponseData {
-                success: true,
-                message: "Login successful.".into(),
-            }))
-        } else {
-            Err(Custom(
-                Status::Unauthorized,
-                Json(ResponseData {
-                    success: false,
-                    message: "Invalid username or password.".into(),
-                }),
-            ))
+    const CSRF_COOKIE: &str = "csrf_token";
+    const CSRF_HEADER: &str = "X-CSRF-Token";
+
+    /// Double-submit CSRF protection for the API.
+    ///
+    /// Every visitor gets a random `csrf_token` cookie that page scripts can
+    /// read. Mutating `/api` requests must echo it back in the `X-CSRF-Token`
+    /// header; another site can make the browser send the cookie, but it can't
+    /// read it to set the header. Mismatches are rerouted to [`csrf_rejected`]
+    /// before any handler runs.
+    pub(super) struct Csrf;
+
+    #[rocket::async_trait]
+    impl Fairing for Csrf {
+        fn info(&self) -> Info {
+            Info {
+                name: "CSRF protection",
+                kind: Kind::Request,
+            }
+        }
+
+        async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+            let cookie_token = req.cookies().get(CSRF_COOKIE).map(|c| c.value().to_string());
+            if cookie_token.is_none() {
+                let token: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect();
+                req.cookies().add(
+                    Cookie::build((CSRF_COOKIE, token))
+                        .path("/")
+                        .same_site(SameSite::Strict)
+                        .build(),
+                );
+            }
+
+            let mutating = matches!(
+                req.method(),
+                Method::Post | Method::Put | Method::Patch | Method::Delete
+            );
+            if !mutating || !req.uri().path().starts_with("/api/") {
+                return;
+            }
+
+            let header_token = req.headers().get_one(CSRF_HEADER);
+            let valid = match (cookie_token.as_deref(), header_token) {
+                (Some(cookie), Some(header)) => !cookie.is_empty() && cookie == header,
+                _ => false,
+            };
+            if !valid {
+                req.set_method(Method::Get);
+                req.set_uri(Origin::parse("/api/csrf_rejected").unwrap());
+            }
         }
     }
 
-    fn validate_user(_token: &str) -> Option<String> {
-        Some("test".into())
+    #[allow(private_interfaces)]
+    #[get("/csrf_rejected")]
+    pub(super) fn csrf_rejected() -> Custom<Json<ResponseData>> {
+        failure(Status::Forbidden, "Missing or invalid CSRF token.")
     }
 
-    #[get("/admin_menu")]
-    pub async fn admin_menu(
-        jar: &CookieJar<'_>,
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Value>, String> {
-        let token = jar.get("token").map(|c| c.value().to_string());
+    /// Every permission that can be stored in the `permissions` table.
+    const PERMISSIONS: [&str; 5] = [
+        "inventory.write",
+        "orders.read",
+        "admins.manage",
+        "site.edit",
+        "audit.read",
+    ];
 
-        if let Some(token_value) = token {
-            // Validate the token
-            let user = rocket_db_pools::sqlx::query("SELECT * FROM admins WHERE token = ?")
-                .bind(&token_value)
-                .fetch_one(&mut **db)
-                .await;
+    /// A named permission that a route can require through [`Authorized`].
+    pub(super) trait Permission: Send + Sync + 'static {
+        const NAME: &'static str;
+    }
 
-            if let Ok(row) = user {
-                // Fetch `token_expiration` as a `String` from the row
-                let token_expiration_str: String = match row.try_get("token_expiration") {
-                    Ok(expiration) => expiration,
-                    Err(_) => return Err("Failed to retrieve token expiration.".to_string()),
-                };
+    /// Add, edit and remove products, variants and images.
+    pub(super) struct InventoryWrite;
+    impl Permission for InventoryWrite {
+        const NAME: &'static str = "inventory.write";
+    }
 
-                // Parse the token expiration string into NaiveDateTime
-                let token_expires =
-                    match NaiveDateTime::parse_from_str(&token_expiration_str, "%Y-%m-%d %H:%M:%S")
-                    {
-                        Ok(parsed_date) => parsed_date,
-                        Err(_) => return Err("Failed to parse token expiration.".to_string()),
-                    };
+    /// View customers and their orders.
+    pub(super) struct OrdersRead;
+    impl Permission for OrdersRead {
+        const NAME: &'static str = "orders.read";
+    }
 
-                let now = Utc::now().naive_utc(); // Get the current time in naive UTC
+    /// Create and delete admins, and grant or revoke their permissions.
+    pub(super) struct AdminsManage;
+    impl Permission for AdminsManage {
+        const NAME: &'static str = "admins.manage";
+    }
 
-                // Check if the token has expired
-                if token_expires > now {
-                    return Ok(Json(serde_json::json!({
-                        "success": true,
-                        "message": "Access granted."
-                    })));
-                } else {
-                    // If the token is expired, clear it from the database
-                    rocket_db_pools::sqlx::query(
-                        "UPDATE admins SET token = NULL, token_expires = NULL WHERE token = ?",
-                    )
-                    .bind(token_value) // Use the cloned value here
-                    .execute(&mut **db)
-                    .await
-                    .ok();
+    /// Edit the information shown on the about us page.
+    pub(super) struct SiteEdit;
+    impl Permission for SiteEdit {
+        const NAME: &'static str = "site.edit";
+    }
 
-                    return Err("Token has expired.".to_string());
-                }
-            }
-        }
-        Err("No valid token found.".to_string())
+    /// Read the audit log.
+    pub(super) struct AuditRead;
+    impl Permission for AuditRead {
+        const NAME: &'static str = "audit.read";
     }
 
-    #[post("/logout")]
-    pub async fn logout(jar: &CookieJar<'_>, mut db: Connection<RoboDatabase>) {
-        // Get the token from the cookie
-        if let Some(token_cookie) = jar.get("token") {
-            let token_value = token_cookie.value().to_string();
+    /// An [`AdminSession`] whose admin holds permission `P`.
+    ///
+    /// Fails with `403 Forbidden` when the admin is logged in but lacks `P`.
+    pub(super) struct Authorized<P: Permission> {
+        admin: AdminSession,
+        _permission: PhantomData<P>,
+    }
 
-            // Remove the "token" cookie from the jar to log out the client
-            jar.remove(Cookie::from("token"));
+    impl<P: Permission> std::ops::Deref for Authorized<P> {
+        type Target = AdminSession;
 
-            // Set the token and token_expires fields to NULL for the user in the database
-            let update_result = rocket_db_pools::sqlx::query(
-                "UPDATE admins SET token = NULL, token_expiration = NULL WHERE token = ?",
-            )
-            .bind(&token_value)
-            .execute(&mut **db)
-            .await;
+        fn deref(&self) -> &AdminSession {
+            &self.admin
+        }
+    }
 
-            // Optional: Check if the update succeeded
-            if let Err(e) = update_result {
-                eprintln!("Failed to clear token for user: {}", e);
+    #[rocket::async_trait]
+    impl<'r, P: Permission> FromRequest<'r> for Authorized<P> {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let admin = match req.guard::<AdminSession>().await {
+                Outcome::Success(admin) => admin,
+                Outcome::Error(e) => return Outcome::Error(e),
+                Outcome::Forward(s) => return Outcome::Forward(s),
+            };
+
+            if admin.permissions.iter().any(|p| p == P::NAME) {
+                Outcome::Success(Authorized {
+                    admin,
+                    _permission: PhantomData,
+                })
+            } else {
+                Outcome::Error((Status::Forbidden, ()))
             }
         }
     }
 
-    #[allow(private_interfaces)]
-    #[post("/create_admin", data = "<admin_form>")]
-    pub async fn create_admin(
-        admin_form: Form<CreateAdmin>,
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<ResponseData>, Status> {
-        // Generate a random salt
-        let salt: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16) // You can adjust the length of the salt as needed
-            .map(char::from)
-            .collect();
+    /// Appends an entry to the audit log. Run it on the same connection or
+    /// transaction as the change it records, so one is never saved without the other.
+    async fn audit(
+        conn: &mut SqliteConnection,
+        admin: &AdminSession,
+        action: &str,
+        target: &str,
+        before: Option<Value>,
+        after: Option<Value>,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO audit_log (actor, action, target, before, after, created_at, ip)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&admin.username)
+        .bind(action)
+        .bind(target)
+        .bind(before.map(|v| v.to_string()))
+        .bind(after.map(|v| v.to_string()))
+        .bind(timestamp(Utc::now()))
+        .bind(admin.ip.map(|ip| ip.to_string()))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
 
-        // Concatenate the salt with the password, then hash the combined string
-        let salted_password = format!("{}{}", admin_form.password, salt);
-        let hashed_password = hash_password(&salted_password);
+    /// Copies a database row into a JSON object for the audit log. Blobs such as
+    /// images are recorded by size only.
+    fn row_json(row: &SqliteRow) -> Value {
+        let mut object = serde_json::Map::new();
+        for column in row.columns() {
+            let i = column.ordinal();
+            let value = match row.try_get_raw(i) {
+                Ok(raw) if raw.is_null() => Value::Null,
+                Ok(raw) => match raw.type_info().name() {
+                    "INTEGER" => row.try_get::<i64, _>(i).map(Value::from).unwrap_or_default(),
+                    "REAL" => row.try_get::<f64, _>(i).map(Value::from).unwrap_or_default(),
+                    "BLOB" => row
+                        .try_get::<Vec<u8>, _>(i)
+                        .map(|b| Value::from(format!("<{} bytes>", b.len())))
+                        .unwrap_or_default(),
+                    _ => row.try_get::<String, _>(i).map(Value::from).unwrap_or_default(),
+                },
+                Err(_) => Value::Null,
+            };
+            object.insert(column.name().to_string(), value);
+        }
+        Value::Object(object)
+    }
 
-        // SQL query to insert the new admin into the database
-        let result = rocket_db_pools::sqlx::query(
-            "INSERT INTO admins (username, salt, password, expiration) VALUES (?, ?, ?, ?)",
-        )
-        .bind(&admin_form.username)
-        .bind(salt)
-        .bind(hashed_password)
-        .bind(&admin_form.expiration)
-        .execute(&mut **db)
-        .await;
+    /// Entries per page of `/audit` unless `per_page` says otherwise.
+    const AUDIT_PAGE_SIZE: u32 = 50;
+    const AUDIT_MAX_PAGE_SIZE: u32 = 200;
 
-        // Handle the result of the database operation
-        match result {
-            Ok(_) => {
-                // Return a JSON response with a success flag
-                Ok(Json(ResponseData {
-                    success: true,
-                    message: "Admin user created successfully.".to_string(),
-                }))
-            }
-            Err(_) => {
-                // Return a JSON response with an error message
-                Err(Status::InternalServerError)
-            }
-        }
+    /// Filters for `/audit`. `action` also matches a whole group, so `product`
+    /// finds `product.create`, `product.update` and so on. `since` and `until`
+    /// are inclusive `YYYY-MM-DD` dates in UTC.
+    #[derive(FromForm)]
+    struct AuditFilter<'r> {
+        page: Option<u32>,
+        per_page: Option<u32>,
+        actor: Option<&'r str>,
+        action: Option<&'r str>,
+        target: Option<&'r str>,
+        since: Option<&'r str>,
+        until: Option<&'r str>,
     }
 
-    fn hash_password(password: &str) -> String {
-        // Hashing logic using SHA-256
-        let mut hasher = Sha256::new();
-        hasher.update(password);
-        let result = hasher.finalize();
-        hex::encode(result) // Return the hex representation of the hash
+    #[derive(Serialize)]
+    struct AuditEntry {
+        audit_id: i64,
+        actor: String,
+        action: String,
+        target: String,
+        before: Option<Value>,
+        after: Option<Value>,
+        created_at: String,
+        ip: Option<String>,
     }
 
-    #[derive(Serialize, Deserialize, FromForm)]
-    struct CartItem {
-        product: i32,
-        name: String,           // Common name (product or variant name)
-        quantity: u32,          // Quantity of the item
-        price: f32,             // Price of the item
-        variant: String, // Variant-specific data (if applicable)
+    #[derive(Serialize)]
+    struct AuditPage {
+        entries: Vec<AuditEntry>,
+        page: u32,
+        per_page: u32,
+        total: i64,
     }
 
+    /// Lists audit log entries, newest first.
     #[allow(private_interfaces)]
-    #[post("/addcart", data = "<item>")]
-    pub async fn add_cart(pot: &CookieJar<'_>, item: Json<CartItem>) -> Json<usize> {
-        // Retrieve the existing cart from the cookie, or initialize an empty cart
-        let mut cart_items: Vec<CartItem> = if let Some(cookie) = pot.get("cart_items") {
-            serde_json::from_str(cookie.value()).unwrap_or_default()
-        } else {
-            vec![]
+    #[get("/audit?<filter..>")]
+    pub(super) async fn get_audit(
+        filter: AuditFilter<'_>,
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<AuditRead>,
+    ) -> Result<Json<AuditPage>, Custom<Json<ResponseData>>> {
+        let parse_date = |date: Option<&str>| -> Result<Option<NaiveDate>, Custom<Json<ResponseData>>> {
+            date.map(|d| {
+                NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| {
+                    failure(Status::BadRequest, format!("`{d}` is not a valid date (expected YYYY-MM-DD)."))
+                })
+            })
+            .transpose()
         };
 
-        // Extract the item to be added
-        let mut new_item = item.into_inner();
-
-        // Check if the item already exists in the cart (considering both name and variant)
-        if let Some(existing_item) = cart_items.iter_mut().find(|cart_item| {
-            // Compare name and variant (check if both are equal, including the variant details)
-            cart_item.name == new_item.name &&
-            // Handle the variant comparison explicitly
-            cart_item.variant == new_item.variant
-        }) {
-            // If the item exists, update its quantity
-            existing_item.quantity += new_item.quantity;
-        } else {
-            // If the item does not exist, add it to the cart
-            new_item.quantity = 1; // Ensure the quantity starts at 1
-            cart_items.push(new_item);
-        }
-
-        // Convert the updated cart to a JSON string
-        let cart_json = serde_json::to_string(&cart_items).unwrap();
-
-        // Store the updated cart in the cookie
-        pot.add(Cookie::new("cart_items", cart_json));
+        let page = filter.page.unwrap_or(1).max(1);
+        let per_page = filter
+            .per_page
+            .unwrap_or(AUDIT_PAGE_SIZE)
+            .clamp(1, AUDIT_MAX_PAGE_SIZE);
+        // Timestamps are stored as RFC 3339 text, so whole days compare as text bounds
+        let since = parse_date(filter.since)?.map(|d| format!("{d}T00:00:00Z"));
+        let until = parse_date(filter.until)?
+            .and_then(|d| d.succ_opt())
+            .map(|d| format!("{d}T00:00:00Z"));
+
+        let conditions = "(?1 IS NULL OR actor = ?1)
+            AND (?2 IS NULL OR action = ?2 OR action LIKE ?2 || '.%')
+            AND (?3 IS NULL OR target = ?3)
+            AND (?4 IS NULL OR created_at >= ?4)
+            AND (?5 IS NULL OR created_at < ?5)";
+
+        let total: i64 = rocket_db_pools::sqlx::query(&format!(
+            "SELECT COUNT(*) AS n FROM audit_log WHERE {conditions}"
+        ))
+        .bind(filter.actor)
+        .bind(filter.action)
+        .bind(filter.target)
+        .bind(&since)
+        .bind(&until)
+        .fetch_one(&mut **db)
+        .await
+        .map_err(db_error)?
+        .get("n");
+
+        let rows = rocket_db_pools::sqlx::query(&format!(
+            "SELECT * FROM audit_log WHERE {conditions}
+            ORDER BY audit_id DESC LIMIT ?6 OFFSET ?7"
+        ))
+        .bind(filter.actor)
+        .bind(filter.action)
+        .bind(filter.target)
+        .bind(&since)
+        .bind(&until)
+        .bind(per_page)
+        .bind((page - 1) * per_page)
+        .fetch_all(&mut **db)
+        .await
+        .map_err(db_error)?;
 
-        // Calculate the total number of items (sum of quantities)
-        let total_items = cart_items.iter().map(|item| item.quantity).sum::<u32>();
+        let json = |column: Option<String>| column.and_then(|c| serde_json::from_str(&c).ok());
+        Ok(Json(AuditPage {
+            entries: rows
+                .iter()
+                .map(|row| AuditEntry {
+                    audit_id: row.get("audit_id"),
+                    actor: row.get("actor"),
+                    action: row.get("action"),
+                    target: row.get("target"),
+                    before: json(row.get("before")),
+                    after: json(row.get("after")),
+                    created_at: row.get("created_at"),
+                    ip: row.get("ip"),
+                })
+                .collect(),
+            page,
+            per_page,
+            total,
+        }))
+    }
 
-        // Return the total number of items in the cart
-        Json(total_items as usize)
+    /// Failed logins for one username before it is locked out.
+    const MAX_USERNAME_FAILURES: i64 = 5;
+    /// Failed logins from one IP address before it is locked out. Higher than
+    /// the per-username limit because club members may share a campus network.
+    const MAX_IP_FAILURES: i64 = 20;
+    /// How long a lockout lasts once the failure limit is reached.
+    const LOCKOUT_DURATION: Duration = Duration::minutes(15);
+    /// Failures older than this are forgotten.
+    const FAILURE_WINDOW: Duration = Duration::hours(1);
+
+    /// The counters that failed logins are tracked under.
+    fn attempt_keys(username: &str, client_ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+        let mut keys = vec![("username", username.to_lowercase())];
+        if let Some(ip) = client_ip {
+            keys.push(("ip", ip.to_string()));
+        }
+        keys
     }
 
-    #[get("/getcart")]
-    pub async fn get_cart(pot: &CookieJar<'_>) -> String {
-        // Retrieve the cart items from cookies, or return an empty array if not found
-        let cart_items: Vec<CartItem> = if let Some(cookie) = pot.get("cart_items") {
-            serde_json::from_str(cookie.value()).unwrap_or_default()
+    fn max_failures(scope: &str) -> i64 {
+        if scope == "ip" {
+            MAX_IP_FAILURES
         } else {
-            vec![]
-        };
-
-        // Convert cart items to JSON response
-        serde_json::to_string(&cart_items).unwrap()
+            MAX_USERNAME_FAILURES
+        }
     }
 
-    #[get("/get_cart_count")]
-    pub async fn get_cart_count(pot: &CookieJar<'_>) -> Json<i32> {
-        // Retrieve the cart items from cookies, or return 0 if no cart exists
-        let cart_items: Vec<CartItem> = if let Some(cookie) = pot.get("cart_items") {
-            serde_json::from_str(cookie.value()).unwrap_or_default()
+    /// How long to block further attempts after `failures` consecutive failures.
+    ///
+    /// Usernames back off exponentially (1s, 2s, 4s, ...) before locking out.
+    /// IP addresses only lock out, so one person's typos don't slow down
+    /// everyone else behind the same address.
+    fn block_duration(scope: &str, failures: i64) -> Option<Duration> {
+        if failures >= max_failures(scope) {
+            Some(LOCKOUT_DURATION)
+        } else if scope == "username" {
+            Some(Duration::seconds(1 << (failures - 1).clamp(0, 10)))
         } else {
-            vec![]
-        };
-
-        // Calculate the total quantity of items in the cart
-        let total_quantity: i32 = cart_items.iter().map(|item| item.quantity as i32).sum();
-
-        // Return the total quantity as an i32
-        Json(total_quantity)
+            None
+        }
     }
 
-    #[post("/removecart?<name>&<variant>")]
-    pub async fn remove_cart(pot: &CookieJar<'_>, name: String, variant: String) -> Json<usize> {
-        // Retrieve the existing cart from the cookie
-        let cart_items: Vec<CartItem> = if let Some(cookie) = pot.get("cart_items") {
-            if let Ok(mut items) = serde_json::from_str::<Vec<CartItem>>(cookie.value()) {
-                // Filter out the item to be removed by matching both name and variant
-                items.retain(|item| item.name != name || item.variant != variant);
-
-                // Update the cookie with the remaining items
-                let updated_cart = serde_json::to_string(&items).unwrap();
-                pot.add(Cookie::new("cart_items", updated_cart));
-                items
-            } else {
-                // Retrieve the existing cart from the cookie, or initialize an empty cart
-                let cart_items: Vec<CartItem> = if let Some(cookie) = pot.get("cart_items") {
-                    serde_json::from_str(cookie.value()).unwrap_or_default()
-                } else {
-                    vec![]
-                };
-                cart_items
+    /// Returns when logins may be attempted again, if any of the counters are blocked.
+    async fn login_blocked_until(
+        db: &mut Connection<RoboDatabase>,
+        keys: &[(&'static str, String)],
+    ) -> Result<Option<String>, rocket_db_pools::sqlx::Error> {
+        let now = timestamp(Utc::now());
+        let mut blocked_until: Option<String> = None;
+        for (scope, key) in keys {
+            let row = rocket_db_pools::sqlx::query(
+                "SELECT locked_until FROM login_attempts
+                WHERE scope = ? AND key = ? AND locked_until > ?",
+            )
+            .bind(scope)
+            .bind(key)
+            .bind(&now)
+            .fetch_optional(&mut ***db)
+            .await?;
+            if let Some(row) = row {
+                let until: String = row.get("locked_until");
+                if blocked_until.as_ref().is_none_or(|b| until > *b) {
+                    blocked_until = Some(until);
+                }
             }
-        } else {
-            vec![]
-        };
-
-        Json(cart_items.len())
+        }
+        Ok(blocked_until)
     }
 
-    #[post("/clearcart")]
-    pub async fn clear_cart(pot: &CookieJar<'_>) -> Json<Result<usize, String>> {
-        // Remove the "cart_items" cookie by setting it to an empty value
-        pot.remove(Cookie::new("cart_items", ""));
-        
-        // Return a success response
-        Json(Ok(1))  // Return 1 for success
-    }
+    async fn record_login_failure(
+        db: &mut Connection<RoboDatabase>,
+        keys: &[(&'static str, String)],
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        let now = Utc::now();
+        for (scope, key) in keys {
+            let failures: i64 = rocket_db_pools::sqlx::query(
+                "INSERT INTO login_attempts (scope, key, failures, last_failure) VALUES (?, ?, 1, ?)
+                ON CONFLICT (scope, key) DO UPDATE SET
+                    failures = CASE WHEN last_failure < ? THEN 1 ELSE failures + 1 END,
+                    last_failure = excluded.last_failure
+                RETURNING failures",
+            )
+            .bind(scope)
+            .bind(key)
+            .bind(timestamp(now))
+            .bind(timestamp(now - FAILURE_WINDOW))
+            .fetch_one(&mut ***db)
+            .await?
+            .get("failures");
 
-    #[allow(private_interfaces)]
-    #[get("/get_items")]
-    pub(super) async fn get_items(
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Vec<Product>>, String> {
-        let rows: Vec<Result<Product, String>> =
-            rocket_db_pools::sqlx::query("select * from products")
-                .fetch(&mut **db)
-                .map(|row| {
-                    let row = row.map_err(|e| format!("Couldnt get row {e}"))?;
-                    let item_r: Result<Product, String> = row.try_into();
-                    item_r
-                })
-                .collect()
-                .await;
-        let mut rows_ret = Vec::with_capacity(rows.len());
-        for row in rows {
-            match row {
-                Ok(row) => rows_ret.push(row),
-                Err(e) => return Err(e),
-            }
+            rocket_db_pools::sqlx::query(
+                "UPDATE login_attempts SET locked_until = ? WHERE scope = ? AND key = ?",
+            )
+            .bind(block_duration(scope, failures).map(|d| timestamp(now + d)))
+            .bind(scope)
+            .bind(key)
+            .execute(&mut ***db)
+            .await?;
         }
-        Ok(Json(rows_ret))
+        Ok(())
     }
 
-    #[get("/additem/<name>")]
-    pub(super) async fn add_item(
-        name: &str,
-        mut db: Connection<RoboDatabase>,
-        jar: &CookieJar<'_>,
-    ) -> Result<&'static str, String> {
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
-        rocket_db_pools::sqlx::query(
-            "insert into products (name, price, quantity) values ($1, $2 ,$3)",
-        )
-        .bind(name)
-        .bind(10.0)
-        .bind(100)
-        .execute(&mut **db)
-        .await
-        .map_err(|e| format!("Database error {e}"))?;
-        Ok("Added")
+    /// A hash to check passwords against when the username doesn't exist, so
+    /// unknown usernames take as long to reject as wrong passwords.
+    fn dummy_password_hash() -> &'static str {
+        static DUMMY: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        DUMMY.get_or_init(|| {
+            hash_password("not a real password")
+                .map(|(_, hash)| hash)
+                .unwrap_or_default()
+        })
     }
 
     #[allow(private_interfaces)]
-    #[post("/add_product", data = "<new_product>")]
-    pub(super) async fn add_product(
-        new_product: Json<Product>,
+    #[post("/login", data = "<login_form>")]
+    pub async fn login(
+        login_form: Form<LoginCredentials>,
         mut db: Connection<RoboDatabase>,
         jar: &CookieJar<'_>,
-    ) -> Result<Json<i32>, String> {
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
+        client_ip: Option<IpAddr>,
+        user_agent: UserAgent,
+    ) -> Result<Json<LoginResponse>, Custom<Json<ResponseData>>> {
+        // Every way a login can fail looks the same, so it can't be used to find valid usernames
+        let invalid_credentials = || failure(Status::Unauthorized, "Invalid username or password.");
+
+        let keys = attempt_keys(&login_form.username, client_ip);
+        if let Some(until) = login_blocked_until(&mut db, &keys).await.map_err(db_error)? {
+            return Err(failure(
+                Status::TooManyRequests,
+                format!("Too many failed login attempts. Try again after {until}."),
+            ));
+        }
+
+        // Fetch the admin from the database using the provided username
+        let row = rocket_db_pools::sqlx::query("SELECT * FROM admins WHERE username = ?")
+            .bind(&login_form.username)
+            .fetch_optional(&mut **db)
+            .await
+            .map_err(db_error)?;
+
+        let row = match row {
+            Some(row) => row,
+            None => {
+                verify_password(&login_form.password, dummy_password_hash(), "");
+                record_login_failure(&mut db, &keys).await.map_err(db_error)?;
+                return Err(invalid_credentials());
+            }
         };
 
-        let mut item_to_add = new_product.into_inner();
+        let hashed_password = row.try_get::<String, _>("password").map_err(db_error)?;
+        let salt: String = row.try_get("salt").map_err(db_error)?;
+        let expiration_str: String = row.try_get("expiration").map_err(db_error)?;
 
-        // Round the price to exactly 2 decimal places
-        let formatted_price = (item_to_add.price * 100.0).round() / 100.0;
-        item_to_add.price = formatted_price;
+        // Check the input password against the stored hash
+        let check = verify_password(&login_form.password, &hashed_password, &salt);
 
-        // Insert the new product into the database without specifying the ID (let the DB auto-generate it)
-        let result = rocket_db_pools::sqlx::query(
-            "insert into products (name, desc, price, quantity) values ($1, $2, $3, $4) returning product_id",
-        )
-        .bind(&item_to_add.name)
-        .bind(&item_to_add.desc)
-        .bind(&item_to_add.price)
-        .bind(&item_to_add.quantity)
-        .fetch_one(&mut **db)
-        .await;
+        if check == PasswordCheck::Invalid {
+            record_login_failure(&mut db, &keys).await.map_err(db_error)?;
+            return Err(invalid_credentials());
+        }
 
-        match result {
-            Ok(row) => {
-                // Extract the generated ID from the result
-                let product_id: i32 = row.try_get("product_id").map_err(|e| format!("Error extracting ID: {}", e))?;
+        // Only reveal that the account expired once the password has been proven.
+        // Parse the expiration date from the string in "YYYY-MM-DD" format
+        let expiration_date =
+            NaiveDate::parse_from_str(&expiration_str, "%Y-%m-%d").map_err(|e| {
+                eprintln!("Admin {} has an unreadable expiration date {expiration_str}: {e}", login_form.username);
+                failure(Status::InternalServerError, "Login failed. Ask an admin to check your account.")
+            })?;
 
-                // Return the ID as JSON
-                Ok(Json(product_id))
+        // Expired accounts are disabled, not deleted, so an admin can extend them later
+        let status: String = row.try_get("status").map_err(db_error)?;
+        let today = Utc::now().date_naive();
+        if status == "disabled" || expiry_status(expiration_date, today) == "disabled" {
+            rocket_db_pools::sqlx::query("UPDATE admins SET status = 'disabled' WHERE username = ?")
+                .bind(&login_form.username)
+                .execute(&mut **db)
+                .await
+                .map_err(db_error)?;
+
+            return Err(failure(
+                Status::Unauthorized,
+                format!(
+                    "Admin account expired on {expiration_date}. Ask an admin to extend it."
+                ),
+            ));
+        }
+
+        if check == PasswordCheck::NeedsRehash {
+            // Silently upgrade legacy or outdated hashes now that we know the password
+            match hash_password(&login_form.password) {
+                Ok((new_salt, new_hash)) => {
+                    if let Err(e) = rocket_db_pools::sqlx::query(
+                        "UPDATE admins SET salt = ?, password = ? WHERE username = ?",
+                    )
+                    .bind(new_salt)
+                    .bind(new_hash)
+                    .bind(&login_form.username)
+                    .execute(&mut **db)
+                    .await
+                    {
+                        eprintln!("Failed to upgrade password hash: {e}");
+                    }
+                }
+                Err(e) => eprintln!("Failed to upgrade password hash: {e}"),
             }
-            Err(e) => Err(format!("Database error: {}", e)),
         }
+
+        // Admins with two-factor authentication must also pass the second stage
+        let totp_enabled =
+            rocket_db_pools::sqlx::query("SELECT 1 FROM admin_totp WHERE username = ? AND enabled = 1")
+                .bind(&login_form.username)
+                .fetch_optional(&mut **db)
+                .await
+                .map_err(db_error)?
+                .is_some();
+        if totp_enabled {
+            jar.add_private(pending_login_cookie(&login_form.username, Utc::now()));
+            return Ok(Json(LoginResponse {
+                success: true,
+                message: "Enter the code from your authenticator app.".into(),
+                totp_required: true,
+            }));
+        }
+
+        finish_login(&mut db, jar, &login_form.username, client_ip, user_agent).await
     }
 
-    #[allow(private_interfaces)]
-    #[post("/update_product", data = "<updated_product>")]
-    pub(super) async fn update_product(
-        updated_product: Json<Product>, // Handle the updated form data
-        mut db: Connection<RoboDatabase>,
+    #[derive(Serialize)]
+    struct LoginResponse {
+        success: bool,
+        message: String,
+        /// Set when the password was right but a TOTP code must be sent to `/login/totp`.
+        totp_required: bool,
+    }
+
+    /// Clears the username's failed logins and starts a session once every login stage has passed.
+    async fn finish_login(
+        db: &mut Connection<RoboDatabase>,
         jar: &CookieJar<'_>,
-    ) -> Result<Json<i32>, String> { // Return the product_id as a String
-        // Validate the user's session
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
+        username: &str,
+        client_ip: Option<IpAddr>,
+        user_agent: UserAgent,
+    ) -> Result<Json<LoginResponse>, Custom<Json<ResponseData>>> {
+        // A successful login clears the username's failure count
+        rocket_db_pools::sqlx::query("DELETE FROM login_attempts WHERE scope = 'username' AND key = ?")
+            .bind(username.to_lowercase())
+            .execute(&mut ***db)
+            .await
+            .map_err(db_error)?;
 
-        let product = updated_product.into_inner();
+        // Start a new session alongside any the admin already has on other devices
+        let token = start_session(db, username, client_ip, user_agent)
+            .await
+            .map_err(|e| failure(Status::InternalServerError, format!("Failed to start session: {e}")))?;
 
-        // Update product in the database
-        let update_result = rocket_db_pools::sqlx::query(
-            "UPDATE products SET 'desc' = $1, price = $2, quantity = $3 WHERE name = $4 RETURNING product_id"
-        )
-        .bind(&product.desc)
-        .bind(&product.price)
-        .bind(&product.quantity)
-        .bind(&product.name)
-        .fetch_one(&mut **db)
-        .await;
+        // Store the token in a cookie
+        jar.add_private(session_cookie(token));
 
-        match update_result {
-            Ok(row) => {
-                // Retrieve the product_id from the returned row
-                let product_id: i32 = row.get("product_id");
-                Ok(Json(product_id)) // Return the product_id as a string
-            }
-            Err(e) => Err(format!("Error updating product: {e}")),
+        Ok(Json(LoginResponse {
+            success: true,
+            message: "Login successful.".into(),
+            totp_required: false,
+        }))
+    }
+
+    /// How long an admin has to enter their TOTP code after giving the right password.
+    const PENDING_LOGIN_TIMEOUT: Duration = Duration::minutes(5);
+
+    /// Remembers who passed the password stage. It's a private cookie, so the
+    /// client can't forge or alter it.
+    fn pending_login_cookie(username: &str, now: DateTime<Utc>) -> Cookie<'static> {
+        Cookie::build((
+            "pending_login",
+            format!("{}\n{}", timestamp(now + PENDING_LOGIN_TIMEOUT), username),
+        ))
+        .path("/api")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(true)
+        .build()
+    }
+
+    /// Time step of the TOTP codes, in seconds.
+    const TOTP_STEP: u64 = 30;
+    /// Number of digits in a TOTP code.
+    const TOTP_DIGITS: u32 = 6;
+    const TOTP_ISSUER: &str = "Kent State Robotics";
+    const RECOVERY_CODE_COUNT: usize = 10;
+
+    /// The RFC 6238 TOTP code (HMAC-SHA1) for time step `step`.
+    fn totp_code(secret: &[u8], step: u64) -> u32 {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Dynamic truncation from RFC 4226
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        binary % 10u32.pow(TOTP_DIGITS)
+    }
+
+    /// Checks `code` against the steps around `unix_time`, allowing one step of
+    /// clock drift either way. Steps at or before `last_used_step` are refused
+    /// so a code can't be replayed. Returns the step that matched.
+    fn verify_totp(
+        secret: &[u8],
+        code: &str,
+        unix_time: u64,
+        last_used_step: Option<u64>,
+    ) -> Option<u64> {
+        let code = code.trim();
+        if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
         }
+        let code: u32 = code.parse().ok()?;
+        let current = unix_time / TOTP_STEP;
+
+        (current.saturating_sub(1)..=current + 1)
+            .filter(|step| last_used_step.is_none_or(|last| *step > last))
+            .find(|step| totp_code(secret, *step) == code)
     }
 
-    #[allow(private_interfaces)]
-    #[delete("/remove_product/<product_name>")]
-    pub(super) async fn remove_product(
-        product_name: &str, // Parameter type still as String
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<String>, String> {
-        // Retrieve the product_id of the product to delete
-        let product_result = rocket_db_pools::sqlx::query("SELECT product_id FROM products WHERE name = ?")
-            .bind(&product_name) // Bind the product name to the query
-            .fetch_one(&mut **db) // Fetch the row
-            .await;
+    /// The `otpauth://` URI authenticator apps read from a QR code.
+    fn totp_uri(username: &str, secret_base32: &str) -> String {
+        let issuer = RawStr::new(TOTP_ISSUER).percent_encode();
+        let account = RawStr::new(username).percent_encode();
+        format!(
+            "otpauth://totp/{issuer}:{account}?secret={secret_base32}&issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_STEP}"
+        )
+    }
 
-        // Check if the product exists and extract the product_id
-        let product_id = match product_result {
-            Ok(row) => row.get::<i32, _>("product_id"), // Extract product_id (assuming it's of type i32)
-            Err(e) => {
-                return Err(format!("Failed to find product: {}", e)); // Return error if the product is not found
-            }
-        };
+    fn generate_totp_secret() -> Vec<u8> {
+        let mut secret = vec![0u8; 20];
+        rand::thread_rng().fill(&mut secret[..]);
+        secret
+    }
 
-        // First, remove variants associated with the product
-        let variant_result = rocket_db_pools::sqlx::query("DELETE FROM product_variants WHERE product_id = ?")
-            .bind(product_id) // Bind the product_id to the query
-            .execute(&mut **db) // Execute the delete query within the transaction
-            .await;
+    /// Generates one-time recovery codes shaped like `abcd-efgh-ijkl`.
+    fn generate_recovery_codes() -> Vec<String> {
+        (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let raw: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(12)
+                    .map(|c| char::from(c).to_ascii_lowercase())
+                    .collect();
+                format!("{}-{}-{}", &raw[..4], &raw[4..8], &raw[8..])
+            })
+            .collect()
+    }
 
-        match variant_result {
-            Ok(query_result) => {
-                if query_result.rows_affected() == 0 {
-                    // No variants were removed, which might be fine, so continue
-                    println!("No variants found for product '{}'", product_name);
-                }
-            }
-            Err(e) => {
-                return Err(format!("Failed to remove variants: {}", e));
-            }
-        }
+    /// Recovery codes are random enough that a fast hash is fine; dashes and case are ignored.
+    fn hash_recovery_code(code: &str) -> String {
+        let normalized: String = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        sha256_hex(&normalized)
+    }
 
-        // Now, remove the product
-        let product_result = rocket_db_pools::sqlx::query("DELETE FROM products WHERE name = ?")
-            .bind(&product_name) // Bind the product name to the query
-            .execute(&mut **db) // Execute the delete query within the transaction
-            .await;
+    /// Checks a TOTP or recovery code for an admin with two-factor authentication
+    /// enabled, as of `now`, consuming it if it matches. Returns whether it matched.
+    async fn check_second_factor(
+        conn: &mut SqliteConnection,
+        username: &str,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, rocket_db_pools::sqlx::Error> {
+        let row = rocket_db_pools::sqlx::query(
+            "SELECT secret, last_used_step FROM admin_totp WHERE username = ? AND enabled = 1",
+        )
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(false),
+        };
 
-        match product_result {
-            Ok(query_result) => {
-                if query_result.rows_affected() > 0 {
-                    // Commit the transaction if both delete operations were successful
-                    Ok(Json("Product and associated variants removed successfully.".to_string()))
-                } else {
-                    // No product found with the given name
-                    Err("Product not found.".to_string())
-                }
-            }
-            Err(e) => {
-                // Return an error if the product deletion fails
-                Err(format!("Failed to remove product: {}", e))
-            }
+        let secret: String = row.get("secret");
+        let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+            .unwrap_or_default();
+        let last_used_step: Option<i64> = row.get("last_used_step");
+
+        if let Some(step) = verify_totp(&secret, code, now.timestamp() as u64, last_used_step.map(|s| s as u64)) {
+            rocket_db_pools::sqlx::query("UPDATE admin_totp SET last_used_step = ? WHERE username = ?")
+                .bind(step as i64)
+                .bind(username)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(true);
         }
-    }
 
-    #[allow(private_interfaces)]
-    #[get("/get_product_variants?<name>")]
-    pub(super) async fn get_product_variants(
-        name: String,
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Vec<serde_json::Value>>, String> {
-        let product_id: u32 =
-            rocket_db_pools::sqlx::query("select product_id from products where name = $1")
-                .bind(name)
-                .fetch_one(&mut **db)
-                .map_err(|e| format!("Could not find product {e}"))
-                .await?
-                .try_get("product_id")
-                .map_err(|e| format!("Could not get product_id from product {e}"))?;
-        let prod_vars: Vec<Result<ProductVariant, String>> =
-            rocket_db_pools::sqlx::query("select * from product_variants where product_id = $1")
-                .bind(product_id)
-                .fetch(&mut **db)
-                .map(|row| {
-                    let row = match row {
-                        Ok(row) => row,
-                        Err(e) => 
-                        return Err(format!("Row in product variants not found {e}")),
-                    };
-                    row.try_into()
-                })
-                .collect()
-                .await;
-            let mut formatted_prod_vars = vec![];
-            for prodvar in prod_vars {
-                match prodvar {
-                    Ok(variant) => {
-                        formatted_prod_vars.push(serde_json::json!({
-                            "quantity": variant.quantity,
-                            "tag_name": variant
-                                .tag_name
-                                .iter()
-                                .map(|tag| tag.to_string()) // Format each tag name
-                                .collect::<Vec<String>>()
-                                .join(" "), // Join all tags into a single string
-                            "product": variant.product,
-                            "varid": variant.varid,
-                            "image": variant.image,
-                        }));
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-        // Step 4: Return the transformed variants as JSON
-        Ok(Json(formatted_prod_vars))
+        // Fall back to a recovery code, each of which works once
+        let used = rocket_db_pools::sqlx::query(
+            "UPDATE recovery_codes SET used_at = ?
+            WHERE username = ? AND code_hash = ? AND used_at IS NULL",
+        )
+        .bind(timestamp(now))
+        .bind(username)
+        .bind(hash_recovery_code(code))
+        .execute(conn)
+        .await?;
+        Ok(used.rows_affected() > 0)
     }
 
     #[allow(private_interfaces)]
-    #[get("/get_variant_id?<product_id>&<tag_name>")]
-    pub(super) async fn get_variant_id(
-        product_id: u32,
-        tag_name: String,
+    #[post("/login/totp", data = "<totp_form>")]
+    pub async fn login_totp(
+        totp_form: Form<TotpCode>,
         mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<u32>, String> {
-        // Split the tag_name into two strings based on whitespace
-        let tags: Vec<&str> = tag_name.split_whitespace().collect();
+        jar: &CookieJar<'_>,
+        client_ip: Option<IpAddr>,
+        user_agent: UserAgent,
+    ) -> Result<Json<LoginResponse>, Custom<Json<ResponseData>>> {
+        let expired = || failure(Status::Unauthorized, "Login expired. Enter your password again.");
+        let now = Utc::now();
+
+        let pending = jar.get_private("pending_login").ok_or_else(expired)?;
+        let (expires, username) = pending.value().split_once('\n').ok_or_else(expired)?;
+        if expires <= timestamp(now).as_str() {
+            jar.remove_private(Cookie::from("pending_login"));
+            return Err(expired());
+        }
+        let username = username.to_string();
 
-        // Ensure there are at least two tags to work with, if not return an error
-        if tags.len() < 2 {
-            return Err("tag_name must contain at least two words".to_string());
+        let keys = attempt_keys(&username, client_ip);
+        if let Some(until) = login_blocked_until(&mut db, &keys).await.map_err(db_error)? {
+            return Err(failure(
+                Status::TooManyRequests,
+                format!("Too many failed login attempts. Try again after {until}."),
+            ));
         }
 
-        // Create the formatted tags with wildcards for partial matching
-        let tag1 = format!("%{}%", tags[0]); // First part of the tag
-        let tag2 = format!("%{}%", tags[1]); // Second part of the tag
+        if !check_second_factor(&mut db, &username, &totp_form.code, now)
+            .await
+            .map_err(db_error)?
+        {
+            record_login_failure(&mut db, &keys).await.map_err(db_error)?;
+            return Err(failure(Status::Unauthorized, "Invalid authentication code."));
+        }
 
-        // Query to find the var_id for the given product_id and both tag names
-        let var_id: u32 = rocket_db_pools::sqlx::query(
-            "SELECT var_id FROM product_variants WHERE product_id = $1 AND tag_name LIKE $2 AND tag_name LIKE $3",
-        )
-        .bind(product_id)
-        .bind(tag1) // Use the formatted first part of the tag
-        .bind(tag2) // Use the formatted second part of the tag
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| format!("Could not find variant for product_id {product_id} and tag_name {tag_name}: {e}"))?
-        .try_get("var_id")
-        .map_err(|e| format!("Could not get var_id from the database: {e}"))?;
+        jar.remove_private(Cookie::from("pending_login"));
+        finish_login(&mut db, jar, &username, client_ip, user_agent).await
+    }
 
-        // Return the var_id as a JSON response
-        Ok(Json(var_id))
+    #[derive(Serialize)]
+    struct TotpEnrollment {
+        /// The base32 secret, for typing into an authenticator app by hand.
+        secret: String,
+        otpauth_uri: String,
     }
 
+    /// Starts (or restarts) TOTP enrollment. The secret isn't used for logins
+    /// until it's confirmed with a code from `/totp/confirm`.
     #[allow(private_interfaces)]
-    #[post("/modify_variant", data = "<variant>")]
-    pub(super) async fn modify_variant(
-        variant: Json<ProductVariant>,
+    #[post("/totp/enroll")]
+    pub(super) async fn totp_enroll(
         mut db: Connection<RoboDatabase>,
-        jar: &CookieJar<'_>,
-    ) -> Result<&'static str, String> {
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
-        rocket_db_pools::sqlx::query(
-            "UPDATE product_variants SET quantity = ?, tag_name = ? WHERE var_id = ?",
-        )
-        .bind(variant.quantity)
-        .bind(
-            variant
-                .tag_name
-                .iter()
-                .map(|e| e.to_string())
-                .reduce(|x, y| x + " " + &y),
+        admin: AdminSession,
+    ) -> Result<Json<TotpEnrollment>, Custom<Json<ResponseData>>> {
+        let secret = base32::encode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &generate_totp_secret(),
+        );
+
+        let result = rocket_db_pools::sqlx::query(
+            "INSERT INTO admin_totp (username, secret, enabled) VALUES (?, ?, 0)
+            ON CONFLICT (username) DO UPDATE SET secret = excluded.secret, last_used_step = NULL
+            WHERE enabled = 0",
         )
-        .bind(variant.varid)
+        .bind(&admin.username)
+        .bind(&secret)
         .execute(&mut **db)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(db_error)?;
 
-        Ok("ok")
+        if result.rows_affected() == 0 {
+            return Err(failure(
+                Status::Conflict,
+                "Two-factor authentication is already enabled.",
+            ));
+        }
+
+        audit(&mut db, &admin, "totp.enroll", &admin.username, None, None)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Json(TotpEnrollment {
+            otpauth_uri: totp_uri(&admin.username, &secret),
+            secret,
+        }))
+    }
+
+    #[derive(Serialize)]
+    struct RecoveryCodes {
+        success: bool,
+        /// Shown once; only hashes are kept.
+        recovery_codes: Vec<String>,
     }
 
     #[allow(private_interfaces)]
-    #[post("/add_variant", data = "<variant>")]
-    pub(super) async fn add_product_variant(
-        variant: Json<ProductVariant>,
+    #[post("/totp/confirm", data = "<totp_form>")]
+    pub(super) async fn totp_confirm(
+        totp_form: Form<TotpCode>,
         mut db: Connection<RoboDatabase>,
-        jar: &CookieJar<'_>,
-    ) -> Result<Json<i32>, String> {
-        // Validate the user's token (authentication)
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
+        admin: AdminSession,
+    ) -> Result<Json<RecoveryCodes>, Custom<Json<ResponseData>>> {
+        let row = rocket_db_pools::sqlx::query(
+            "SELECT secret FROM admin_totp WHERE username = ? AND enabled = 0",
+        )
+        .bind(&admin.username)
+        .fetch_optional(&mut **db)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::NotFound, "Start enrollment with /totp/enroll first."))?;
 
-        // Map tags to their categories
-        let tag_mapping = vec![
-            ("small", VarTag::Size("small".to_string())),
-            ("medium", VarTag::Size("medium".to_string())),
-            ("large", VarTag::Size("large".to_string())),
-            ("white", VarTag::Color("white".to_string())),
-            ("red", VarTag::Color("red".to_string())),
-            ("blue", VarTag::Color("blue".to_string())),
-            ("fitted", VarTag::Fitted(true)),
-            ("snap", VarTag::Fitted(false)),
-        ];
+        let secret: String = row.get("secret");
+        let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+            .unwrap_or_default();
+        let step = verify_totp(&secret, &totp_form.code, Utc::now().timestamp() as u64, None)
+            .ok_or_else(|| failure(Status::BadRequest, "Invalid authentication code."))?;
 
-        // Validate and normalize the tags
-        let normalized_tags: Vec<String> = variant
-            .tag_name
-            .iter()
-            .map(|tag| {
-                // Map the tag to its corresponding VarTag variant
-                tag_mapping
-                    .iter()
-                    .find(|(_, category)| *category == *tag)
-                    .map(|(tag_value, _)| tag_value.to_string()) // Convert to string
-            })
-            .filter_map(|t| t) // Filter out invalid tags that couldn't be mapped
-            .collect();
+        let recovery_codes = generate_recovery_codes();
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
 
-        // Ensure all tags are valid (i.e., they map to recognized categories)
-        if normalized_tags.is_empty() {
-            return Err("Invalid tag name".into());
-        }
+        rocket_db_pools::sqlx::query(
+            "UPDATE admin_totp SET enabled = 1, last_used_step = ? WHERE username = ?",
+        )
+        .bind(step as i64)
+        .bind(&admin.username)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
 
-        // Combine the valid tags into a single string (e.g., "size small color white")
-        let combined_tags = normalized_tags.join(" ");
+        rocket_db_pools::sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+            .bind(&admin.username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
 
-        // Insert into the database and return the generated ID (var_id)
-        let result = rocket_db_pools::sqlx::query(
-            "insert into product_variants (quantity, tag_name, product_id) values (?, ?, ?) RETURNING var_id",
-        )
-        .bind(variant.quantity)
-        .bind(combined_tags) // Use the combined, normalized tags string
-        .bind(variant.product)
-        .fetch_one(&mut **db)
-        .await;
+        for code in &recovery_codes {
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO recovery_codes (username, code_hash) VALUES (?, ?)",
+            )
+            .bind(&admin.username)
+            .bind(hash_recovery_code(code))
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
 
-        match result {
-            Ok(row) => {
-                // Extract the generated ID (var_id) from the result
-                let var_id: i32 = row.try_get("var_id").map_err(|e| format!("Error extracting ID: {}", e))?;
+        audit(&mut tx, &admin, "totp.enable", &admin.username, None, None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
 
-                // Return the ID as JSON
-                Ok(Json(var_id))
-            }
-            Err(e) => Err(format!("Database error: {}", e)),
+        Ok(Json(RecoveryCodes {
+            success: true,
+            recovery_codes,
+        }))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/totp/disable", data = "<totp_form>")]
+    pub(super) async fn totp_disable(
+        totp_form: Form<TotpCode>,
+        mut db: Connection<RoboDatabase>,
+        admin: AdminSession,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        // Turning it off needs a current code, so a stolen session alone can't do it
+        if !check_second_factor(&mut db, &admin.username, &totp_form.code, Utc::now())
+            .await
+            .map_err(db_error)?
+        {
+            return Err(failure(Status::BadRequest, "Invalid authentication code."));
         }
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        rocket_db_pools::sqlx::query("DELETE FROM admin_totp WHERE username = ?")
+            .bind(&admin.username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        rocket_db_pools::sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+            .bind(&admin.username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        audit(&mut tx, &admin, "totp.disable", &admin.username, None, None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Two-factor authentication disabled.".into(),
+        }))
     }
 
+    #[derive(Serialize)]
+    struct LoginAttempts {
+        scope: String,
+        key: String,
+        failures: i64,
+        last_failure: String,
+        locked_until: Option<String>,
+        locked: bool,
+    }
 
     #[allow(private_interfaces)]
-    #[get("/get_websiteinfo")]
-    pub(super) async fn get_websiteinfo(
+    #[get("/lockouts")]
+    pub(super) async fn get_lockouts(
         mut db: Connection<RoboDatabase>,
-    ) -> Json<Vec<Description>> {
-        let rows = rocket_db_pools::sqlx::query("select * from website_information")
-            .fetch(&mut **db)
-            .filter_map(|row| async move {
-                let row = row.ok()?;
-                Some(Description {
-                    name: row.try_get("name").ok()?,
-                    desc: row.try_get("desc").ok()?,
+        _admin: Authorized<AdminsManage>,
+    ) -> Result<Json<Vec<LoginAttempts>>, Status> {
+        let now = Utc::now();
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT scope, key, failures, last_failure, locked_until FROM login_attempts
+            WHERE last_failure >= ? OR locked_until > ?
+            ORDER BY last_failure DESC",
+        )
+        .bind(timestamp(now - FAILURE_WINDOW))
+        .bind(timestamp(now))
+        .fetch_all(&mut **db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(
+            rows.into_iter()
+                .map(|row| {
+                    let locked_until: Option<String> = row.get("locked_until");
+                    let failures: i64 = row.get("failures");
+                    let scope: String = row.get("scope");
+                    LoginAttempts {
+                        locked: failures >= max_failures(&scope)
+                            && locked_until.as_ref().is_some_and(|u| *u > timestamp(now)),
+                        scope,
+                        key: row.get("key"),
+                        failures,
+                        last_failure: row.get("last_failure"),
+                        locked_until,
+                    }
                 })
-            })
-            .collect()
-            .await;
-        Json(rows)
+                .collect(),
+        ))
     }
 
     #[allow(private_interfaces)]
-    #[post("/update_websiteinfo", data = "<info>")]
-    pub(super) async fn update_websiteinfo(
-        info: Form<WebsiteInfo>,
+    #[delete("/lockouts/<scope>/<key>")]
+    pub(super) async fn clear_lockout(
+        scope: &str,
+        key: &str,
         mut db: Connection<RoboDatabase>,
-        jar: &CookieJar<'_>,
-    ) -> Result<Json<Value>, String> {
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
-        // SQL query to update the website information in the database
-        let result = rocket_db_pools::sqlx::query(
-            "UPDATE website_information SET desc = CASE name
-                WHEN 'aboutClub1' THEN ?
-                WHEN 'aboutClub2' THEN ?
-                WHEN 'clubHistory' THEN ?
-                WHEN 'clubActivities' THEN ?
-                WHEN 'joinClub' THEN ?
-                WHEN 'contact_email' THEN ?
-                WHEN 'contact_address' THEN ?
-                ELSE desc END
-            WHERE name IN ('aboutClub1', 'aboutClub2', 'clubHistory', 'clubActivities', 'joinClub', 'contact_email', 'contact_address');"
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = rocket_db_pools::sqlx::query(
+            "DELETE FROM login_attempts WHERE scope = ? AND key = ? RETURNING *",
         )
-        .bind(&info.club_desc1) // for 'aboutClub1'
-        .bind(&info.club_desc2) // for 'aboutClub2'
-        .bind(&info.club_history) // for 'clubHistory'
-        .bind(&info.club_activities) // for 'clubActivities'
-        .bind(&info.join_info) // for 'joinClub'
-        .bind(&info.contact_email) // for 'contact_email'
-        .bind(&info.contact_address) // for 'contact_address'
-        .execute(&mut **db)
-        .await;
+        .bind(scope)
+        .bind(key.to_lowercase())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::NotFound, "No failed logins recorded for that key."))?;
 
-        // Handle the result of the database operation
-        match result {
-            Ok(_) => Ok(Json(serde_json::json!({
-                "success": true,
-                "message": "Website information updated successfully.",
-            }))),
-            Err(err) => Err(format!("Database error: {err}")),
+        audit(&mut tx, &admin, "lockout.clear", &format!("{scope}:{key}"), Some(row_json(&before)), None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: format!("Cleared failed logins for {scope} {key}."),
+        }))
+    }
+
+    #[get("/admin_menu")]
+    pub async fn admin_menu(_admin: AdminSession) -> Json<Value> {
+        Json(serde_json::json!({
+            "success": true,
+            "message": "Access granted."
+        }))
+    }
+
+    #[post("/logout")]
+    pub async fn logout(jar: &CookieJar<'_>, mut db: Connection<RoboDatabase>) {
+        // Get the token from the cookie
+        if let Some(token_cookie) = jar.get_private("token") {
+            let token_value = token_cookie.value().to_string();
+
+            // Remove the "token" cookie from the jar to log out the client
+            jar.remove_private(Cookie::from("token"));
+
+            // End only this session; the admin's other devices stay logged in
+            let delete_result = rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+                .bind(sha256_hex(&token_value))
+                .execute(&mut **db)
+                .await;
+
+            // Optional: Check if the delete succeeded
+            if let Err(e) = delete_result {
+                eprintln!("Failed to end session: {}", e);
+            }
         }
     }
 
-    #[post("/makeimage", data = "<image>")]
-    pub(super) async fn make_image(
-        mut image: Form<TempFile<'_>>,
-        jar: &CookieJar<'_>,
-    ) -> Result<Json<String>, String> {
-        match validate_user(jar.get("token").map(|x| x.value()).unwrap_or("")) {
-            Some(_) => {}
-            None => return Err("Not logged in".into()),
-        };
+    /// The encrypted session cookie. It's kept away from scripts and never sent cross-site.
+    fn session_cookie(token: String) -> Cookie<'static> {
+        Cookie::build(("token", token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .secure(true)
+            .build()
+    }
+
+    /// Creates a session for `username` and returns the token to hand to the client.
+    async fn start_session(
+        db: &mut Connection<RoboDatabase>,
+        username: &str,
+        client_ip: Option<IpAddr>,
+        user_agent: UserAgent,
+    ) -> Result<String, rocket_db_pools::sqlx::Error> {
+        let token = Uuid::new_v4().to_string(); // Generate a unique token
+        let now = Utc::now();
+
+        // Sweep out expired sessions while we're here
+        rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+            .bind(timestamp(now))
+            .execute(&mut ***db)
+            .await?;
+
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO sessions (username, token_hash, created_at, last_seen, expires_at, ip, user_agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(sha256_hex(&token))
+        .bind(timestamp(now))
+        .bind(timestamp(now))
+        .bind(timestamp(now + SESSION_IDLE_TIMEOUT))
+        .bind(client_ip.map(|ip| ip.to_string()))
+        .bind(user_agent.0)
+        .execute(&mut ***db)
+        .await?;
+
+        Ok(token)
+    }
+
+    #[derive(Serialize)]
+    struct SessionInfo {
+        session_id: i64,
+        created_at: String,
+        last_seen: String,
+        expires_at: String,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        /// Whether this is the session making the request.
+        current: bool,
+    }
+
+    async fn active_sessions(
+        db: &mut Connection<RoboDatabase>,
+        username: &str,
+        current_session: i64,
+    ) -> Result<Vec<SessionInfo>, Status> {
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT session_id, created_at, last_seen, expires_at, ip, user_agent FROM sessions
+            WHERE username = ? AND expires_at > ? ORDER BY last_seen DESC",
+        )
+        .bind(username)
+        .bind(timestamp(Utc::now()))
+        .fetch_all(&mut ***db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let session_id: i64 = row.get("session_id");
+                SessionInfo {
+                    session_id,
+                    created_at: row.get("created_at"),
+                    last_seen: row.get("last_seen"),
+                    expires_at: row.get("expires_at"),
+                    ip: row.get("ip"),
+                    user_agent: row.get("user_agent"),
+                    current: session_id == current_session,
+                }
+            })
+            .collect())
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/sessions")]
+    pub(super) async fn get_sessions(
+        mut db: Connection<RoboDatabase>,
+        admin: AdminSession,
+    ) -> Result<Json<Vec<SessionInfo>>, Status> {
+        Ok(Json(
+            active_sessions(&mut db, &admin.username, admin.session_id).await?,
+        ))
+    }
+
+    #[allow(private_interfaces)]
+    #[delete("/sessions/<session_id>")]
+    pub(super) async fn revoke_session(
+        session_id: i64,
+        mut db: Connection<RoboDatabase>,
+        admin: AdminSession,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        // Admins can only end their own sessions here
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = rocket_db_pools::sqlx::query(
+            "DELETE FROM sessions WHERE session_id = ? AND username = ?
+            RETURNING session_id, created_at, last_seen, ip, user_agent",
+        )
+        .bind(session_id)
+        .bind(&admin.username)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::NotFound, "Session not found."))?;
 
-        let tfile = image.open();
-        let mut contents = String::new();
-        tfile
+        audit(&mut tx, &admin, "session.revoke", &admin.username, Some(row_json(&before)), None)
             .await
-            .map_err(|e| format!("File didn't upload {e}"))?
-            .read_to_string(&mut contents)
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Session ended.".into(),
+        }))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/logout_all")]
+    pub(super) async fn logout_all(
+        jar: &CookieJar<'_>,
+        mut db: Connection<RoboDatabase>,
+        admin: AdminSession,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let result = rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE username = ?")
+            .bind(&admin.username)
+            .execute(&mut *tx)
             .await
-            .map_err(|e| format!("Couldnt read file {e}"))?;
-        let contents = contents;
+            .map_err(db_error)?;
 
-        let name = hash_password(&contents);
-        let ctype = image
-            .content_type()
-            .ok_or("No file type detected")?
-            .extension()
-            .ok_or("File type not recognized")?
-            .to_string();
-        image
-            .persist_to(format!("images/{name}.{ctype}",))
+        let ended = serde_json::json!({ "sessions": result.rows_affected() });
+        audit(&mut tx, &admin, "session.revoke_all", &admin.username, Some(ended), None)
             .await
-            .map_err(|e| format!("Couldn't save file {e}"))?;
-        Ok(Json(format!("{name}.{ctype}")))
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        jar.remove_private(Cookie::from("token"));
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Logged out of all devices.".into(),
+        }))
     }
 
     #[allow(private_interfaces)]
-    #[get("/get_admins")]
-    pub(super) async fn get_admins(
+    #[get("/admins/<username>/sessions")]
+    pub(super) async fn get_admin_sessions(
+        username: &str,
         mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Vec<String>>, Status> {
-        // SQL query to fetch all usernames from the admins table
-        let usernames_query = rocket_db_pools::sqlx::query("SELECT username FROM admins")
-            .fetch_all(&mut **db)
-            .await;
-
-        // Map rows to Vec<String> containing only usernames
-        match usernames_query {
-            Ok(rows) => {
-                let usernames: Vec<String> =
-                    rows.into_iter().map(|row| row.get("username")).collect();
-                Ok(Json(usernames))
-            }
-            Err(_) => Err(Status::InternalServerError),
-        }
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<Vec<SessionInfo>>, Status> {
+        Ok(Json(
+            active_sessions(&mut db, username, admin.session_id).await?,
+        ))
     }
 
     #[allow(private_interfaces)]
-    #[delete("/delete_admin/<username>")]
-    pub(super) async fn delete_admin(
+    #[delete("/admins/<username>/sessions")]
+    pub(super) async fn revoke_admin_sessions(
         username: &str,
         mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<ResponseData>, Status> {
-        // SQL query to delete the admin by username
-        let result = rocket_db_pools::sqlx::query("DELETE FROM admins WHERE username = ?")
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let result = rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE username = ?")
             .bind(username)
-            .execute(&mut **db)
-            .await;
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
 
-        // Handle the result of the database operation
-        match result {
-            Ok(_) => Ok(Json(ResponseData {
-                success: true,
-                message: "Admin user deleted successfully.".to_string(),
-            })),
-            Err(_) => Err(Status::InternalServerError),
-        }
+        let ended = serde_json::json!({ "sessions": result.rows_affected() });
+        audit(&mut tx, &admin, "session.revoke_all", username, Some(ended), None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: format!(
+                "Ended {} session(s) for {username}.",
+                result.rows_affected()
+            ),
+        }))
     }
 
-    // Structs for customers, orders, and products
-    #[derive(Serialize, Deserialize)]
-    struct Customer {
-        cust_id: Option<i32>,
-        name: String,
-        address: Address,
-        email: String,
-        phone_number: Option<String>,
+    #[allow(private_interfaces)]
+    #[post("/create_admin", data = "<admin_form>")]
+    pub async fn create_admin(
+        admin_form: Form<CreateAdmin>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        validate_new_password(&admin_form.password)
+            .map_err(|message| failure(Status::BadRequest, message))?;
+        let today = Utc::now().date_naive();
+        let expiration = parse_expiration(&admin_form.expiration, today)
+            .map_err(|message| failure(Status::BadRequest, message))?;
+
+        if let Some(unknown) = admin_form
+            .permissions
+            .iter()
+            .find(|p| !PERMISSIONS.contains(&p.as_str()))
+        {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Unknown permission `{unknown}`."),
+            ));
+        }
+
+        let (salt, hashed_password) = hash_password(&admin_form.password).map_err(|e| {
+            failure(Status::InternalServerError, format!("Failed to hash password: {e}"))
+        })?;
+
+        // Insert the admin and its initial permissions together so a failure leaves neither behind
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO admins (username, salt, password, expiration, status) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&admin_form.username)
+        .bind(salt)
+        .bind(hashed_password)
+        .bind(expiration.format("%Y-%m-%d").to_string())
+        .bind(expiry_status(expiration, today))
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        for permission in &admin_form.permissions {
+            rocket_db_pools::sqlx::query(
+                "INSERT OR IGNORE INTO permissions (username, permission) VALUES (?, ?)",
+            )
+            .bind(&admin_form.username)
+            .bind(permission)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+
+        let after = admin_json(&mut tx, &admin_form.username).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "admin.create", &admin_form.username, None, after)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Admin user created successfully.".to_string(),
+        }))
     }
 
-    #[derive(Serialize)]
-    struct Order {
-        order_id: i32,
-        products: Vec<OrderedItem>,
+    /// Parses an admin account's expiration date, which must be a real date after `today`.
+    fn parse_expiration(expiration: &str, today: NaiveDate) -> Result<NaiveDate, String> {
+        let date = NaiveDate::parse_from_str(expiration.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("`{expiration}` is not a valid date (expected YYYY-MM-DD)."))?;
+        if date <= today {
+            return Err("Expiration date must be in the future.".into());
+        }
+        Ok(date)
     }
 
-    #[derive(Serialize, Deserialize)]
-    struct OrderedItem {
-        product_id: i32,
-        variant: Option<i32>,
-        quantity: i32,
+    /// How long the first admin account lasts unless `--expiration` is given.
+    const BOOTSTRAP_ADMIN_LIFETIME: Duration = Duration::days(365);
+
+    #[derive(Debug, PartialEq)]
+    struct BootstrapOptions {
+        username: String,
+        expiration: Option<String>,
     }
 
-    #[derive(Serialize, Deserialize)]
-    struct Address {
-        address_line_1: String,
-        admin_area_2: String, // City
-        admin_area_1: String, // State
-        postal_code: String,
-        country_code: String,
+    /// Parses the arguments after `bootstrap-admin`: `--username <name>` and an
+    /// optional `--expiration <YYYY-MM-DD>`. Both also accept `--flag=value`.
+    fn parse_bootstrap_args(args: &[String]) -> Result<BootstrapOptions, String> {
+        let mut username = None;
+        let mut expiration = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let slot = match flag {
+                "--username" => &mut username,
+                "--expiration" => &mut expiration,
+                _ => return Err(format!("Unknown argument `{arg}`.")),
+            };
+            let value = inline
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| format!("`{flag}` needs a value."))?;
+            *slot = Some(value);
+        }
+
+        let username = username
+            .filter(|u| !u.trim().is_empty())
+            .ok_or("Usage: kent_state_robotics_website bootstrap-admin --username <name> [--expiration YYYY-MM-DD]")?;
+        Ok(BootstrapOptions {
+            username,
+            expiration,
+        })
     }
 
-    // Fetch all customers
-    #[allow(private_interfaces)]
-    #[get("/getallcustomers")]
-    pub(super) async fn get_all_customers(
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Vec<Customer>>, Status> {
-        let query = "SELECT cust_id, name, address, email, phone_number FROM customers";
+    /// Creates the first admin, with every permission, in a database that has no
+    /// admins yet. Run from the command line with `bootstrap-admin` before anyone
+    /// can log in; it refuses to touch a database that already has an admin.
+    pub(super) async fn bootstrap_admin(args: &[String]) -> Result<String, String> {
+        let options = parse_bootstrap_args(args)?;
+        let username = options.username.trim();
+        let today = Utc::now().date_naive();
+        let expiration = match &options.expiration {
+            Some(expiration) => parse_expiration(expiration, today)?,
+            None => today + BOOTSTRAP_ADMIN_LIFETIME,
+        };
+
+        // Use the same database the server would
+        let url: String = rocket::Config::figment()
+            .extract_inner("databases.db.url")
+            .map_err(|e| format!("No database configured: {e}"))?;
+        let connect_options = url
+            .parse::<SqliteConnectOptions>()
+            .map_err(|e| format!("Invalid database URL `{url}`: {e}"))?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(connect_options)
+            .await
+            .map_err(|e| format!("Failed to open database `{url}`: {e}"))?;
+        run_migrations(&pool)
+            .await
+            .map_err(|e| format!("Database migration failed: {e}"))?;
+
+        let count_admins = "SELECT COUNT(*) AS n FROM admins";
+        let db_error = |e| format!("Database error: {e}");
+        let admins: i64 = rocket_db_pools::sqlx::query(count_admins)
+            .fetch_one(&pool)
+            .await
+            .map_err(db_error)?
+            .get("n");
+        if admins > 0 {
+            return Err("Admins already exist. Ask one of them to create your account.".into());
+        }
+
+        let password = rpassword::prompt_password(format!("Password for {username}: "))
+            .map_err(|e| format!("Failed to read password: {e}"))?;
+        validate_new_password(&password)?;
+        let repeated = rpassword::prompt_password("Repeat password: ")
+            .map_err(|e| format!("Failed to read password: {e}"))?;
+        if password != repeated {
+            return Err("Passwords don't match.".into());
+        }
+        let (salt, hashed_password) =
+            hash_password(&password).map_err(|e| format!("Failed to hash password: {e}"))?;
+
+        let mut tx = pool.begin().await.map_err(db_error)?;
+
+        // Check again in case an admin was added while we waited for the password
+        let admins: i64 = rocket_db_pools::sqlx::query(count_admins)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .get("n");
+        if admins > 0 {
+            return Err("Admins already exist. Ask one of them to create your account.".into());
+        }
+
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO admins (username, salt, password, expiration, status) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(salt)
+        .bind(hashed_password)
+        .bind(expiration.format("%Y-%m-%d").to_string())
+        .bind(expiry_status(expiration, today))
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        for permission in PERMISSIONS {
+            rocket_db_pools::sqlx::query("INSERT INTO permissions (username, permission) VALUES (?, ?)")
+                .bind(username)
+                .bind(permission)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+
+        // There's no logged-in admin yet, so the command itself is the actor
+        let actor = AdminSession {
+            session_id: 0,
+            username: "bootstrap-admin".into(),
+            permissions: vec![],
+            ip: None,
+        };
+        let after = admin_json(&mut tx, username).await.map_err(db_error)?;
+        audit(&mut tx, &actor, "admin.create", username, None, after)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(format!(
+            "Created admin {username} with every permission. The account expires on {expiration}."
+        ))
+    }
+
+    /// An admin's account details and permissions, for the audit log. Never
+    /// includes password hashes.
+    async fn admin_json(
+        conn: &mut SqliteConnection,
+        username: &str,
+    ) -> Result<Option<Value>, rocket_db_pools::sqlx::Error> {
+        let Some(row) = rocket_db_pools::sqlx::query(
+            "SELECT username, expiration, status FROM admins WHERE username = ?",
+        )
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let mut admin = row_json(&row);
+        admin["permissions"] = rocket_db_pools::sqlx::query(
+            "SELECT permission FROM permissions WHERE username = ? ORDER BY permission",
+        )
+        .bind(username)
+        .fetch_all(conn)
+        .await?
+        .iter()
+        .map(|row| Value::from(row.get::<String, _>("permission")))
+        .collect();
+        Ok(Some(admin))
+    }
+
+    /// Admins who can still log in: neither disabled nor past their expiration
+    /// date, which the sweep may not have caught up with yet. Binds today's date.
+    const USABLE_ADMIN: &str = "a.status != 'disabled' AND a.expiration >= ?";
+
+    /// How many admins who can still log in hold `permission`.
+    async fn count_with_permission(
+        conn: &mut SqliteConnection,
+        permission: &str,
+    ) -> Result<i64, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query(&format!(
+            "SELECT COUNT(*) AS n FROM permissions p JOIN admins a ON a.username = p.username
+            WHERE p.permission = ? AND {USABLE_ADMIN}"
+        ))
+        .bind(permission)
+        .bind(Utc::now().date_naive().format("%Y-%m-%d").to_string())
+        .fetch_one(conn)
+        .await?
+        .get("n"))
+    }
+
+    /// How many admins can still log in.
+    async fn count_usable_admins(conn: &mut SqliteConnection) -> Result<i64, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query(&format!("SELECT COUNT(*) AS n FROM admins a WHERE {USABLE_ADMIN}"))
+            .bind(Utc::now().date_naive().format("%Y-%m-%d").to_string())
+            .fetch_one(conn)
+            .await?
+            .get("n"))
+    }
+
+    /// How close to its expiration date an account is flagged as expiring.
+    const EXPIRY_WARNING_DAYS: i64 = 14;
+    /// How often the background task re-checks admin expiration dates.
+    const EXPIRY_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+    /// The status an admin account should have on `today`. An account stays usable
+    /// through its expiration date and is disabled the day after.
+    fn expiry_status(expiration: NaiveDate, today: NaiveDate) -> &'static str {
+        if today > expiration {
+            "disabled"
+        } else if expiration - today <= Duration::days(EXPIRY_WARNING_DAYS) {
+            "expiring"
+        } else {
+            "active"
+        }
+    }
+
+    /// Brings every admin's `status` up to date with its expiration date.
+    ///
+    /// Expired accounts are disabled rather than deleted, so their history stays
+    /// intact, and any sessions they still hold are revoked. A disabled account
+    /// stays disabled, however it got that way; only [`extend_admins`] enables
+    /// it again.
+    async fn sweep_admin_expiry(pool: &SqlitePool) -> Result<(), rocket_db_pools::sqlx::Error> {
+        // Nobody is logged in to do this, so the sweep is recorded as the actor
+        let actor = AdminSession {
+            session_id: 0,
+            username: "expiry-sweep".into(),
+            permissions: vec![],
+            ip: None,
+        };
+        let today = Utc::now().date_naive();
+        let rows = rocket_db_pools::sqlx::query("SELECT username, expiration, status FROM admins")
+            .fetch_all(pool)
+            .await?;
+
+        for row in rows {
+            let username: String = row.get("username");
+            let current: String = row.get("status");
+            let expiration: String = row.get("expiration");
+            let Ok(expiration) = NaiveDate::parse_from_str(&expiration, "%Y-%m-%d") else {
+                eprintln!("Admin {username} has an unreadable expiration date: {expiration}");
+                continue;
+            };
+
+            let status = expiry_status(expiration, today);
+            if status == current || current == "disabled" {
+                continue;
+            }
+
+            let mut tx = pool.begin().await?;
+            let before = admin_json(&mut tx, &username).await?;
+            // Skipped if the account changed since it was read, e.g. an admin just extended it
+            let updated = rocket_db_pools::sqlx::query(
+                "UPDATE admins SET status = ? WHERE username = ? AND status = ?",
+            )
+            .bind(status)
+            .bind(&username)
+            .bind(&current)
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 0 {
+                continue;
+            }
+            if status == "disabled" {
+                rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE username = ?")
+                    .bind(&username)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            let after = admin_json(&mut tx, &username).await?;
+            audit(&mut tx, &actor, "admin.status", &username, before, after).await?;
+            tx.commit().await?;
+
+            match status {
+                "disabled" => println!("Admin account {username} expired on {expiration} and was disabled."),
+                "expiring" => println!("Admin account {username} expires on {expiration}."),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs [`sweep_admin_expiry`], [`sweep_expired_carts`] and
+    /// [`sweep_expired_holds`] once at launch and then every
+    /// [`EXPIRY_SWEEP_INTERVAL`]. Setting `expiry_sweep = false` in Rocket.toml
+    /// turns it off, for when another instance already sweeps the same database.
+    pub(super) fn expiry_sweeper() -> AdHoc {
+        AdHoc::on_liftoff("Expiry Sweep", |rocket| {
+            Box::pin(async move {
+                if !rocket.figment().extract_inner("expiry_sweep").unwrap_or(true) {
+                    return;
+                }
+                let Some(db) = RoboDatabase::fetch(rocket) else {
+                    eprintln!("Admin expiry sweep disabled: database is not attached.");
+                    return;
+                };
+                let pool = (**db).clone();
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = sweep_admin_expiry(&pool).await {
+                            eprintln!("Failed to sweep admin expiry: {e}");
+                        }
+                        if let Err(e) = sweep_expired_carts(&pool).await {
+                            eprintln!("Failed to delete expired carts: {e}");
+                        }
+                        if let Err(e) = sweep_expired_holds(&pool).await {
+                            eprintln!("Failed to delete expired stock holds: {e}");
+                        }
+                    }
+                });
+            })
+        })
+    }
+
+    #[derive(Serialize)]
+    struct AdminExpiry {
+        username: String,
+        expiration: String,
+        status: String,
+    }
+
+    /// Lists accounts that are expiring soon or already disabled, soonest first.
+    #[allow(private_interfaces)]
+    #[get("/admins/expiring")]
+    pub(super) async fn expiring_admins(
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<AdminsManage>,
+    ) -> Result<Json<Vec<AdminExpiry>>, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT username, expiration, status FROM admins
+            WHERE status != 'active' ORDER BY expiration",
+        )
+        .fetch_all(&mut **db)
+        .await
+        .map_err(db_error)?;
+
+        Ok(Json(
+            rows.iter()
+                .map(|row| AdminExpiry {
+                    username: row.get("username"),
+                    expiration: row.get("expiration"),
+                    status: row.get("status"),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Moves several accounts to a new expiration date at once, e.g. to the end of
+    /// next semester, re-enabling any that had been disabled.
+    #[allow(private_interfaces)]
+    #[post("/admins/extend", data = "<extend_form>")]
+    pub(super) async fn extend_admins(
+        extend_form: Form<ExtendAdmins>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        if extend_form.usernames.is_empty() {
+            return Err(failure(Status::BadRequest, "Choose at least one admin to extend."));
+        }
+        let today = Utc::now().date_naive();
+        let expiration = parse_expiration(&extend_form.expiration, today)
+            .map_err(|message| failure(Status::BadRequest, message))?;
+        let status = expiry_status(expiration, today);
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        for username in &extend_form.usernames {
+            let before = admin_json(&mut tx, username)
+                .await
+                .map_err(db_error)?
+                .ok_or_else(|| failure(Status::NotFound, format!("Admin {username} not found.")))?;
+            rocket_db_pools::sqlx::query(
+                "UPDATE admins SET expiration = ?, status = ? WHERE username = ?",
+            )
+            .bind(expiration.format("%Y-%m-%d").to_string())
+            .bind(status)
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+            let after = admin_json(&mut tx, username).await.map_err(db_error)?;
+            audit(&mut tx, &admin, "admin.extend", username, Some(before), after)
+                .await
+                .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: format!(
+                "Extended {} until {expiration}.",
+                extend_form.usernames.join(", ")
+            ),
+        }))
+    }
+
+    #[get("/permissions")]
+    pub(super) async fn list_permissions(_admin: AdminSession) -> Json<Vec<&'static str>> {
+        Json(PERMISSIONS.to_vec())
+    }
+
+    #[get("/admins/<username>/permissions")]
+    pub(super) async fn get_admin_permissions(
+        username: &str,
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<AdminsManage>,
+    ) -> Result<Json<Vec<String>>, Status> {
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT permission FROM permissions WHERE username = ? ORDER BY permission",
+        )
+        .bind(username)
+        .fetch_all(&mut **db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(rows.iter().map(|row| row.get("permission")).collect()))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/admins/<username>/permissions/<permission>")]
+    pub(super) async fn grant_permission(
+        username: &str,
+        permission: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        if !PERMISSIONS.contains(&permission) {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Unknown permission `{permission}`."),
+            ));
+        }
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = admin_json(&mut tx, username)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Admin user not found."))?;
+
+        let result = rocket_db_pools::sqlx::query(
+            "INSERT OR IGNORE INTO permissions (username, permission) VALUES (?, ?)",
+        )
+        .bind(username)
+        .bind(permission)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        if result.rows_affected() > 0 {
+            let after = admin_json(&mut tx, username).await.map_err(db_error)?;
+            audit(&mut tx, &admin, "permission.grant", username, Some(before), after)
+                .await
+                .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: format!("Granted `{permission}` to {username}."),
+        }))
+    }
+
+    #[allow(private_interfaces)]
+    #[delete("/admins/<username>/permissions/<permission>")]
+    pub(super) async fn revoke_permission(
+        username: &str,
+        permission: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = admin_json(&mut tx, username).await.map_err(db_error)?;
+
+        let result = rocket_db_pools::sqlx::query(
+            "DELETE FROM permissions WHERE username = ? AND permission = ?",
+        )
+        .bind(username)
+        .bind(permission)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(failure(
+                Status::NotFound,
+                format!("{username} does not have `{permission}`."),
+            ));
+        }
+
+        // Someone must always be left who can manage admins. Counted after the
+        // delete, in the same transaction, so two managers revoking each other
+        // at once can't both succeed.
+        if permission == AdminsManage::NAME && count_with_permission(&mut tx, permission).await.map_err(db_error)? == 0 {
+            return Err(failure(
+                Status::Conflict,
+                format!("{username} is the only admin who can manage admins."),
+            ));
+        }
+
+        let after = admin_json(&mut tx, username).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "permission.revoke", username, before, after)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: format!("Revoked `{permission}` from {username}."),
+        }))
+    }
+
+    /// Hashes a password with Argon2id, returning `(salt, phc_hash)`.
+    ///
+    /// The PHC string carries its own salt and parameters; the salt is also
+    /// returned because `admins.salt` is still a required column.
+    fn hash_password(password: &str) -> Result<(String, String), argon2::password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+        Ok((salt.as_str().to_string(), hash.to_string()))
+    }
+
+    const MIN_PASSWORD_LENGTH: usize = 8;
+
+    fn validate_new_password(password: &str) -> Result<(), String> {
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(format!(
+                "Password must be at least {MIN_PASSWORD_LENGTH} characters long."
+            ));
+        }
+        Ok(())
+    }
+
+    /// How long a password reset token can be used for.
+    const RESET_TOKEN_LIFETIME: Duration = Duration::hours(24);
+
+    #[allow(private_interfaces)]
+    #[post("/change_password", data = "<password_form>")]
+    pub(super) async fn change_password(
+        password_form: Form<ChangePassword>,
+        mut db: Connection<RoboDatabase>,
+        client_ip: Option<IpAddr>,
+        admin: AdminSession,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        // Wrong guesses here count as failed logins, so a hijacked session can't brute force the password
+        let keys = attempt_keys(&admin.username, client_ip);
+        if let Some(until) = login_blocked_until(&mut db, &keys).await.map_err(db_error)? {
+            return Err(failure(
+                Status::TooManyRequests,
+                format!("Too many failed attempts. Try again after {until}."),
+            ));
+        }
+
+        let row = rocket_db_pools::sqlx::query("SELECT salt, password FROM admins WHERE username = ?")
+            .bind(&admin.username)
+            .fetch_one(&mut **db)
+            .await
+            .map_err(db_error)?;
+        let check = verify_password(
+            &password_form.current_password,
+            row.get("password"),
+            row.get("salt"),
+        );
+        if check == PasswordCheck::Invalid {
+            record_login_failure(&mut db, &keys).await.map_err(db_error)?;
+            return Err(failure(Status::Unauthorized, "Current password is incorrect."));
+        }
+
+        validate_new_password(&password_form.new_password)
+            .map_err(|message| failure(Status::BadRequest, message))?;
+        let (salt, hashed_password) = hash_password(&password_form.new_password).map_err(|e| {
+            failure(Status::InternalServerError, format!("Failed to hash password: {e}"))
+        })?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        rocket_db_pools::sqlx::query("UPDATE admins SET salt = ?, password = ? WHERE username = ?")
+            .bind(salt)
+            .bind(hashed_password)
+            .bind(&admin.username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        // Log out every other device that knew the old password
+        rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE username = ? AND session_id != ?")
+            .bind(&admin.username)
+            .bind(admin.session_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        audit(&mut tx, &admin, "admin.password_change", &admin.username, None, None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Password changed. Other sessions have been logged out.".into(),
+        }))
+    }
+
+    #[derive(Serialize)]
+    struct PasswordResetToken {
+        success: bool,
+        /// Shown once; only a hash is kept. Give it to the admin in person.
+        token: String,
+        expires_at: String,
+    }
+
+    /// Issues a one-time token that lets `username` set a new password without
+    /// knowing the old one. Any earlier unused token for them stops working.
+    #[allow(private_interfaces)]
+    #[post("/admins/<username>/password_reset")]
+    pub(super) async fn create_password_reset(
+        username: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<PasswordResetToken>, Custom<Json<ResponseData>>> {
+        let exists = rocket_db_pools::sqlx::query("SELECT 1 FROM admins WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&mut **db)
+            .await
+            .map_err(db_error)?;
+        if exists.is_none() {
+            return Err(failure(Status::NotFound, "Admin user not found."));
+        }
+
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let now = Utc::now();
+        let expires_at = timestamp(now + RESET_TOKEN_LIFETIME);
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        rocket_db_pools::sqlx::query(
+            "DELETE FROM password_resets WHERE username = ? AND used_at IS NULL",
+        )
+        .bind(username)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO password_resets (username, token_hash, created_by, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(username)
+        .bind(sha256_hex(&token))
+        .bind(&admin.username)
+        .bind(timestamp(now))
+        .bind(&expires_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        let after = serde_json::json!({ "expires_at": expires_at });
+        audit(&mut tx, &admin, "admin.password_reset", username, None, Some(after))
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(PasswordResetToken {
+            success: true,
+            token,
+            expires_at,
+        }))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/reset_password", data = "<reset_form>")]
+    pub(super) async fn reset_password(
+        reset_form: Form<ResetPassword>,
+        client_ip: Option<IpAddr>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        validate_new_password(&reset_form.new_password)
+            .map_err(|message| failure(Status::BadRequest, message))?;
+        let (salt, hashed_password) = hash_password(&reset_form.new_password).map_err(|e| {
+            failure(Status::InternalServerError, format!("Failed to hash password: {e}"))
+        })?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        // Claim the token; the `used_at IS NULL` check makes it single-use even under concurrent requests
+        let username: String = rocket_db_pools::sqlx::query(
+            "UPDATE password_resets SET used_at = ?
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > ?
+            RETURNING username",
+        )
+        .bind(timestamp(Utc::now()))
+        .bind(sha256_hex(reset_form.token.trim()))
+        .bind(timestamp(Utc::now()))
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::BadRequest, "Reset token is invalid or has expired."))?
+        .get("username");
+
+        rocket_db_pools::sqlx::query("UPDATE admins SET salt = ?, password = ? WHERE username = ?")
+            .bind(salt)
+            .bind(hashed_password)
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        rocket_db_pools::sqlx::query("DELETE FROM sessions WHERE username = ?")
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        // Let a locked-out admin log in with the new password straight away
+        rocket_db_pools::sqlx::query("DELETE FROM login_attempts WHERE scope = 'username' AND key = ?")
+            .bind(username.to_lowercase())
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        // Whoever holds the token acts as the admin it was issued for
+        let actor = AdminSession {
+            session_id: 0,
+            username: username.clone(),
+            permissions: vec![],
+            ip: client_ip,
+        };
+        audit(&mut tx, &actor, "admin.password_reset_used", &username, None, None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Password reset. You can now log in with your new password.".into(),
+        }))
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum PasswordCheck {
+        Valid,
+        /// The password matched, but the stored hash is legacy SHA-256 or uses old parameters.
+        NeedsRehash,
+        Invalid,
+    }
+
+    /// Checks a password against `admins.password`, accepting both Argon2 PHC
+    /// strings and legacy hex SHA-256 hashes of `password + salt`.
+    fn verify_password(password: &str, stored: &str, legacy_salt: &str) -> PasswordCheck {
+        match PasswordHash::new(stored) {
+            Ok(parsed) => {
+                if Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_err()
+                {
+                    PasswordCheck::Invalid
+                } else if parsed.algorithm != argon2::Algorithm::Argon2id.ident()
+                    || Params::try_from(&parsed)
+                        .map(|p| (p.m_cost(), p.t_cost(), p.p_cost()))
+                        .ok()
+                        != Some((
+                            Params::DEFAULT_M_COST,
+                            Params::DEFAULT_T_COST,
+                            Params::DEFAULT_P_COST,
+                        ))
+                {
+                    PasswordCheck::NeedsRehash
+                } else {
+                    PasswordCheck::Valid
+                }
+            }
+            Err(_) => {
+                if sha256_hex(&format!("{password}{legacy_salt}")) == stored {
+                    PasswordCheck::NeedsRehash
+                } else {
+                    PasswordCheck::Invalid
+                }
+            }
+        }
+    }
+
+    fn sha256_hex(data: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let result = hasher.finalize();
+        hex::encode(result) // Return the hex representation of the hash
+    }
+
+    /// How long a cart is kept after it last changed, set as `cart_ttl_days`
+    /// in Rocket.toml. The cart cookie lasts as long, so the cart is still
+    /// there after the browser is closed. `cart_hold_minutes` is how long its
+    /// stock stays held once checkout starts.
+    #[derive(Deserialize)]
+    pub(super) struct CartConfig {
+        #[serde(default = "CartConfig::default_ttl_days")]
+        cart_ttl_days: u16,
+        #[serde(default = "CartConfig::default_hold_minutes")]
+        cart_hold_minutes: u16,
+    }
+
+    impl CartConfig {
+        fn default_ttl_days() -> u16 {
+            30
+        }
+
+        fn default_hold_minutes() -> u16 {
+            15
+        }
+
+        fn ttl(&self) -> Duration {
+            Duration::days(self.cart_ttl_days.into())
+        }
+
+        fn hold(&self) -> Duration {
+            Duration::minutes(self.cart_hold_minutes.into())
+        }
+    }
+
+    /// The private cookie holding the visitor's cart token. The cart itself is
+    /// in the database, found by the token's hash.
+    const CART_COOKIE: &str = "cart";
+
+    fn cart_cookie(token: String, config: &CartConfig) -> Cookie<'static> {
+        Cookie::build((CART_COOKIE, token))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(rocket::time::Duration::days(config.cart_ttl_days.into()))
+            .build()
+    }
+
+    /// The visitor's cart, if they have one that hasn't expired.
+    async fn current_cart(
+        conn: &mut SqliteConnection,
+        jar: &CookieJar<'_>,
+    ) -> Result<Option<i64>, rocket_db_pools::sqlx::Error> {
+        let Some(cookie) = jar.get_private(CART_COOKIE) else {
+            return Ok(None);
+        };
+        Ok(rocket_db_pools::sqlx::query("SELECT cart_id FROM carts WHERE token_hash = ? AND expires_at > ?")
+            .bind(sha256_hex(cookie.value()))
+            .bind(timestamp(Utc::now()))
+            .fetch_optional(conn)
+            .await?
+            .map(|row| row.get("cart_id")))
+    }
+
+    /// The visitor's cart, started if they don't have one. Either way it's
+    /// kept for another `cart_ttl_days` from now.
+    async fn open_cart(
+        conn: &mut SqliteConnection,
+        jar: &CookieJar<'_>,
+        config: &CartConfig,
+    ) -> Result<i64, rocket_db_pools::sqlx::Error> {
+        let now = Utc::now();
+        let expires_at = timestamp(now + config.ttl());
+        if let Some(cart_id) = current_cart(conn, jar).await? {
+            rocket_db_pools::sqlx::query("UPDATE carts SET updated_at = ?, expires_at = ? WHERE cart_id = ?")
+                .bind(timestamp(now))
+                .bind(&expires_at)
+                .bind(cart_id)
+                .execute(conn)
+                .await?;
+            if let Some(cookie) = jar.get_private(CART_COOKIE) {
+                jar.add_private(cart_cookie(cookie.value().to_string(), config));
+            }
+            return Ok(cart_id);
+        }
+
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let cart_id = rocket_db_pools::sqlx::query(
+            "INSERT INTO carts (token_hash, created_at, updated_at, expires_at) VALUES (?, ?, ?, ?)
+            RETURNING cart_id",
+        )
+        .bind(sha256_hex(&token))
+        .bind(timestamp(now))
+        .bind(timestamp(now))
+        .bind(&expires_at)
+        .fetch_one(conn)
+        .await?
+        .get("cart_id");
+        jar.add_private(cart_cookie(token, config));
+        Ok(cart_id)
+    }
+
+    /// The body of a `409 Conflict` for asking for more of an item than is
+    /// left. `available` is the most the cart can have.
+    #[derive(Serialize)]
+    struct Shortage {
+        success: bool,
+        message: String,
+        product: i64,
+        var_id: Option<u32>,
+        available: u32,
+    }
+
+    /// Why a cart change or checkout was refused: the usual JSON error, or a
+    /// shortage saying how many are left.
+    enum CartError {
+        Failed(Custom<Json<ResponseData>>),
+        OutOfStock(Shortage),
+    }
+
+    impl From<Custom<Json<ResponseData>>> for CartError {
+        fn from(error: Custom<Json<ResponseData>>) -> Self {
+            CartError::Failed(error)
+        }
+    }
+
+    impl<'r> Responder<'r, 'static> for CartError {
+        fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+            match self {
+                CartError::Failed(error) => error.respond_to(req),
+                CartError::OutOfStock(shortage) => Custom(Status::Conflict, Json(shortage)).respond_to(req),
+            }
+        }
+    }
+
+    /// How many of a product, or of one of its variants, a cart can have: the
+    /// stock, less what other carts hold in checkout. Expired holds don't count.
+    async fn available_stock(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+        cart_id: Option<i64>,
+    ) -> Result<u32, rocket_db_pools::sqlx::Error> {
+        // Stock of a product with variants is counted per variant
+        let stock: i64 = match var_id {
+            Some(var_id) => rocket_db_pools::sqlx::query("SELECT quantity FROM product_variants WHERE var_id = ?")
+                .bind(var_id)
+                .fetch_optional(&mut *conn)
+                .await?,
+            None => rocket_db_pools::sqlx::query("SELECT quantity FROM products WHERE product_id = ?")
+                .bind(product_id)
+                .fetch_optional(&mut *conn)
+                .await?,
+        }
+        .map_or(0, |row| row.get("quantity"));
+        let held: i64 = rocket_db_pools::sqlx::query(
+            "SELECT COALESCE(SUM(quantity), 0) AS held FROM stock_holds
+            WHERE product_id = ? AND var_id IS ? AND expires_at > ? AND cart_id IS NOT ?",
+        )
+        .bind(product_id)
+        .bind(var_id)
+        .bind(timestamp(Utc::now()))
+        .bind(cart_id)
+        .fetch_one(conn)
+        .await?
+        .get("held");
+        Ok((stock - held).clamp(0, u32::MAX.into()) as u32)
+    }
+
+    /// Checks that a cart can have `wanted` of an item.
+    async fn check_stock(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+        cart_id: Option<i64>,
+        wanted: u32,
+    ) -> Result<(), CartError> {
+        let available = available_stock(conn, product_id, var_id, cart_id)
+            .await
+            .map_err(db_error)?;
+        if wanted <= available {
+            return Ok(());
+        }
+        let item = describe_item(conn, product_id, var_id).await.map_err(db_error)?;
+        Err(CartError::OutOfStock(Shortage {
+            success: false,
+            message: match available {
+                0 => format!("{item} is sold out."),
+                1 => format!("Only 1 {item} is left."),
+                _ => format!("Only {available} {item} are left."),
+            },
+            product: product_id,
+            var_id,
+            available,
+        }))
+    }
+
+    /// Lets go of the stock a cart holds. Changing a cart does this, so
+    /// checkout holds the new contents instead.
+    async fn release_holds(
+        conn: &mut SqliteConnection,
+        cart_id: i64,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query("DELETE FROM stock_holds WHERE cart_id = ?")
+            .bind(cart_id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// What to put in the cart. Only the product and variant are taken from
+    /// the client; names and prices always come from the database. The
+    /// variant can be given by id, or by its attributes.
+    #[derive(Deserialize)]
+    struct CartAddition {
+        product: i64,
+        #[serde(default)]
+        var_id: Option<u32>,
+        #[serde(default)]
+        attributes: BTreeMap<String, String>,
+        #[serde(default = "CartAddition::one")]
+        quantity: u32,
+    }
+
+    impl CartAddition {
+        fn one() -> u32 {
+            1
+        }
+    }
+
+    /// A line of the cart, priced as it would be ordered now.
+    #[derive(Serialize)]
+    struct CartLine {
+        id: i64,
+        product: i64,
+        var_id: Option<u32>,
+        name: String,
+        /// The variant's attribute values for display, e.g. `Small White`, or
+        /// `N/A` for a product without variants.
+        variant: String,
+        attributes: BTreeMap<String, String>,
+        quantity: u32,
+        /// The price of one: the variant's if it has its own, otherwise the product's.
+        price: Money,
+        /// The most of it this cart can have, counting what's in stock and
+        /// not held by other carts.
+        available: u32,
+    }
+
+    /// The lines of a cart that can still be bought, in the order they were added.
+    async fn cart_lines(
+        conn: &mut SqliteConnection,
+        cart_id: i64,
+    ) -> Result<Vec<CartLine>, rocket_db_pools::sqlx::Error> {
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT i.item_id, i.product_id, i.var_id, i.quantity, p.name,
+                COALESCE(v.price_cents, p.price_cents) AS price_cents
+            FROM cart_items i
+            JOIN products p ON p.product_id = i.product_id
+            LEFT JOIN product_variants v ON v.var_id = i.var_id
+            WHERE i.cart_id = ? AND p.archived_at IS NULL
+            ORDER BY i.item_id",
+        )
+        .bind(cart_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut lines = Vec::with_capacity(rows.len());
+        for row in rows {
+            let var_id: Option<u32> = row.get("var_id");
+            let attributes = match var_id {
+                Some(var_id) => variant_attributes(conn, var_id).await?,
+                None => vec![],
+            };
+            let variant = match attributes.is_empty() {
+                true => "N/A".to_string(),
+                false => attributes
+                    .iter()
+                    .map(|(_, value)| value.capitalize())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+            let product: i64 = row.get("product_id");
+            let available = available_stock(conn, product, var_id, Some(cart_id)).await?;
+            lines.push(CartLine {
+                id: row.get("item_id"),
+                product,
+                var_id,
+                name: row.get("name"),
+                variant,
+                attributes: attributes.into_iter().collect(),
+                quantity: row.get("quantity"),
+                price: Money::from_cents(row.get("price_cents")),
+                available,
+            });
+        }
+        Ok(lines)
+    }
+
+    /// How many of a product or variant a cart has.
+    async fn quantity_in_cart(
+        conn: &mut SqliteConnection,
+        cart_id: Option<i64>,
+        product_id: i64,
+        var_id: Option<u32>,
+    ) -> Result<u32, rocket_db_pools::sqlx::Error> {
+        let Some(cart_id) = cart_id else {
+            return Ok(0);
+        };
+        Ok(rocket_db_pools::sqlx::query(
+            "SELECT quantity FROM cart_items WHERE cart_id = ? AND product_id = ? AND var_id IS ?",
+        )
+        .bind(cart_id)
+        .bind(product_id)
+        .bind(var_id)
+        .fetch_optional(conn)
+        .await?
+        .map_or(0, |row| row.get("quantity")))
+    }
+
+    /// Sets how many of a product or variant a cart has, removing the line
+    /// at 0. Any stock the cart held in checkout is let go.
+    async fn set_cart_quantity(
+        conn: &mut SqliteConnection,
+        cart_id: i64,
+        product_id: i64,
+        var_id: Option<u32>,
+        quantity: u32,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        release_holds(conn, cart_id).await?;
+        if quantity == 0 {
+            rocket_db_pools::sqlx::query("DELETE FROM cart_items WHERE cart_id = ? AND product_id = ? AND var_id IS ?")
+                .bind(cart_id)
+                .bind(product_id)
+                .bind(var_id)
+                .execute(conn)
+                .await?;
+            return Ok(());
+        }
+        let updated = rocket_db_pools::sqlx::query(
+            "UPDATE cart_items SET quantity = ? WHERE cart_id = ? AND product_id = ? AND var_id IS ?",
+        )
+        .bind(quantity)
+        .bind(cart_id)
+        .bind(product_id)
+        .bind(var_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO cart_items (cart_id, product_id, var_id, quantity, added_at) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(cart_id)
+            .bind(product_id)
+            .bind(var_id)
+            .bind(quantity)
+            .bind(timestamp(Utc::now()))
+            .execute(conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Adds to the visitor's cart, starting one if needed, and returns how
+    /// many items it now holds. Asking for more than is left is a `409` with
+    /// the number that is.
+    #[allow(private_interfaces)]
+    #[post("/addcart", data = "<item>")]
+    pub async fn add_cart(
+        pot: &CookieJar<'_>,
+        item: Json<CartAddition>,
+        config: &State<CartConfig>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<u32>, CartError> {
+        if item.quantity == 0 {
+            return Err(failure(Status::BadRequest, "Quantity must be at least 1.").into());
+        }
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let archived_at: Option<String> =
+            rocket_db_pools::sqlx::query("SELECT archived_at FROM products WHERE product_id = ?")
+                .bind(item.product)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(db_error)?
+                .ok_or_else(|| failure(Status::NotFound, "Product not found."))?
+                .get("archived_at");
+        if archived_at.is_some() {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Product {} is no longer sold.", item.product),
+            )
+            .into());
+        }
+        let variants = product_variant_attributes(&mut tx, item.product)
+            .await
+            .map_err(db_error)?;
+        let var_id = match item.var_id {
+            Some(var_id) if variants.iter().any(|(id, _)| *id == var_id) => Some(var_id),
+            Some(var_id) => {
+                return Err(failure(
+                    Status::BadRequest,
+                    format!("Variant {var_id} isn't a variant of product {}.", item.product),
+                )
+                .into())
+            }
+            None => pick_variant(&variants, &item.attributes)?,
+        };
+
+        // Checked before a cart is started, so a refused first item doesn't leave one
+        let cart_id = current_cart(&mut tx, pot).await.map_err(db_error)?;
+        let wanted = quantity_in_cart(&mut tx, cart_id, item.product, var_id)
+            .await
+            .map_err(db_error)?
+            .saturating_add(item.quantity);
+        check_stock(&mut tx, item.product, var_id, cart_id, wanted).await?;
+
+        let cart_id = open_cart(&mut tx, pot, config).await.map_err(db_error)?;
+        set_cart_quantity(&mut tx, cart_id, item.product, var_id, wanted)
+            .await
+            .map_err(db_error)?;
+        let lines = cart_lines(&mut tx, cart_id).await.map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(lines.iter().map(|line| line.quantity).sum()))
+    }
+
+    /// Sets the quantity of a line of the cart, removing it at 0. Returns the
+    /// cart as it now is.
+    #[allow(private_interfaces)]
+    #[post("/updatecart?<item>&<quantity>")]
+    pub async fn update_cart(
+        pot: &CookieJar<'_>,
+        item: i64,
+        quantity: u32,
+        config: &State<CartConfig>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<CartLine>>, CartError> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let cart_id = current_cart(&mut tx, pot)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Item not found in the cart."))?;
+        let line = rocket_db_pools::sqlx::query("SELECT product_id, var_id FROM cart_items WHERE item_id = ? AND cart_id = ?")
+            .bind(item)
+            .bind(cart_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Item not found in the cart."))?;
+        let product_id: i64 = line.get("product_id");
+        let var_id: Option<u32> = line.get("var_id");
+
+        check_stock(&mut tx, product_id, var_id, Some(cart_id), quantity).await?;
+        open_cart(&mut tx, pot, config).await.map_err(db_error)?;
+        set_cart_quantity(&mut tx, cart_id, product_id, var_id, quantity)
+            .await
+            .map_err(db_error)?;
+        let lines = cart_lines(&mut tx, cart_id).await.map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(lines))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/getcart")]
+    pub async fn get_cart(
+        pot: &CookieJar<'_>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<CartLine>>, Custom<Json<ResponseData>>> {
+        let Some(cart_id) = current_cart(&mut db, pot).await.map_err(db_error)? else {
+            return Ok(Json(vec![]));
+        };
+        Ok(Json(cart_lines(&mut db, cart_id).await.map_err(db_error)?))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/get_cart_count")]
+    pub async fn get_cart_count(
+        pot: &CookieJar<'_>,
+        db: Connection<RoboDatabase>,
+    ) -> Result<Json<u32>, Custom<Json<ResponseData>>> {
+        let Json(lines) = get_cart(pot, db).await?;
+        Ok(Json(lines.iter().map(|line| line.quantity).sum()))
+    }
+
+    /// Removes a line from the cart, by its `item` id, or by the product name
+    /// and variant shown for it. Returns how many lines are left.
+    #[allow(private_interfaces)]
+    #[post("/removecart?<item>&<name>&<variant>")]
+    pub async fn remove_cart(
+        pot: &CookieJar<'_>,
+        item: Option<i64>,
+        name: Option<String>,
+        variant: Option<String>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<usize>, Custom<Json<ResponseData>>> {
+        let Some(cart_id) = current_cart(&mut db, pot).await.map_err(db_error)? else {
+            return Ok(Json(0));
+        };
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let removed: Vec<i64> = cart_lines(&mut tx, cart_id)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .filter(|line| match (item, &name, &variant) {
+                (Some(item), _, _) => line.id == item,
+                (None, Some(name), Some(variant)) => &line.name == name && &line.variant == variant,
+                _ => false,
+            })
+            .map(|line| line.id)
+            .collect();
+        if !removed.is_empty() {
+            release_holds(&mut tx, cart_id).await.map_err(db_error)?;
+        }
+        for item_id in removed {
+            rocket_db_pools::sqlx::query("DELETE FROM cart_items WHERE item_id = ? AND cart_id = ?")
+                .bind(item_id)
+                .bind(cart_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        let left = cart_lines(&mut tx, cart_id).await.map_err(db_error)?.len();
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(left))
+    }
+
+    /// Empties the cart and forgets it. Returns 1 for success.
+    #[allow(private_interfaces)]
+    #[post("/clearcart")]
+    pub async fn clear_cart(
+        pot: &CookieJar<'_>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<usize>, Custom<Json<ResponseData>>> {
+        if let Some(cart_id) = current_cart(&mut db, pot).await.map_err(db_error)? {
+            // Its items and holds go with it
+            rocket_db_pools::sqlx::query("DELETE FROM carts WHERE cart_id = ?")
+                .bind(cart_id)
+                .execute(&mut **db)
+                .await
+                .map_err(db_error)?;
+        }
+        pot.remove_private(Cookie::from(CART_COOKIE));
+
+        Ok(Json(1))
+    }
+
+    /// What checkout holds for the cart until `expires_at`, and what it costs.
+    #[derive(Serialize)]
+    struct CartHold {
+        expires_at: String,
+        total: Money,
+        items: Vec<CartLine>,
+    }
+
+    /// Holds the cart's stock for `cart_hold_minutes` while its owner checks
+    /// out, replacing any earlier hold. Refused with a `409` if there's no
+    /// longer enough of something in it.
+    #[allow(private_interfaces)]
+    #[post("/checkout/hold")]
+    pub async fn hold_cart(
+        pot: &CookieJar<'_>,
+        config: &State<CartConfig>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<CartHold>, CartError> {
+        let empty = || failure(Status::BadRequest, "The cart is empty.");
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let cart_id = current_cart(&mut tx, pot).await.map_err(db_error)?.ok_or_else(empty)?;
+        release_holds(&mut tx, cart_id).await.map_err(db_error)?;
+        let items = cart_lines(&mut tx, cart_id).await.map_err(db_error)?;
+        if items.is_empty() {
+            return Err(empty().into());
+        }
+        let expires_at = timestamp(Utc::now() + config.hold());
+        for line in &items {
+            check_stock(&mut tx, line.product, line.var_id, Some(cart_id), line.quantity).await?;
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO stock_holds (cart_id, product_id, var_id, quantity, expires_at) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(cart_id)
+            .bind(line.product)
+            .bind(line.var_id)
+            .bind(line.quantity)
+            .bind(&expires_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(CartHold {
+            expires_at,
+            total: items.iter().map(|line| line.price * line.quantity).sum(),
+            items,
+        }))
+    }
+
+    /// Deletes carts nobody has touched for `cart_ttl_days`, with their items.
+    async fn sweep_expired_carts(pool: &SqlitePool) -> Result<u64, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query("DELETE FROM carts WHERE expires_at <= ?")
+            .bind(timestamp(Utc::now()))
+            .execute(pool)
+            .await?
+            .rows_affected())
+    }
+
+    /// Deletes holds on stock that have lapsed. They already don't count
+    /// against what's available; this just keeps the table small.
+    async fn sweep_expired_holds(pool: &SqlitePool) -> Result<u64, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query("DELETE FROM stock_holds WHERE expires_at <= ?")
+            .bind(timestamp(Utc::now()))
+            .execute(pool)
+            .await?
+            .rows_affected())
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/get_items")]
+    pub(super) async fn get_items(
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<Product>>, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query("select * from products where archived_at is null")
+            .fetch_all(&mut **db)
+            .await
+            .map_err(db_error)?;
+        let mut rows_ret = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut product = Product::try_from(row).map_err(|e| failure(Status::InternalServerError, e))?;
+            load_product_images(&mut db, &mut product)
+                .await
+                .map_err(db_error)?;
+            rows_ret.push(product);
+        }
+        Ok(Json(rows_ret))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/additem/<name>")]
+    pub(super) async fn add_item(
+        name: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<&'static str, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let slug = unique_slug(&mut tx, &slugify(name), None)
+            .await
+            .map_err(db_error)?;
+        let row = rocket_db_pools::sqlx::query(
+            "insert into products (name, price_cents, quantity, slug) values ($1, $2 ,$3, $4) returning *",
+        )
+        .bind(name)
+        .bind(1000)
+        .bind(100)
+        .bind(slug)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        let product_id: i64 = row.get("product_id");
+        audit(&mut tx, &admin, "product.create", &product_id.to_string(), None, Some(row_json(&row)))
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+        Ok("Added")
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/add_product", data = "<new_product>")]
+    pub(super) async fn add_product(
+        new_product: Json<Product>,
+        store: &State<ImageStore>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<i32>, Custom<Json<ResponseData>>> {
+        let item_to_add = new_product.into_inner();
+        if item_to_add.price < Money::default() {
+            return Err(failure(Status::BadRequest, "Price can't be negative."));
+        }
+        let image = decode_image(item_to_add.image.as_deref())?;
+        let image_alt = image.as_ref().map(|_| clean_alt(item_to_add.image_alt.as_deref())).transpose()?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let slug = unique_slug(&mut tx, &slugify(&item_to_add.name), None)
+            .await
+            .map_err(db_error)?;
+        let image_file = match image {
+            Some(bytes) => Some(save_image(&mut tx, store, &bytes).await?),
+            None => None,
+        };
+
+        // Insert the new product into the database without specifying the ID (let the DB auto-generate it)
+        let result = rocket_db_pools::sqlx::query(
+            "insert into products (name, desc, price_cents, quantity, slug) values ($1, $2, $3, $4, $5)
+            returning *",
+        )
+        .bind(&item_to_add.name)
+        .bind(&item_to_add.desc)
+        .bind(item_to_add.price.cents())
+        .bind(item_to_add.quantity)
+        .bind(slug)
+        .fetch_one(&mut *tx)
+        .await;
+
+        match result {
+            Ok(row) => {
+                // Extract the generated ID from the result
+                let product_id: i32 = row.try_get("product_id").map_err(|e| {
+                    failure(Status::InternalServerError, format!("Error extracting ID: {e}"))
+                })?;
+
+                if let (Some(file), Some(alt)) = (image_file, image_alt) {
+                    add_to_gallery(&mut tx, product_id.into(), None, &file, &alt, true)
+                        .await
+                        .map_err(db_error)?;
+                }
+
+                audit(&mut tx, &admin, "product.create", &product_id.to_string(), None, Some(row_json(&row)))
+                    .await
+                    .map_err(db_error)?;
+                tx.commit().await.map_err(db_error)?;
+
+                // Return the ID as JSON
+                Ok(Json(product_id))
+            }
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    /// Deprecated: use `PUT /api/products/<id>`, which can also rename.
+    #[allow(private_interfaces)]
+    #[post("/update_product", data = "<updated_product>")]
+    pub(super) async fn update_product(
+        updated_product: Json<Product>, // Handle the updated form data
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Deprecated<Result<Json<i32>, Custom<Json<ResponseData>>>> { // Return the product_id
+        let product = updated_product.into_inner();
+        let product_id = match product_id_by_name(&mut db, &product.name).await {
+            Ok(product_id) => product_id,
+            Err(error) => return Deprecated::unlinked(Err(error)),
+        };
+
+        let update = ProductUpdate {
+            name: product.name,
+            desc: product.desc,
+            price: product.price,
+            quantity: product.quantity,
+            slug: None,
+        };
+        let result = update_product_by_id(&mut db, &admin, product_id, update)
+            .await
+            .map(|_| Json(product_id as i32));
+        Deprecated::new(result, format!("/api/products/{product_id}"))
+    }
+
+    /// Deprecated: use `DELETE /api/products/<id>`.
+    #[allow(private_interfaces)]
+    #[delete("/remove_product/<product_name>")]
+    pub(super) async fn remove_product(
+        product_name: &str, // Parameter type still as String
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Deprecated<Result<Json<String>, Custom<Json<ResponseData>>>> {
+        let product_id = match product_id_by_name(&mut db, product_name).await {
+            Ok(product_id) => product_id,
+            Err(error) => return Deprecated::unlinked(Err(error)),
+        };
+        let result = delete_product_by_id(&mut db, &admin, product_id)
+            .await
+            .map(|_| Json("Product and associated variants removed successfully.".to_string()));
+        Deprecated::new(result, format!("/api/products/{product_id}"))
+    }
+
+    /// Deprecated: use `GET /api/products/<id>/variants`.
+    #[allow(private_interfaces)]
+    #[get("/get_product_variants?<name>")]
+    pub(super) async fn get_product_variants(
+        name: String,
+        mut db: Connection<RoboDatabase>,
+    ) -> Deprecated<Result<Json<Vec<ProductVariant>>, Custom<Json<ResponseData>>>> {
+        let product_id = match product_id_by_name(&mut db, &name).await {
+            Ok(product_id) => product_id,
+            Err(error) => return Deprecated::unlinked(Err(error)),
+        };
+        let result = product_variants(&mut db, product_id).await.map(Json);
+        Deprecated::new(result, format!("/api/products/{product_id}/variants"))
+    }
+
+    /// A response from a route that is only kept for old clients, including
+    /// its errors. Adds a `Deprecation` header and a `Link` to the route that
+    /// replaces it.
+    pub(super) struct Deprecated<R> {
+        response: R,
+        successor: Option<String>,
+    }
+
+    impl<R> Deprecated<R> {
+        fn new(response: R, successor: String) -> Self {
+            Deprecated { response, successor: Some(successor) }
+        }
+
+        /// For a failure before the replacement is known, e.g. a product name
+        /// that doesn't resolve to an id.
+        fn unlinked(response: R) -> Self {
+            Deprecated { response, successor: None }
+        }
+    }
+
+    impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Deprecated<R> {
+        fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+            let mut response = Response::build_from(self.response.respond_to(req)?);
+            response.raw_header("Deprecation", "true");
+            if let Some(successor) = self.successor {
+                response.raw_header("Link", format!("<{successor}>; rel=\"successor-version\""));
+            }
+            response.ok()
+        }
+    }
+
+    /// Turns a product name into a URL-friendly slug, e.g. `T Shirt` becomes `t-shirt`.
+    fn slugify(name: &str) -> String {
+        let mut slug = String::new();
+        for c in name.replace('&', " and ").chars() {
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if c != '\'' && !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        let slug = slug.trim_end_matches('-');
+
+        // Slugs share a route with numeric ids, so each needs at least one letter
+        if slug.is_empty() {
+            "product".into()
+        } else if !slug.chars().any(|c| c.is_ascii_alphabetic()) {
+            format!("product-{slug}")
+        } else {
+            slug.into()
+        }
+    }
+
+    /// Finds a slug based on `slug` that no other product uses, adding `-2`,
+    /// `-3` and so on when needed.
+    async fn unique_slug(
+        conn: &mut SqliteConnection,
+        slug: &str,
+        product_id: Option<i64>,
+    ) -> Result<String, rocket_db_pools::sqlx::Error> {
+        let mut candidate = slug.to_string();
+        for n in 2.. {
+            let taken = rocket_db_pools::sqlx::query(
+                "SELECT 1 FROM products WHERE slug = ? AND product_id IS NOT ?",
+            )
+            .bind(&candidate)
+            .bind(product_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .is_some();
+            if !taken {
+                break;
+            }
+            candidate = format!("{slug}-{n}");
+        }
+        Ok(candidate)
+    }
+
+    /// Looks up a product id by name for the old name-based routes. Fails if
+    /// the name is missing or shared by several products.
+    async fn product_id_by_name(
+        db: &mut Connection<RoboDatabase>,
+        name: &str,
+    ) -> Result<i64, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query("SELECT product_id FROM products WHERE name = ?")
+            .bind(name)
+            .fetch_all(&mut ***db)
+            .await
+            .map_err(db_error)?;
+        match rows.as_slice() {
+            [row] => Ok(row.get("product_id")),
+            [] => Err(failure(Status::NotFound, format!("No product named `{name}`."))),
+            _ => Err(failure(
+                Status::Conflict,
+                format!("Several products are named `{name}`. Use /api/products/<id> instead."),
+            )),
+        }
+    }
+
+    async fn product_by_id(
+        db: &mut Connection<RoboDatabase>,
+        product_id: i64,
+    ) -> Result<Product, Custom<Json<ResponseData>>> {
+        let mut product: Product = rocket_db_pools::sqlx::query("SELECT * FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_optional(&mut ***db)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Product not found."))?
+            .try_into()
+            .map_err(|e| failure(Status::InternalServerError, e))?;
+        load_product_images(db, &mut product).await.map_err(db_error)?;
+        Ok(product)
+    }
+
+    async fn product_variants(
+        db: &mut Connection<RoboDatabase>,
+        product_id: i64,
+    ) -> Result<Vec<ProductVariant>, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query("select * from product_variants where product_id = $1")
+            .bind(product_id)
+            .fetch_all(&mut ***db)
+            .await
+            .map_err(db_error)?;
+
+        let mut variants = vec![];
+        for row in rows {
+            let mut variant = ProductVariant::try_from(row)
+                .map_err(|e| failure(Status::InternalServerError, e))?;
+            load_variant_attributes(db, &mut variant)
+                .await
+                .map_err(db_error)?;
+            load_variant_images(db, &mut variant)
+                .await
+                .map_err(db_error)?;
+            variants.push(variant);
+        }
+        Ok(variants)
+    }
+
+    /// The editable fields of a product. Changing `name` renames the product;
+    /// its slug follows the new name unless `slug` is given.
+    #[derive(Deserialize)]
+    struct ProductUpdate {
+        name: String,
+        desc: String,
+        price: Money,
+        quantity: u32,
+        slug: Option<String>,
+    }
+
+    async fn update_product_by_id(
+        db: &mut Connection<RoboDatabase>,
+        admin: &AdminSession,
+        product_id: i64,
+        update: ProductUpdate,
+    ) -> Result<Product, Custom<Json<ResponseData>>> {
+        let name = update.name.trim();
+        if name.is_empty() {
+            return Err(failure(Status::BadRequest, "Product name can't be empty."));
+        }
+        if update.price < Money::default() {
+            return Err(failure(Status::BadRequest, "Price can't be negative."));
+        }
+
+        let mut tx = (&mut ***db).begin().await.map_err(db_error)?;
+
+        let before = rocket_db_pools::sqlx::query("SELECT * FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Product not found."))?;
+        let old_name: String = before.get("name");
+        let old_slug: Option<String> = before.get("slug");
+
+        let slug = match &update.slug {
+            // An explicit slug must be free, rather than quietly changed
+            Some(requested) => {
+                let slug = slugify(requested);
+                if unique_slug(&mut tx, &slug, Some(product_id)).await.map_err(db_error)? != slug {
+                    return Err(failure(
+                        Status::Conflict,
+                        format!("Another product already uses the slug `{slug}`."),
+                    ));
+                }
+                slug
+            }
+            None => match old_slug {
+                Some(slug) if old_name == name => slug,
+                _ => unique_slug(&mut tx, &slugify(name), Some(product_id))
+                    .await
+                    .map_err(db_error)?,
+            },
+        };
+
+        let after = rocket_db_pools::sqlx::query(
+            "UPDATE products SET name = ?, \"desc\" = ?, price_cents = ?, quantity = ?, slug = ?
+            WHERE product_id = ? RETURNING *",
+        )
+        .bind(name)
+        .bind(&update.desc)
+        .bind(update.price.cents())
+        .bind(update.quantity)
+        .bind(&slug)
+        .bind(product_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        audit(
+            &mut tx,
+            admin,
+            "product.update",
+            &product_id.to_string(),
+            Some(row_json(&before)),
+            Some(row_json(&after)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        product_by_id(db, product_id).await
+    }
+
+    /// Deletes a product together with its variants. Products that appear in
+    /// past orders can't be deleted; they should be archived instead.
+    async fn delete_product_by_id(
+        db: &mut Connection<RoboDatabase>,
+        admin: &AdminSession,
+        product_id: i64,
+    ) -> Result<(), Custom<Json<ResponseData>>> {
+        let mut tx = (&mut ***db).begin().await.map_err(db_error)?;
+
+        let mut before = rocket_db_pools::sqlx::query("SELECT * FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .map(|row| row_json(&row))
+            .ok_or_else(|| failure(Status::NotFound, "Product not found."))?;
+
+        // The foreign keys would refuse anyway, but with an unhelpful error
+        let ordered = rocket_db_pools::sqlx::query("SELECT 1 FROM ordered_products WHERE product_id = ? LIMIT 1")
+            .bind(product_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .is_some();
+        if ordered {
+            return Err(failure(
+                Status::Conflict,
+                format!("Product {product_id} appears in past orders, so it can't be deleted. Archive it instead."),
+            ));
+        }
+
+        // Record the variants that go with it too
+        let variants = rocket_db_pools::sqlx::query(
+            "DELETE FROM product_variants WHERE product_id = ? RETURNING *",
+        )
+        .bind(product_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        before["variants"] = variants.iter().map(row_json).collect();
+
+        rocket_db_pools::sqlx::query("DELETE FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        audit(&mut tx, admin, "product.delete", &product_id.to_string(), Some(before), None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)
+    }
+
+    /// Archives or restores a product. Setting the state it's already in
+    /// changes nothing and isn't audited.
+    async fn set_product_archived(
+        db: &mut Connection<RoboDatabase>,
+        admin: &AdminSession,
+        product_id: i64,
+        archived: bool,
+    ) -> Result<Product, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut ***db).begin().await.map_err(db_error)?;
+
+        let before = rocket_db_pools::sqlx::query("SELECT * FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Product not found."))?;
+        let archived_at: Option<String> = before.get("archived_at");
+        if archived_at.is_some() == archived {
+            drop(tx);
+            return product_by_id(db, product_id).await;
+        }
+
+        let after = rocket_db_pools::sqlx::query(
+            "UPDATE products SET archived_at = ? WHERE product_id = ? RETURNING *",
+        )
+        .bind(archived.then(|| timestamp(Utc::now())))
+        .bind(product_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        let action = if archived { "product.archive" } else { "product.unarchive" };
+        audit(
+            &mut tx,
+            admin,
+            action,
+            &product_id.to_string(),
+            Some(row_json(&before)),
+            Some(row_json(&after)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        product_by_id(db, product_id).await
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/products/<product_id>")]
+    pub(super) async fn get_product(
+        product_id: i64,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Product>, Custom<Json<ResponseData>>> {
+        Ok(Json(product_by_id(&mut db, product_id).await?))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/products/<slug>", rank = 2)]
+    pub(super) async fn get_product_by_slug(
+        slug: &str,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Product>, Custom<Json<ResponseData>>> {
+        let product_id: i64 =
+            rocket_db_pools::sqlx::query("SELECT product_id FROM products WHERE slug = ?")
+                .bind(slug)
+                .fetch_optional(&mut **db)
+                .await
+                .map_err(db_error)?
+                .ok_or_else(|| failure(Status::NotFound, "Product not found."))?
+                .get("product_id");
+        Ok(Json(product_by_id(&mut db, product_id).await?))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/products/<product_id>/variants")]
+    pub(super) async fn get_variants_for_product(
+        product_id: i64,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<ProductVariant>>, Custom<Json<ResponseData>>> {
+        // Tell "no such product" apart from "no variants"
+        product_by_id(&mut db, product_id).await?;
+        Ok(Json(product_variants(&mut db, product_id).await?))
+    }
+
+    #[allow(private_interfaces)]
+    #[put("/products/<product_id>", data = "<update>")]
+    pub(super) async fn put_product(
+        product_id: i64,
+        update: Json<ProductUpdate>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Product>, Custom<Json<ResponseData>>> {
+        Ok(Json(
+            update_product_by_id(&mut db, &admin, product_id, update.into_inner()).await?,
+        ))
+    }
+
+    #[allow(private_interfaces)]
+    #[delete("/products/<product_id>")]
+    pub(super) async fn delete_product(
+        product_id: i64,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        delete_product_by_id(&mut db, &admin, product_id).await?;
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Product and associated variants removed successfully.".into(),
+        }))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/products/<product_id>/archive")]
+    pub(super) async fn archive_product(
+        product_id: i64,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Product>, Custom<Json<ResponseData>>> {
+        Ok(Json(set_product_archived(&mut db, &admin, product_id, true).await?))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/products/<product_id>/unarchive")]
+    pub(super) async fn unarchive_product(
+        product_id: i64,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Product>, Custom<Json<ResponseData>>> {
+        Ok(Json(set_product_archived(&mut db, &admin, product_id, false).await?))
+    }
+
+    /// Archived products, newest first, so they can be found and restored.
+    #[allow(private_interfaces)]
+    #[get("/archived_products")]
+    pub(super) async fn get_archived_products(
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Vec<Product>>, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query(
+            "SELECT * FROM products WHERE archived_at IS NOT NULL ORDER BY archived_at DESC",
+        )
+        .fetch_all(&mut **db)
+        .await
+        .map_err(db_error)?;
+        let mut products = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut product = Product::try_from(row).map_err(|e| failure(Status::InternalServerError, e))?;
+            load_product_images(&mut db, &mut product).await.map_err(db_error)?;
+            products.push(product);
+        }
+        Ok(Json(products))
+    }
+
+    /// An admin-defined attribute, such as Size or Voltage, with the values a
+    /// variant may use.
+    #[derive(Serialize, Deserialize)]
+    struct AttributeType {
+        #[serde(default, skip_deserializing)]
+        id: i64,
+        name: String,
+        #[serde(default)]
+        values: Vec<String>,
+    }
+
+    async fn attribute_types(
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<AttributeType>, rocket_db_pools::sqlx::Error> {
+        let mut types: Vec<AttributeType> =
+            rocket_db_pools::sqlx::query("SELECT type_id, name FROM attribute_types ORDER BY type_id")
+                .fetch_all(&mut *conn)
+                .await?
+                .iter()
+                .map(|row| AttributeType {
+                    id: row.get("type_id"),
+                    name: row.get("name"),
+                    values: vec![],
+                })
+                .collect();
+        let values = rocket_db_pools::sqlx::query("SELECT type_id, value FROM attribute_values ORDER BY value_id")
+            .fetch_all(&mut *conn)
+            .await?;
+        for row in values {
+            let type_id: i64 = row.get("type_id");
+            if let Some(attribute) = types.iter_mut().find(|t| t.id == type_id) {
+                attribute.values.push(row.get("value"));
+            }
+        }
+        Ok(types)
+    }
+
+    async fn attribute_type(
+        conn: &mut SqliteConnection,
+        type_id: i64,
+    ) -> Result<AttributeType, Custom<Json<ResponseData>>> {
+        attribute_types(conn)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .find(|attribute| attribute.id == type_id)
+            .ok_or_else(|| failure(Status::NotFound, "Attribute not found."))
+    }
+
+    /// Trims new attribute values, rejecting blank or repeated ones. `existing`
+    /// values are rejected too.
+    fn clean_attribute_values(
+        values: &[String],
+        existing: &[String],
+    ) -> Result<Vec<String>, Custom<Json<ResponseData>>> {
+        let mut cleaned: Vec<String> = vec![];
+        let mut rejected = vec![];
+        for value in values {
+            let value = value.trim();
+            let seen = |v: &String| v.eq_ignore_ascii_case(value);
+            if value.is_empty() {
+                rejected.push("a blank value".to_string());
+            } else if existing.iter().any(seen) {
+                rejected.push(format!("`{value}` is already allowed"));
+            } else if cleaned.iter().any(seen) {
+                rejected.push(format!("`{value}` is listed twice"));
+            } else {
+                cleaned.push(value.to_string());
+            }
+        }
+        if !rejected.is_empty() {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Rejected values: {}.", rejected.join("; ")),
+            ));
+        }
+        Ok(cleaned)
+    }
+
+    /// Looks up the `(type_id, value_id)` of each attribute of a variant,
+    /// listing everything that isn't a known attribute and allowed value.
+    async fn resolve_attributes(
+        conn: &mut SqliteConnection,
+        attributes: &BTreeMap<String, String>,
+    ) -> Result<Vec<(i64, i64)>, Custom<Json<ResponseData>>> {
+        let mut resolved: Vec<(i64, i64)> = vec![];
+        let mut rejected = vec![];
+        for (name, value) in attributes {
+            let row = rocket_db_pools::sqlx::query(
+                "SELECT t.type_id, v.value_id FROM attribute_types t
+                LEFT JOIN attribute_values v ON v.type_id = t.type_id AND v.value = ?
+                WHERE t.name = ?",
+            )
+            .bind(value.trim())
+            .bind(name.trim())
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(db_error)?;
+
+            let Some(row) = row else {
+                rejected.push(format!("`{name}` is not an attribute"));
+                continue;
+            };
+            let type_id: i64 = row.get("type_id");
+            match row.get::<Option<i64>, _>("value_id") {
+                // Names match case-insensitively, so `size` and `Size` collide
+                _ if resolved.iter().any(|(t, _)| *t == type_id) => {
+                    rejected.push(format!("`{name}` is given more than once"))
+                }
+                Some(value_id) => resolved.push((type_id, value_id)),
+                None => rejected.push(format!("`{value}` is not an allowed {name}")),
+            }
+        }
+        if !rejected.is_empty() {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Rejected attributes: {}.", rejected.join("; ")),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// Decodes an image sent base64-encoded in a JSON body.
+    fn decode_image(image: Option<&str>) -> Result<Option<Vec<u8>>, Custom<Json<ResponseData>>> {
+        image
+            .filter(|image| !image.is_empty())
+            .map(|image| {
+                STANDARD
+                    .decode(image)
+                    .map_err(|_| failure(Status::BadRequest, "`image` is not valid base64."))
+            })
+            .transpose()
+    }
+
+    /// A variant's attributes as `(name, value)`, in attribute order.
+    async fn variant_attributes(
+        conn: &mut SqliteConnection,
+        var_id: u32,
+    ) -> Result<Vec<(String, String)>, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query(
+            "SELECT t.name, v.value FROM variant_attributes a
+            JOIN attribute_types t ON t.type_id = a.type_id
+            JOIN attribute_values v ON v.value_id = a.value_id
+            WHERE a.var_id = ? ORDER BY t.type_id",
+        )
+        .bind(var_id)
+        .fetch_all(conn)
+        .await?
+        .iter()
+        .map(|row| (row.get("name"), row.get("value")))
+        .collect())
+    }
+
+    async fn load_variant_attributes(
+        conn: &mut SqliteConnection,
+        variant: &mut ProductVariant,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        let Some(var_id) = variant.varid else {
+            return Ok(());
+        };
+        let attributes = variant_attributes(conn, var_id).await?;
+        variant.tag_name = attributes
+            .iter()
+            .map(|(_, value)| value.capitalize())
+            .collect::<Vec<_>>()
+            .join(" ");
+        variant.attributes = Some(attributes.into_iter().collect());
+        Ok(())
+    }
+
+    /// A variant row together with its attributes, for the audit log.
+    async fn variant_json(
+        conn: &mut SqliteConnection,
+        var_id: u32,
+    ) -> Result<Option<Value>, rocket_db_pools::sqlx::Error> {
+        let row = rocket_db_pools::sqlx::query("SELECT * FROM product_variants WHERE var_id = ?")
+            .bind(var_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut json = row_json(&row);
+        json["attributes"] = variant_attributes(conn, var_id)
+            .await?
+            .into_iter()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        Ok(Some(json))
+    }
+
+    /// Checks a variant's price override and SKU before they're written.
+    /// Returns the SKU trimmed, or `None` if it's blank.
+    async fn variant_sku(
+        conn: &mut SqliteConnection,
+        variant: &ProductVariant,
+        except: Option<u32>,
+    ) -> Result<Option<String>, Custom<Json<ResponseData>>> {
+        if variant.price.is_some_and(|price| price < Money::default()) {
+            return Err(failure(Status::BadRequest, "Price can't be negative."));
+        }
+        let Some(sku) = variant.sku.as_deref().map(str::trim).filter(|sku| !sku.is_empty()) else {
+            return Ok(None);
+        };
+        let owner = rocket_db_pools::sqlx::query("SELECT var_id FROM product_variants WHERE sku = ? AND var_id IS NOT ?")
+            .bind(sku)
+            .bind(except)
+            .fetch_optional(conn)
+            .await
+            .map_err(db_error)?;
+        if let Some(owner) = owner {
+            let owner: i64 = owner.get("var_id");
+            return Err(failure(
+                Status::Conflict,
+                format!("Variant {owner} already uses the SKU `{sku}`."),
+            ));
+        }
+        Ok(Some(sku.to_string()))
+    }
+
+    /// Finds a variant of the product, other than `except`, with exactly these
+    /// attribute values. An empty list finds a variant without attributes.
+    async fn matching_variant(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        attributes: &[(i64, i64)],
+        except: Option<u32>,
+    ) -> Result<Option<i64>, rocket_db_pools::sqlx::Error> {
+        // A variant has at most one value per type, so it has exactly these
+        // attributes when it has as many as wanted and each one is wanted
+        let wanted = serde_json::json!(attributes).to_string();
+        Ok(rocket_db_pools::sqlx::query(
+            "SELECT p.var_id FROM product_variants p
+            LEFT JOIN variant_attributes a ON a.var_id = p.var_id
+            WHERE p.product_id = ? AND p.var_id IS NOT ?
+            GROUP BY p.var_id
+            HAVING COUNT(a.var_id) = ? AND TOTAL((a.type_id, a.value_id) IN (
+                SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]') FROM json_each(?)
+            )) = ?",
+        )
+        .bind(product_id)
+        .bind(except)
+        .bind(attributes.len() as i64)
+        .bind(wanted)
+        .bind(attributes.len() as f64)
+        .fetch_optional(conn)
+        .await?
+        .map(|row| row.get("var_id")))
+    }
+
+    async fn set_variant_attributes(
+        conn: &mut SqliteConnection,
+        var_id: u32,
+        attributes: &[(i64, i64)],
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query("DELETE FROM variant_attributes WHERE var_id = ?")
+            .bind(var_id)
+            .execute(&mut *conn)
+            .await?;
+        for (type_id, value_id) in attributes {
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO variant_attributes (var_id, type_id, value_id) VALUES (?, ?, ?)",
+            )
+            .bind(var_id)
+            .bind(type_id)
+            .bind(value_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/attributes")]
+    pub(super) async fn get_attributes(
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<AttributeType>>, Custom<Json<ResponseData>>> {
+        attribute_types(&mut db)
+            .await
+            .map(Json)
+            .map_err(db_error)
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/attributes", data = "<attribute>")]
+    pub(super) async fn create_attribute(
+        attribute: Json<AttributeType>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<AttributeType>, Custom<Json<ResponseData>>> {
+        let name = attribute.name.trim();
+        if name.is_empty() {
+            return Err(failure(Status::BadRequest, "Attribute name can't be empty."));
+        }
+        let values = clean_attribute_values(&attribute.values, &[])?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        let taken = rocket_db_pools::sqlx::query("SELECT 1 FROM attribute_types WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .is_some();
+        if taken {
+            return Err(failure(
+                Status::Conflict,
+                format!("There is already an attribute named `{name}`."),
+            ));
+        }
+
+        let type_id: i64 = rocket_db_pools::sqlx::query(
+            "INSERT INTO attribute_types (name) VALUES (?) RETURNING type_id",
+        )
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .get("type_id");
+        for value in &values {
+            rocket_db_pools::sqlx::query("INSERT INTO attribute_values (type_id, value) VALUES (?, ?)")
+                .bind(type_id)
+                .bind(value)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+
+        let created = attribute_type(&mut tx, type_id).await?;
+        audit(
+            &mut tx,
+            &admin,
+            "attribute.create",
+            &type_id.to_string(),
+            None,
+            Some(serde_json::json!(created)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(created))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/attributes/<type_id>/values", data = "<values>")]
+    pub(super) async fn add_attribute_values(
+        type_id: i64,
+        values: Json<Vec<String>>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<AttributeType>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        let before = attribute_type(&mut tx, type_id).await?;
+        let values = clean_attribute_values(&values, &before.values)?;
+        for value in &values {
+            rocket_db_pools::sqlx::query("INSERT INTO attribute_values (type_id, value) VALUES (?, ?)")
+                .bind(type_id)
+                .bind(value)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+
+        let after = attribute_type(&mut tx, type_id).await?;
+        audit(
+            &mut tx,
+            &admin,
+            "attribute.update",
+            &type_id.to_string(),
+            Some(serde_json::json!(before)),
+            Some(serde_json::json!(after)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(after))
+    }
+
+    /// Removes an allowed value, as long as no variant uses it.
+    #[allow(private_interfaces)]
+    #[delete("/attributes/<type_id>/values/<value>")]
+    pub(super) async fn delete_attribute_value(
+        type_id: i64,
+        value: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<AttributeType>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        let before = attribute_type(&mut tx, type_id).await?;
+        let value_id: i64 = rocket_db_pools::sqlx::query(
+            "SELECT value_id FROM attribute_values WHERE type_id = ? AND value = ?",
+        )
+        .bind(type_id)
+        .bind(value)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::NotFound, format!("`{value}` is not an allowed {}.", before.name)))?
+        .get("value_id");
+
+        let used: i64 = rocket_db_pools::sqlx::query(
+            "SELECT COUNT(*) AS n FROM variant_attributes WHERE value_id = ?",
+        )
+        .bind(value_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .get("n");
+        if used > 0 {
+            return Err(failure(
+                Status::Conflict,
+                format!("`{value}` is used by {used} variant(s), so it can't be removed."),
+            ));
+        }
+
+        rocket_db_pools::sqlx::query("DELETE FROM attribute_values WHERE value_id = ?")
+            .bind(value_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        let after = attribute_type(&mut tx, type_id).await?;
+        audit(
+            &mut tx,
+            &admin,
+            "attribute.update",
+            &type_id.to_string(),
+            Some(serde_json::json!(before)),
+            Some(serde_json::json!(after)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(after))
+    }
+
+    /// Every variant of a product with its attributes.
+    async fn product_variant_attributes(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+    ) -> Result<Vec<(u32, BTreeMap<String, String>)>, rocket_db_pools::sqlx::Error> {
+        let var_ids: Vec<u32> = rocket_db_pools::sqlx::query(
+            "SELECT var_id FROM product_variants WHERE product_id = ? ORDER BY var_id",
+        )
+        .bind(product_id)
+        .fetch_all(&mut *conn)
+        .await?
+        .iter()
+        .map(|row| row.get("var_id"))
+        .collect();
+
+        let mut variants = Vec::with_capacity(var_ids.len());
+        for var_id in var_ids {
+            let attributes = variant_attributes(conn, var_id).await?;
+            variants.push((var_id, attributes.into_iter().collect()));
+        }
+        Ok(variants)
+    }
+
+    /// Picks the variant whose attributes are exactly those in `wanted`,
+    /// comparing names and values case-insensitively. A product without
+    /// variants resolves to `None` when nothing is wanted.
+    fn pick_variant(
+        variants: &[(u32, BTreeMap<String, String>)],
+        wanted: &BTreeMap<String, String>,
+    ) -> Result<Option<u32>, Custom<Json<ResponseData>>> {
+        if variants.is_empty() {
+            return match wanted.is_empty() {
+                true => Ok(None),
+                false => Err(failure(Status::NotFound, "This product has no variants.")),
+            };
+        }
+
+        let same = |a: &str, b: &str| a.trim().eq_ignore_ascii_case(b.trim());
+        let mut rejected = vec![];
+        for (i, name) in wanted.keys().enumerate() {
+            if wanted.keys().take(i).any(|earlier| same(earlier, name)) {
+                rejected.push(format!("`{name}` is given more than once"));
+            } else if !variants.iter().any(|(_, attributes)| attributes.keys().any(|n| same(n, name))) {
+                rejected.push(format!("`{name}` is not an attribute of this product"));
+            }
+        }
+        if !rejected.is_empty() {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Rejected attributes: {}.", rejected.join("; ")),
+            ));
+        }
+
+        let has = |attributes: &BTreeMap<String, String>, name: &str, value: &str| {
+            attributes.iter().any(|(n, v)| same(n, name) && same(v, value))
+        };
+        // Variants with every wanted attribute, some of which may have more
+        let candidates: Vec<&(u32, BTreeMap<String, String>)> = variants
+            .iter()
+            .filter(|(_, attributes)| wanted.iter().all(|(name, value)| has(attributes, name, value)))
+            .collect();
+        let exact: Vec<u32> = candidates
+            .iter()
+            .filter(|(_, attributes)| attributes.len() == wanted.len())
+            .map(|(var_id, _)| *var_id)
+            .collect();
+        if let [var_id] = exact.as_slice() {
+            return Ok(Some(*var_id));
+        }
+
+        // Name the attributes that are still to be chosen
+        let mut missing: Vec<&str> = candidates
+            .iter()
+            .flat_map(|(_, attributes)| attributes.keys())
+            .filter(|name| !wanted.keys().any(|w| same(w, name)))
+            .map(String::as_str)
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        let wanted: Vec<String> = wanted.iter().map(|(name, value)| format!("{name}={value}")).collect();
+        let wanted = match wanted.is_empty() {
+            true => "no attributes".to_string(),
+            false => wanted.join(", "),
+        };
+        match candidates.len() {
+            0 => Err(failure(
+                Status::NotFound,
+                format!("No variant of this product has {wanted}."),
+            )),
+            1 => Err(failure(
+                Status::NotFound,
+                format!("No variant of this product has exactly {wanted}. Also choose: {}.", missing.join(", ")),
+            )),
+            n => Err(failure(
+                Status::Conflict,
+                format!("{n} variants match. Also choose: {}.", missing.join(", ")),
+            )),
+        }
+    }
+
+    /// Finds the variant of a product with the given attributes, e.g.
+    /// `?Size=small&Color=white`. Returns `null` for a product without variants.
+    #[allow(private_interfaces)]
+    #[get("/products/<product_id>/variant?<attributes..>")]
+    pub(super) async fn find_variant(
+        product_id: i64,
+        attributes: BTreeMap<String, String>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Option<u32>>, Custom<Json<ResponseData>>> {
+        product_by_id(&mut db, product_id).await?;
+        let variants = product_variant_attributes(&mut db, product_id)
+            .await
+            .map_err(db_error)?;
+        Ok(Json(pick_variant(&variants, &attributes)?))
+    }
+
+    /// Deprecated: use `GET /api/products/<id>/variant`, which takes the
+    /// attributes themselves rather than their display name.
+    #[allow(private_interfaces)]
+    #[get("/get_variant_id?<product_id>&<tag_name>")]
+    pub(super) async fn get_variant_id(
+        product_id: u32,
+        tag_name: String,
+        mut db: Connection<RoboDatabase>,
+    ) -> Deprecated<Result<Json<u32>, Custom<Json<ResponseData>>>> {
+        let result = variant_id_by_tag_name(&mut db, product_id, &tag_name).await.map(Json);
+        Deprecated::new(result, format!("/api/products/{product_id}/variant"))
+    }
+
+    /// Matches the display name the cart keeps, e.g. `Small White`.
+    async fn variant_id_by_tag_name(
+        db: &mut Connection<RoboDatabase>,
+        product_id: u32,
+        tag_name: &str,
+    ) -> Result<u32, Custom<Json<ResponseData>>> {
+        let rows = rocket_db_pools::sqlx::query("SELECT * FROM product_variants WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_all(&mut ***db)
+            .await
+            .map_err(db_error)?;
+
+        for row in rows {
+            let mut variant = ProductVariant::try_from(row)
+                .map_err(|e| failure(Status::InternalServerError, e))?;
+            load_variant_attributes(db, &mut variant)
+                .await
+                .map_err(db_error)?;
+            if let (Some(var_id), true) = (variant.varid, variant.tag_name.eq_ignore_ascii_case(tag_name.trim())) {
+                return Ok(var_id);
+            }
+        }
+        Err(failure(
+            Status::NotFound,
+            format!("Could not find variant for product_id {product_id} and tag_name {tag_name}"),
+        ))
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/modify_variant", data = "<variant>")]
+    pub(super) async fn modify_variant(
+        variant: Json<ProductVariant>,
+        store: &State<ImageStore>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<&'static str, Custom<Json<ResponseData>>> {
+        let var_id = variant
+            .varid
+            .ok_or_else(|| failure(Status::BadRequest, "Missing `varid`."))?;
+        let image = decode_image(variant.image.as_deref())?;
+        let image_alt = image.as_ref().map(|_| clean_alt(variant.image_alt.as_deref())).transpose()?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        let product_id: i64 = rocket_db_pools::sqlx::query("SELECT product_id FROM product_variants WHERE var_id = ?")
+            .bind(var_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Variant not found."))?
+            .get("product_id");
+        let before = variant_json(&mut tx, var_id).await.map_err(db_error)?;
+        let sku = variant_sku(&mut tx, &variant, Some(var_id)).await?;
+        let image_file = match image {
+            Some(bytes) => Some(save_image(&mut tx, store, &bytes).await?),
+            None => None,
+        };
+
+        // Leaving out the attributes keeps the current ones
+        if let Some(attributes) = &variant.attributes {
+            let attributes = resolve_attributes(&mut tx, attributes).await?;
+            if let Some(other) = matching_variant(&mut tx, product_id, &attributes, Some(var_id))
+                .await
+                .map_err(db_error)?
+            {
+                return Err(failure(
+                    Status::Conflict,
+                    format!("Variant {other} of this product already has these attributes."),
+                ));
+            }
+            set_variant_attributes(&mut tx, var_id, &attributes)
+                .await
+                .map_err(db_error)?;
+        }
+
+        rocket_db_pools::sqlx::query(
+            "UPDATE product_variants SET quantity = COALESCE(?, quantity), price_cents = ?, sku = ? WHERE var_id = ?",
+        )
+        .bind(variant.quantity)
+        .bind(variant.price.map(Money::cents))
+        .bind(sku)
+        .bind(var_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        if let (Some(file), Some(alt)) = (image_file, image_alt) {
+            add_to_gallery(&mut tx, product_id, Some(var_id), &file, &alt, true)
+                .await
+                .map_err(db_error)?;
+        }
+
+        let after = variant_json(&mut tx, var_id).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "variant.update", &var_id.to_string(), before, after)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok("ok")
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/add_variant", data = "<variant>")]
+    pub(super) async fn add_product_variant(
+        variant: Json<ProductVariant>,
+        store: &State<ImageStore>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<i32>, Custom<Json<ResponseData>>> {
+        let image = decode_image(variant.image.as_deref())?;
+        let image_alt = image.as_ref().map(|_| clean_alt(variant.image_alt.as_deref())).transpose()?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        let product_exists = rocket_db_pools::sqlx::query("SELECT 1 FROM products WHERE product_id = ?")
+            .bind(variant.product)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .is_some();
+        if !product_exists {
+            return Err(failure(Status::NotFound, "Product not found."));
+        }
+
+        let sku = variant_sku(&mut tx, &variant, None).await?;
+        let image_file = match image {
+            Some(bytes) => Some(save_image(&mut tx, store, &bytes).await?),
+            None => None,
+        };
+        let attributes = variant.attributes.clone().unwrap_or_default();
+        let attributes = resolve_attributes(&mut tx, &attributes).await?;
+        if let Some(other) = matching_variant(&mut tx, variant.product.into(), &attributes, None)
+            .await
+            .map_err(db_error)?
+        {
+            return Err(failure(
+                Status::Conflict,
+                format!("Variant {other} of this product already has these attributes."),
+            ));
+        }
+
+        // Insert into the database and return the generated ID (var_id)
+        let var_id: u32 = rocket_db_pools::sqlx::query(
+            "insert into product_variants (quantity, product_id, price_cents, sku) values (?, ?, ?, ?)
+            RETURNING var_id",
+        )
+        .bind(variant.quantity.unwrap_or(0))
+        .bind(variant.product)
+        .bind(variant.price.map(Money::cents))
+        .bind(sku)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .get("var_id");
+        set_variant_attributes(&mut tx, var_id, &attributes)
+            .await
+            .map_err(db_error)?;
+        if let (Some(file), Some(alt)) = (image_file, image_alt) {
+            add_to_gallery(&mut tx, variant.product.into(), Some(var_id), &file, &alt, true)
+                .await
+                .map_err(db_error)?;
+        }
+
+        let after = variant_json(&mut tx, var_id).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "variant.create", &var_id.to_string(), None, after)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(var_id as i32))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/get_websiteinfo")]
+    pub(super) async fn get_websiteinfo(
+        mut db: Connection<RoboDatabase>,
+    ) -> Json<Vec<Description>> {
+        let rows = rocket_db_pools::sqlx::query("select * from website_information")
+            .fetch(&mut **db)
+            .filter_map(|row| async move {
+                let row = row.ok()?;
+                Some(Description {
+                    name: row.try_get("name").ok()?,
+                    desc: row.try_get("desc").ok()?,
+                })
+            })
+            .collect()
+            .await;
+        Json(rows)
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/update_websiteinfo", data = "<info>")]
+    pub(super) async fn update_websiteinfo(
+        info: Form<WebsiteInfo>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<SiteEdit>,
+    ) -> Result<Json<Value>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = website_info_json(&mut tx).await.map_err(db_error)?;
+
+        // SQL query to update the website information in the database
+        let result = rocket_db_pools::sqlx::query(
+            "UPDATE website_information SET desc = CASE name
+                WHEN 'aboutClub1' THEN ?
+                WHEN 'aboutClub2' THEN ?
+                WHEN 'clubHistory' THEN ?
+                WHEN 'clubActivities' THEN ?
+                WHEN 'joinClub' THEN ?
+                WHEN 'contact_email' THEN ?
+                WHEN 'contact_address' THEN ?
+                ELSE desc END
+            WHERE name IN ('aboutClub1', 'aboutClub2', 'clubHistory', 'clubActivities', 'joinClub', 'contact_email', 'contact_address');"
+        )
+        .bind(&info.club_desc1) // for 'aboutClub1'
+        .bind(&info.club_desc2) // for 'aboutClub2'
+        .bind(&info.club_history) // for 'clubHistory'
+        .bind(&info.club_activities) // for 'clubActivities'
+        .bind(&info.join_info) // for 'joinClub'
+        .bind(&info.contact_email) // for 'contact_email'
+        .bind(&info.contact_address) // for 'contact_address'
+        .execute(&mut *tx)
+        .await;
+
+        // Handle the result of the database operation
+        match result {
+            Ok(_) => {
+                let after =
+                    website_info_json(&mut tx).await.map_err(db_error)?;
+                audit(&mut tx, &admin, "website.update", "website_information", Some(before), Some(after))
+                    .await
+                    .map_err(db_error)?;
+                tx.commit().await.map_err(db_error)?;
+
+                Ok(Json(serde_json::json!({
+                "success": true,
+                "message": "Website information updated successfully.",
+                })))
+            }
+            Err(err) => Err(db_error(err)),
+        }
+    }
+
+    /// The website information as a `{name: desc}` object, for the audit log.
+    async fn website_info_json(
+        conn: &mut SqliteConnection,
+    ) -> Result<Value, rocket_db_pools::sqlx::Error> {
+        let rows = rocket_db_pools::sqlx::query("SELECT name, desc FROM website_information")
+            .fetch_all(conn)
+            .await?;
+        Ok(Value::Object(
+            rows.iter()
+                .map(|row| (row.get("name"), Value::from(row.get::<String, _>("desc"))))
+                .collect(),
+        ))
+    }
+
+    /// The largest image `POST /api/images` accepts.
+    const IMAGE_SIZE_LIMIT: u64 = 10 * 1024 * 1024;
+    /// The widest or tallest image that will be decoded, so a small file
+    /// can't claim enormous dimensions and exhaust memory.
+    const MAX_IMAGE_SIDE: u32 = 12_000;
+    /// Widths of the resized copies made of every stored image, smallest first.
+    const RENDITION_WIDTHS: [u32; 3] = [160, 480, 1200];
+
+    /// Where images are stored: `image_dir` in Rocket.toml, `product_images`
+    /// by default. Its files are served from `/product_images`.
+    pub(super) struct ImageStore {
+        dir: PathBuf,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum ImageFormat {
+        Png,
+        Jpeg,
+        Gif,
+        Webp,
+    }
+
+    impl ImageFormat {
+        /// Recognizes an image by its first bytes, rather than trusting the
+        /// file name or the content type the browser sent.
+        fn sniff(bytes: &[u8]) -> Option<Self> {
+            match bytes {
+                [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(Self::Png),
+                [0xff, 0xd8, 0xff, ..] => Some(Self::Jpeg),
+                [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::Gif),
+                [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::Webp),
+                _ => None,
+            }
+        }
+
+        fn extension(self) -> &'static str {
+            match self {
+                Self::Png => "png",
+                Self::Jpeg => "jpg",
+                Self::Gif => "gif",
+                Self::Webp => "webp",
+            }
+        }
+
+        fn content_type(self) -> &'static str {
+            match self {
+                Self::Png => "image/png",
+                Self::Jpeg => "image/jpeg",
+                Self::Gif => "image/gif",
+                Self::Webp => "image/webp",
+            }
+        }
+    }
+
+    fn image_url(file: &str) -> String {
+        format!("/product_images/{file}")
+    }
+
+    /// The file holding a resized copy of a stored image, e.g. `ab12…-480w.jpg`.
+    fn rendition_file(file: &str, width: u32, extension: &str) -> String {
+        let stem = file.split_once('.').map_or(file, |(stem, _)| stem);
+        format!("{stem}-{width}w.{extension}")
+    }
+
+    /// The resized copies of an image, for `<picture>` and `<img srcset>`.
+    #[derive(Serialize)]
+    struct ImageSizes {
+        /// The smallest JPEG copy, for lists and carts.
+        thumbnail: String,
+        /// Every WebP copy as `srcset` candidates, e.g. `/product_images/ab12…-160w.webp 160w, …`.
+        webp_srcset: String,
+        /// The same for the JPEG copies, for browsers without WebP.
+        jpeg_srcset: String,
+    }
+
+    fn image_sizes(file: &str) -> ImageSizes {
+        let srcset = |extension| {
+            RENDITION_WIDTHS
+                .iter()
+                .map(|&width| format!("{} {width}w", image_url(&rendition_file(file, width, extension))))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        ImageSizes {
+            thumbnail: image_url(&rendition_file(file, RENDITION_WIDTHS[0], "jpg")),
+            webp_srcset: srcset("webp"),
+            jpeg_srcset: srcset("jpg"),
+        }
+    }
+
+    /// A resized copy of an image, as WebP and as JPEG.
+    struct Rendition {
+        width: u32,
+        webp: Vec<u8>,
+        jpeg: Vec<u8>,
+    }
+
+    /// A decoded image: its size and its resized copies.
+    struct Rendered {
+        width: u32,
+        height: u32,
+        renditions: Vec<Rendition>,
+    }
+
+    /// Decodes an image and makes a WebP and a JPEG copy of it at each of
+    /// `RENDITION_WIDTHS`. Images narrower than a width are copied at their
+    /// own size rather than scaled up. This is slow, so it's run with
+    /// `spawn_blocking`.
+    fn render(bytes: &[u8]) -> Result<Rendered, image::ImageError> {
+        let mut reader = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?;
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(MAX_IMAGE_SIDE);
+        limits.max_image_height = Some(MAX_IMAGE_SIDE);
+        reader.limits(limits);
+        let original = reader.decode()?;
+
+        let renditions = RENDITION_WIDTHS
+            .iter()
+            .map(|&width| {
+                let copy = if original.width() > width {
+                    original.resize(width, u32::MAX, image::imageops::FilterType::CatmullRom)
+                } else {
+                    original.clone()
+                };
+                // The WebP copy is lossless, so it keeps any transparency
+                let mut webp = Vec::new();
+                copy.to_rgba8()
+                    .write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut webp))?;
+                let mut jpeg = Vec::new();
+                on_white(&copy).write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 80))?;
+                Ok(Rendition { width, webp, jpeg })
+            })
+            .collect::<Result<_, image::ImageError>>()?;
+
+        Ok(Rendered {
+            width: original.width(),
+            height: original.height(),
+            renditions,
+        })
+    }
+
+    /// JPEG has no transparency, so transparent areas are painted white
+    /// instead of turning black.
+    fn on_white(image: &image::DynamicImage) -> image::DynamicImage {
+        if !image.color().has_alpha() {
+            return image::DynamicImage::ImageRgb8(image.to_rgb8());
+        }
+        let rgba = image.to_rgba8();
+        let rgb = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let image::Rgba([r, g, b, a]) = *rgba.get_pixel(x, y);
+            let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+            image::Rgb([blend(r), blend(g), blend(b)])
+        });
+        image::DynamicImage::ImageRgb8(rgb)
+    }
+
+    /// Decodes and resizes an image on a blocking thread.
+    async fn render_blocking(bytes: Vec<u8>) -> Result<Rendered, String> {
+        rocket::tokio::task::spawn_blocking(move || render(&bytes))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())
+    }
+
+    /// Writes a file to the store unless it's already there. Files are named
+    /// by their contents, so one that exists already holds these bytes.
+    async fn write_stored_file(store: &ImageStore, file: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let path = store.dir.join(file);
+        if !rocket::tokio::fs::try_exists(&path).await? {
+            // Written under another name first, so a half-written image is never served
+            let partial = store.dir.join(format!(".{file}.{}", Uuid::new_v4()));
+            rocket::tokio::fs::write(&partial, bytes).await?;
+            rocket::tokio::fs::rename(&partial, &path).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes an image to the store, named by the SHA-256 of its contents, so
+    /// the same image uploaded twice is only kept once. Returns the file name.
+    async fn write_image_file(
+        store: &ImageStore,
+        bytes: &[u8],
+        format: ImageFormat,
+    ) -> std::io::Result<String> {
+        let file = format!("{}.{}", hex::encode(Sha256::digest(bytes)), format.extension());
+        write_stored_file(store, &file, bytes).await?;
+        Ok(file)
+    }
+
+    async fn write_renditions(store: &ImageStore, file: &str, rendered: &Rendered) -> std::io::Result<()> {
+        for rendition in &rendered.renditions {
+            write_stored_file(store, &rendition_file(file, rendition.width, "webp"), &rendition.webp).await?;
+            write_stored_file(store, &rendition_file(file, rendition.width, "jpg"), &rendition.jpeg).await?;
+        }
+        Ok(())
+    }
+
+    /// Records a stored image. `size` is only known once its resized copies
+    /// have been made.
+    async fn record_image(
+        conn: &mut SqliteConnection,
+        file: &str,
+        format: ImageFormat,
+        bytes: usize,
+        size: Option<(u32, u32)>,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query(
+            "INSERT INTO images (file, content_type, bytes, uploaded_at, width, height) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (file) DO UPDATE SET width = COALESCE(width, excluded.width), height = COALESCE(height, excluded.height)",
+        )
+        .bind(file)
+        .bind(format.content_type())
+        .bind(bytes as i64)
+        .bind(timestamp(Utc::now()))
+        .bind(size.map(|(width, _)| width))
+        .bind(size.map(|(_, height)| height))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Checks that `bytes` is an image and stores it. Returns the file name.
+    async fn save_image(
+        conn: &mut SqliteConnection,
+        store: &ImageStore,
+        bytes: &[u8],
+    ) -> Result<String, Custom<Json<ResponseData>>> {
+        let format = ImageFormat::sniff(bytes).ok_or_else(|| {
+            failure(Status::UnsupportedMediaType, "Images must be PNG, JPEG, GIF or WebP.")
+        })?;
+        let rendered = render_blocking(bytes.to_vec())
+            .await
+            .map_err(|e| failure(Status::BadRequest, format!("The image couldn't be read: {e}")))?;
+        let save_error = |e| failure(Status::InternalServerError, format!("Couldn't save the image: {e}"));
+        let file = write_image_file(store, bytes, format).await.map_err(save_error)?;
+        write_renditions(store, &file, &rendered).await.map_err(save_error)?;
+        record_image(conn, &file, format, bytes.len(), Some((rendered.width, rendered.height)))
+            .await
+            .map_err(db_error)?;
+        Ok(file)
+    }
+
+    /// Moves images still held in the old `image` BLOB columns into the store
+    /// and their galleries. Returns how many were moved.
+    async fn import_legacy_images(
+        pool: &SqlitePool,
+        store: &ImageStore,
+    ) -> Result<usize, String> {
+        let mut moved = 0;
+        for (table, key, variant) in [
+            ("products", "product_id", "NULL"),
+            ("product_variants", "var_id", "var_id"),
+        ] {
+            let rows = rocket_db_pools::sqlx::query(&format!(
+                "SELECT {key} AS id, product_id, {variant} AS var_id, image FROM {table} WHERE image IS NOT NULL"
+            ))
+            .fetch_all(pool)
+            .await
+            .map_err(|e| format!("Database error: {e}"))?;
+
+            for row in rows {
+                let id: i64 = row.get("id");
+                let product_id: i64 = row.get("product_id");
+                let var_id: Option<u32> = row.get("var_id");
+                let bytes: Vec<u8> = row.get("image");
+                let Some(format) = ImageFormat::sniff(&bytes) else {
+                    eprintln!("Left the image of {table} {id} in the database: it isn't a PNG, JPEG, GIF or WebP.");
+                    continue;
+                };
+                let file = write_image_file(store, &bytes, format)
+                    .await
+                    .map_err(|e| format!("Couldn't save the image of {table} {id}: {e}"))?;
+
+                let mut tx = pool.begin().await.map_err(|e| format!("Database error: {e}"))?;
+                record_image(&mut tx, &file, format, bytes.len(), None)
+                    .await
+                    .map_err(|e| format!("Database error: {e}"))?;
+                // Nobody wrote alt text for these, so they're described by
+                // name. An image added since the upgrade stays primary.
+                let alt = describe_item(&mut tx, product_id, var_id)
+                    .await
+                    .map_err(|e| format!("Database error: {e}"))?;
+                add_to_gallery(&mut tx, product_id, var_id, &file, &alt, false)
+                    .await
+                    .map_err(|e| format!("Database error: {e}"))?;
+                rocket_db_pools::sqlx::query(&format!("UPDATE {table} SET image = NULL WHERE {key} = ?"))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| format!("Database error: {e}"))?;
+                tx.commit().await.map_err(|e| format!("Database error: {e}"))?;
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// A product's name, and the variant's attribute values if it's a
+    /// variant, e.g. `Robot Tee (small, white)`. Used as alt text for images
+    /// nobody described, and in messages to customers.
+    async fn describe_item(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+    ) -> Result<String, rocket_db_pools::sqlx::Error> {
+        let name: String = rocket_db_pools::sqlx::query("SELECT name FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_one(&mut *conn)
+            .await?
+            .get("name");
+        let values = match var_id {
+            Some(var_id) => variant_attributes(conn, var_id)
+                .await?
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+        Ok(if values.is_empty() { name } else { format!("{name} ({})", values.join(", ")) })
+    }
+
+    /// Makes the resized copies of stored images that don't have them yet,
+    /// such as those moved out of the database. Returns how many were done.
+    async fn render_missing_renditions(
+        pool: &SqlitePool,
+        store: &ImageStore,
+    ) -> Result<usize, String> {
+        let files: Vec<String> = rocket_db_pools::sqlx::query("SELECT file FROM images WHERE width IS NULL")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| format!("Database error: {e}"))?
+            .iter()
+            .map(|row| row.get("file"))
+            .collect();
+
+        let mut rendered_count = 0;
+        for file in files {
+            let bytes = rocket::tokio::fs::read(store.dir.join(&file))
+                .await
+                .map_err(|e| format!("Couldn't read {file}: {e}"))?;
+            let rendered = match render_blocking(bytes).await {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    eprintln!("Couldn't resize {file}: {e}");
+                    continue;
+                }
+            };
+            write_renditions(store, &file, &rendered)
+                .await
+                .map_err(|e| format!("Couldn't save the copies of {file}: {e}"))?;
+            rocket_db_pools::sqlx::query("UPDATE images SET width = ?, height = ? WHERE file = ?")
+                .bind(rendered.width)
+                .bind(rendered.height)
+                .bind(&file)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("Database error: {e}"))?;
+            rendered_count += 1;
+        }
+        Ok(rendered_count)
+    }
+
+    /// Sets up the image store and serves it from `/product_images`, first
+    /// moving any images left in the database into it and resizing any that
+    /// haven't been.
+    pub(super) fn image_storage() -> AdHoc {
+        AdHoc::try_on_ignite("Image Storage", |rocket| async {
+            let dir: PathBuf = rocket
+                .figment()
+                .extract_inner("image_dir")
+                .unwrap_or_else(|_| PathBuf::from("product_images"));
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                eprintln!("Can't create the image directory {}: {e}", dir.display());
+                return Err(rocket);
+            }
+            let store = ImageStore { dir };
+
+            let Some(db) = RoboDatabase::fetch(&rocket) else {
+                eprintln!("Can't import stored images: database is not attached.");
+                return Err(rocket);
+            };
+            match import_legacy_images(db, &store).await {
+                Ok(0) => {}
+                Ok(moved) => println!("Moved {moved} images from the database to {}.", store.dir.display()),
+                Err(e) => {
+                    eprintln!("Image import failed: {e}");
+                    return Err(rocket);
+                }
+            }
+            match render_missing_renditions(db, &store).await {
+                Ok(0) => {}
+                Ok(count) => println!("Made resized copies of {count} images."),
+                Err(e) => {
+                    eprintln!("Resizing stored images failed: {e}");
+                    return Err(rocket);
+                }
+            }
+
+            Ok(rocket.manage(store).mount("/product_images", routes![serve_image]))
+        })
+    }
+
+    /// The `If-None-Match` header of a request, for answering `304 Not Modified`.
+    pub(super) struct IfNoneMatch(Option<String>);
+
+    impl IfNoneMatch {
+        fn matches(&self, etag: &str) -> bool {
+            self.0.as_deref().is_some_and(|header| {
+                header
+                    .split(',')
+                    .map(|tag| tag.trim().trim_start_matches("W/"))
+                    .any(|tag| tag == "*" || tag == etag)
+            })
+        }
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for IfNoneMatch {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            Outcome::Success(IfNoneMatch(
+                req.headers().get_one("If-None-Match").map(str::to_string),
+            ))
+        }
+    }
+
+    /// A stored image, or `304 Not Modified` if the browser has it already.
+    pub(super) enum CachedImage {
+        File(NamedFile, String),
+        NotModified(String),
+    }
+
+    impl<'r> Responder<'r, 'static> for CachedImage {
+        fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+            let (mut response, etag) = match self {
+                CachedImage::File(file, etag) => (Response::build_from(file.respond_to(req)?), etag),
+                CachedImage::NotModified(etag) => {
+                    let mut response = Response::build();
+                    response.status(Status::NotModified);
+                    (response, etag)
+                }
+            };
+            // A file's name is the hash of its contents, so it never changes
+            response
+                .raw_header("ETag", etag)
+                .raw_header("Cache-Control", "public, max-age=31536000, immutable")
+                .ok()
+        }
+    }
+
+    /// Serves stored images and their resized copies.
+    #[get("/<file>")]
+    pub(super) async fn serve_image(
+        file: &str,
+        if_none_match: IfNoneMatch,
+        store: &State<ImageStore>,
+    ) -> Option<CachedImage> {
+        let valid = !file.starts_with('.')
+            && file.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid {
+            return None;
+        }
+        let stem = file.split_once('.').map_or(file, |(stem, _)| stem);
+        let etag = format!("\"{stem}\"");
+        if if_none_match.matches(&etag) {
+            return Some(CachedImage::NotModified(etag));
+        }
+        let named = NamedFile::open(store.dir.join(file)).await.ok()?;
+        Some(CachedImage::File(named, etag))
+    }
+
+    /// The longest alt text accepted. Screen readers read all of it, so it
+    /// should describe the image briefly.
+    const MAX_ALT_LENGTH: usize = 300;
+
+    /// One image of a product's or variant's gallery.
+    #[derive(Serialize)]
+    struct GalleryImage {
+        id: i64,
+        file: String,
+        url: String,
+        sizes: ImageSizes,
+        alt: String,
+        /// Shown first, and wherever only one image fits.
+        primary: bool,
+    }
+
+    impl From<&SqliteRow> for GalleryImage {
+        fn from(row: &SqliteRow) -> Self {
+            let file: String = row.get("file");
+            GalleryImage {
+                id: row.get("image_id"),
+                url: image_url(&file),
+                sizes: image_sizes(&file),
+                file,
+                alt: row.get("alt"),
+                primary: row.get("is_primary"),
+            }
+        }
+    }
+
+    /// Checks alt text before it's stored, and trims it.
+    fn clean_alt(alt: Option<&str>) -> Result<String, Custom<Json<ResponseData>>> {
+        let alt = alt.map(str::trim).unwrap_or_default();
+        if alt.is_empty() {
+            return Err(failure(
+                Status::BadRequest,
+                "Images need alt text describing them, for visitors using screen readers.",
+            ));
+        }
+        if alt.chars().count() > MAX_ALT_LENGTH {
+            return Err(failure(
+                Status::BadRequest,
+                format!("Alt text can be at most {MAX_ALT_LENGTH} characters."),
+            ));
+        }
+        Ok(alt.to_string())
+    }
+
+    /// The images of a product's own gallery, or of one of its variants', in order.
+    async fn gallery(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+    ) -> Result<Vec<GalleryImage>, rocket_db_pools::sqlx::Error> {
+        Ok(rocket_db_pools::sqlx::query(
+            "SELECT * FROM product_images WHERE product_id = ? AND var_id IS ? ORDER BY position, image_id",
+        )
+        .bind(product_id)
+        .bind(var_id)
+        .fetch_all(conn)
+        .await?
+        .iter()
+        .map(GalleryImage::from)
+        .collect())
+    }
+
+    /// The primary image of a gallery. Only a gallery being edited can lack
+    /// one, in which case the first image stands in.
+    fn primary_image(images: &[GalleryImage]) -> Option<&GalleryImage> {
+        images.iter().find(|image| image.primary).or(images.first())
+    }
+
+    async fn load_product_images(
+        conn: &mut SqliteConnection,
+        product: &mut Product,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        let Some(product_id) = product.id else {
+            return Ok(());
+        };
+        product.images = gallery(conn, product_id.into(), None).await?;
+        if let Some(primary) = primary_image(&product.images) {
+            product.image_url = Some(primary.url.clone());
+            product.image_sizes = Some(image_sizes(&primary.file));
+            product.image_alt = Some(primary.alt.clone());
+        }
+        Ok(())
+    }
+
+    async fn load_variant_images(
+        conn: &mut SqliteConnection,
+        variant: &mut ProductVariant,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        let Some(var_id) = variant.varid else {
+            return Ok(());
+        };
+        variant.images = gallery(conn, variant.product.into(), Some(var_id)).await?;
+        if let Some(primary) = primary_image(&variant.images) {
+            variant.image_url = Some(primary.url.clone());
+            variant.image_sizes = Some(image_sizes(&primary.file));
+            variant.image_alt = Some(primary.alt.clone());
+        }
+        Ok(())
+    }
+
+    /// Adds a stored image to the end of a gallery and returns its row. The
+    /// first image of a gallery is always its primary one. Adding an image
+    /// that's already there updates its alt text instead, so saving a form
+    /// twice doesn't show the image twice.
+    async fn add_to_gallery(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+        file: &str,
+        alt: &str,
+        primary: bool,
+    ) -> Result<SqliteRow, rocket_db_pools::sqlx::Error> {
+        let existing = rocket_db_pools::sqlx::query(
+            "SELECT image_id FROM product_images WHERE product_id = ? AND var_id IS ? AND file = ?",
+        )
+        .bind(product_id)
+        .bind(var_id)
+        .bind(file)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let image_id: i64 = match existing {
+            Some(row) => {
+                let image_id = row.get("image_id");
+                rocket_db_pools::sqlx::query("UPDATE product_images SET alt = ? WHERE image_id = ?")
+                    .bind(alt)
+                    .bind(image_id)
+                    .execute(&mut *conn)
+                    .await?;
+                image_id
+            }
+            None => rocket_db_pools::sqlx::query(
+                "INSERT INTO product_images (product_id, var_id, file, alt, position)
+                SELECT ?1, ?2, ?3, ?4, COALESCE(MAX(position) + 1, 0)
+                FROM product_images WHERE product_id = ?1 AND var_id IS ?2
+                RETURNING image_id",
+            )
+            .bind(product_id)
+            .bind(var_id)
+            .bind(file)
+            .bind(alt)
+            .fetch_one(&mut *conn)
+            .await?
+            .get("image_id"),
+        };
+        if primary {
+            make_primary(conn, image_id).await?;
+        } else {
+            ensure_primary(conn, product_id, var_id).await?;
+        }
+        rocket_db_pools::sqlx::query("SELECT * FROM product_images WHERE image_id = ?")
+            .bind(image_id)
+            .fetch_one(conn)
+            .await
+    }
+
+    /// Makes an image the primary one of its gallery.
+    async fn make_primary(
+        conn: &mut SqliteConnection,
+        image_id: i64,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        // The old primary is cleared first, as a gallery can't have two
+        rocket_db_pools::sqlx::query(
+            "UPDATE product_images SET is_primary = 0 WHERE image_id <> ?1 AND is_primary = 1
+            AND (product_id, IFNULL(var_id, 0)) =
+                (SELECT product_id, IFNULL(var_id, 0) FROM product_images WHERE image_id = ?1)",
+        )
+        .bind(image_id)
+        .execute(&mut *conn)
+        .await?;
+        rocket_db_pools::sqlx::query("UPDATE product_images SET is_primary = 1 WHERE image_id = ?")
+            .bind(image_id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Makes the first image of a gallery without a primary image its primary one.
+    async fn ensure_primary(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+    ) -> Result<(), rocket_db_pools::sqlx::Error> {
+        rocket_db_pools::sqlx::query(
+            "UPDATE product_images SET is_primary = 1 WHERE image_id = (
+                SELECT image_id FROM product_images WHERE product_id = ?1 AND var_id IS ?2
+                ORDER BY position, image_id LIMIT 1
+            ) AND NOT EXISTS (
+                SELECT 1 FROM product_images WHERE product_id = ?1 AND var_id IS ?2 AND is_primary = 1
+            )",
+        )
+        .bind(product_id)
+        .bind(var_id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Checks that a product exists and, if given, that the variant is one of its own.
+    async fn gallery_owner(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        var_id: Option<u32>,
+    ) -> Result<(), Custom<Json<ResponseData>>> {
+        rocket_db_pools::sqlx::query("SELECT 1 FROM products WHERE product_id = ?")
+            .bind(product_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Product not found."))?;
+        if let Some(var_id) = var_id {
+            rocket_db_pools::sqlx::query("SELECT 1 FROM product_variants WHERE var_id = ? AND product_id = ?")
+                .bind(var_id)
+                .bind(product_id)
+                .fetch_optional(conn)
+                .await
+                .map_err(db_error)?
+                .ok_or_else(|| {
+                    failure(
+                        Status::NotFound,
+                        format!("Variant {var_id} isn't a variant of product {product_id}."),
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// A gallery image of a product, whichever of its galleries it's in.
+    async fn gallery_image(
+        conn: &mut SqliteConnection,
+        product_id: i64,
+        image_id: i64,
+    ) -> Result<SqliteRow, Custom<Json<ResponseData>>> {
+        rocket_db_pools::sqlx::query("SELECT * FROM product_images WHERE image_id = ? AND product_id = ?")
+            .bind(image_id)
+            .bind(product_id)
+            .fetch_optional(conn)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Image not found."))
+    }
+
+    /// A product's own images in order, or those of `variant`.
+    #[allow(private_interfaces)]
+    #[get("/products/<product_id>/images?<variant>")]
+    pub(super) async fn get_gallery(
+        product_id: i64,
+        variant: Option<u32>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<Vec<GalleryImage>>, Custom<Json<ResponseData>>> {
+        gallery_owner(&mut db, product_id, variant).await?;
+        gallery(&mut db, product_id, variant)
+            .await
+            .map(Json)
+            .map_err(db_error)
+    }
+
+    /// An image already in the store, to add to a gallery.
+    #[derive(Deserialize)]
+    struct NewGalleryImage {
+        file: String,
+        alt: String,
+        /// Adds it to this variant's gallery instead of the product's.
+        #[serde(default)]
+        variant: Option<u32>,
+        #[serde(default)]
+        primary: bool,
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/products/<product_id>/images", data = "<image>")]
+    pub(super) async fn add_gallery_image(
+        product_id: i64,
+        image: Json<NewGalleryImage>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<GalleryImage>, Custom<Json<ResponseData>>> {
+        let alt = clean_alt(Some(&image.alt))?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        gallery_owner(&mut tx, product_id, image.variant).await?;
+        rocket_db_pools::sqlx::query("SELECT 1 FROM images WHERE file = ?")
+            .bind(&image.file)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| {
+                failure(
+                    Status::NotFound,
+                    format!("No image `{}` has been uploaded. Upload it to /api/images first.", image.file),
+                )
+            })?;
+
+        let row = add_to_gallery(&mut tx, product_id, image.variant, &image.file, &alt, image.primary)
+            .await
+            .map_err(db_error)?;
+        let image_id: i64 = row.get("image_id");
+        audit(&mut tx, &admin, "gallery.add", &image_id.to_string(), None, Some(row_json(&row)))
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(GalleryImage::from(&row)))
+    }
+
+    /// The editable fields of a gallery image. Leaving `alt` out keeps it.
+    /// To change which image is primary, make another one primary.
+    #[derive(Deserialize)]
+    struct GalleryImageUpdate {
+        #[serde(default)]
+        alt: Option<String>,
+        #[serde(default)]
+        primary: bool,
+    }
+
+    #[allow(private_interfaces)]
+    #[put("/products/<product_id>/images/<image_id>", data = "<update>")]
+    pub(super) async fn update_gallery_image(
+        product_id: i64,
+        image_id: i64,
+        update: Json<GalleryImageUpdate>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<GalleryImage>, Custom<Json<ResponseData>>> {
+        let alt = update.alt.as_deref().map(|alt| clean_alt(Some(alt))).transpose()?;
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = gallery_image(&mut tx, product_id, image_id).await?;
+        if let Some(alt) = alt {
+            rocket_db_pools::sqlx::query("UPDATE product_images SET alt = ? WHERE image_id = ?")
+                .bind(alt)
+                .bind(image_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        if update.primary {
+            make_primary(&mut tx, image_id).await.map_err(db_error)?;
+        }
+        let after = gallery_image(&mut tx, product_id, image_id).await?;
+        audit(
+            &mut tx,
+            &admin,
+            "gallery.update",
+            &image_id.to_string(),
+            Some(row_json(&before)),
+            Some(row_json(&after)),
+        )
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(GalleryImage::from(&after)))
+    }
+
+    /// Puts a gallery in the order of the image ids given, which must be
+    /// every image of that gallery once.
+    #[allow(private_interfaces)]
+    #[put("/products/<product_id>/images?<variant>", data = "<order>")]
+    pub(super) async fn reorder_gallery(
+        product_id: i64,
+        variant: Option<u32>,
+        order: Json<Vec<i64>>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Vec<GalleryImage>>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        gallery_owner(&mut tx, product_id, variant).await?;
+        let before = gallery(&mut tx, product_id, variant).await.map_err(db_error)?;
+
+        let mut current: Vec<i64> = before.iter().map(|image| image.id).collect();
+        let mut requested = order.to_vec();
+        current.sort_unstable();
+        requested.sort_unstable();
+        if current != requested {
+            return Err(failure(
+                Status::BadRequest,
+                "List every image of the gallery exactly once, in the new order.",
+            ));
+        }
+
+        for (position, image_id) in order.iter().enumerate() {
+            rocket_db_pools::sqlx::query("UPDATE product_images SET position = ? WHERE image_id = ?")
+                .bind(position as i64)
+                .bind(image_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        let target = match variant {
+            Some(var_id) => format!("{product_id}/{var_id}"),
+            None => product_id.to_string(),
+        };
+        let ids = |images: &[GalleryImage]| {
+            serde_json::json!(images.iter().map(|image| image.id).collect::<Vec<_>>())
+        };
+        let after = gallery(&mut tx, product_id, variant).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "gallery.reorder", &target, Some(ids(&before)), Some(ids(&after)))
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(after))
+    }
+
+    /// Removes an image from its gallery. The stored file is kept, as other
+    /// galleries may show it. Returns what's left of the gallery.
+    #[allow(private_interfaces)]
+    #[delete("/products/<product_id>/images/<image_id>")]
+    pub(super) async fn remove_gallery_image(
+        product_id: i64,
+        image_id: i64,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<Vec<GalleryImage>>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = gallery_image(&mut tx, product_id, image_id).await?;
+        let var_id: Option<u32> = before.get("var_id");
+        rocket_db_pools::sqlx::query("DELETE FROM product_images WHERE image_id = ?")
+            .bind(image_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        ensure_primary(&mut tx, product_id, var_id).await.map_err(db_error)?;
+        audit(&mut tx, &admin, "gallery.remove", &image_id.to_string(), Some(row_json(&before)), None)
+            .await
+            .map_err(db_error)?;
+        let remaining = gallery(&mut tx, product_id, var_id).await.map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(remaining))
+    }
+
+    #[derive(Serialize)]
+    struct StoredImage {
+        file: String,
+        url: String,
+        sizes: ImageSizes,
+        /// Where it was added to a gallery, if it was.
+        gallery_image: Option<GalleryImage>,
+    }
+
+    /// Where `POST /api/images` adds the image, if anywhere: the gallery of
+    /// `product` or of `variant`. Adding it needs `alt` text; `primary` makes
+    /// it the gallery's primary image.
+    #[derive(FromForm)]
+    struct ImageAttachment<'r> {
+        product: Option<i64>,
+        variant: Option<u32>,
+        alt: Option<&'r str>,
+        primary: bool,
+    }
+
+    /// Stores an image sent as the raw request body, and adds it to a gallery
+    /// if asked to.
+    #[allow(private_interfaces)]
+    #[post("/images?<attach..>", data = "<data>")]
+    pub(super) async fn upload_image(
+        data: Data<'_>,
+        attach: ImageAttachment<'_>,
+        store: &State<ImageStore>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Result<Json<StoredImage>, Custom<Json<ResponseData>>> {
+        let ImageAttachment { product, variant, alt, primary } = attach;
+
+        if product.is_some() && variant.is_some() {
+            return Err(failure(
+                Status::BadRequest,
+                "Attach the image to a product or a variant, not both.",
+            ));
+        }
+        let alt = match product.is_some() || variant.is_some() {
+            true => Some(clean_alt(alt)?),
+            false => None,
+        };
+
+        let bytes = data
+            .open(IMAGE_SIZE_LIMIT.bytes())
+            .into_bytes()
+            .await
+            .map_err(|e| failure(Status::BadRequest, format!("Couldn't read the image: {e}")))?;
+        if !bytes.is_complete() {
+            return Err(failure(Status::PayloadTooLarge, "Images can be at most 10 MiB."));
+        }
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let file = save_image(&mut tx, store, &bytes).await?;
+        audit(&mut tx, &admin, "image.upload", &file, None, None)
+            .await
+            .map_err(db_error)?;
+
+        let product_id = match (product, variant) {
+            (Some(product_id), _) => Some(product_id),
+            (None, Some(var_id)) => Some(
+                rocket_db_pools::sqlx::query("SELECT product_id FROM product_variants WHERE var_id = ?")
+                    .bind(var_id)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .map_err(db_error)?
+                    .ok_or_else(|| failure(Status::NotFound, "Variant not found."))?
+                    .get("product_id"),
+            ),
+            (None, None) => None,
+        };
+        let mut gallery_image = None;
+        if let (Some(product_id), Some(alt)) = (product_id, alt) {
+            gallery_owner(&mut tx, product_id, variant).await?;
+            let row = add_to_gallery(&mut tx, product_id, variant, &file, &alt, primary)
+                .await
+                .map_err(db_error)?;
+            let image_id: i64 = row.get("image_id");
+            audit(&mut tx, &admin, "gallery.add", &image_id.to_string(), None, Some(row_json(&row)))
+                .await
+                .map_err(db_error)?;
+            gallery_image = Some(GalleryImage::from(&row));
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(StoredImage {
+            url: image_url(&file),
+            sizes: image_sizes(&file),
+            file,
+            gallery_image,
+        }))
+    }
+
+    /// Deprecated: use `POST /api/images`, which takes the image as the
+    /// request body and can attach it to a product or variant.
+    #[allow(private_interfaces)]
+    #[post("/makeimage", data = "<image>")]
+    pub(super) async fn make_image(
+        image: Form<TempFile<'_>>,
+        store: &State<ImageStore>,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<InventoryWrite>,
+    ) -> Deprecated<Result<Json<String>, Custom<Json<ResponseData>>>> {
+        let result = async {
+            let mut bytes = Vec::new();
+            image
+                .open()
+                .await
+                .map_err(|e| failure(Status::BadRequest, format!("File didn't upload {e}")))?
+                .read_to_end(&mut bytes)
+                .await
+                .map_err(|e| failure(Status::BadRequest, format!("Couldnt read file {e}")))?;
+
+            let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+            let file = save_image(&mut tx, store, &bytes).await?;
+            audit(&mut tx, &admin, "image.upload", &file, None, None)
+                .await
+                .map_err(db_error)?;
+            tx.commit().await.map_err(db_error)?;
+            Ok(Json(file))
+        };
+        Deprecated::new(result.await, "/api/images".to_string())
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/get_admins")]
+    pub(super) async fn get_admins(
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<AdminsManage>,
+    ) -> Result<Json<Vec<String>>, Status> {
+        // SQL query to fetch all usernames from the admins table
+        let usernames_query = rocket_db_pools::sqlx::query("SELECT username FROM admins")
+            .fetch_all(&mut **db)
+            .await;
+
+        // Map rows to Vec<String> containing only usernames
+        match usernames_query {
+            Ok(rows) => {
+                let usernames: Vec<String> =
+                    rows.into_iter().map(|row| row.get("username")).collect();
+                Ok(Json(usernames))
+            }
+            Err(_) => Err(Status::InternalServerError),
+        }
+    }
+
+    #[allow(private_interfaces)]
+    #[delete("/delete_admin/<username>")]
+    pub(super) async fn delete_admin(
+        username: &str,
+        mut db: Connection<RoboDatabase>,
+        admin: Authorized<AdminsManage>,
+    ) -> Result<Json<ResponseData>, Custom<Json<ResponseData>>> {
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+        let before = admin_json(&mut tx, username)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, "Admin user not found."))?;
+
+        // SQL query to delete the admin by username
+        rocket_db_pools::sqlx::query("DELETE FROM admins WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        // Counted after the delete, in the same transaction, so two managers
+        // deleting each other at once can't both succeed
+        let admins = count_usable_admins(&mut tx).await.map_err(db_error)?;
+        let managers = count_with_permission(&mut tx, AdminsManage::NAME)
+            .await
+            .map_err(db_error)?;
+        if admins == 0 || managers == 0 {
+            return Err(failure(
+                Status::Conflict,
+                "Can't delete the last admin who can manage admins.",
+            ));
+        }
+        // Checked after the guard so the last manager hears why they have to stay
+        if username == admin.username {
+            return Err(failure(
+                Status::BadRequest,
+                "You can't delete your own account.",
+            ));
+        }
+
+        audit(&mut tx, &admin, "admin.delete", username, Some(before), None)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(Json(ResponseData {
+            success: true,
+            message: "Admin user deleted successfully.".to_string(),
+        }))
+    }
+
+    // Structs for customers, orders, and products
+    #[derive(Serialize, Deserialize)]
+    struct Customer {
+        cust_id: Option<i32>,
+        name: String,
+        address: Address,
+        email: String,
+        phone_number: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Order {
+        order_id: i32,
+        products: Vec<OrderedItem>,
+        total: Money,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct OrderedItem {
+        product_id: i32,
+        variant: Option<i32>,
+        quantity: u32,
+        /// What one unit sold for. Set by the server: the variant's price if it
+        /// has one, otherwise the product's.
+        #[serde(default, skip_deserializing)]
+        unit_price: Money,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Address {
+        address_line_1: String,
+        admin_area_2: String, // City
+        admin_area_1: String, // State
+        postal_code: String,
+        country_code: String,
+    }
+
+    // Fetch all customers
+    #[allow(private_interfaces)]
+    #[get("/getallcustomers")]
+    pub(super) async fn get_all_customers(
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<OrdersRead>,
+    ) -> Result<Json<Vec<Customer>>, Status> {
+        let query = "SELECT cust_id, name, address, email, phone_number FROM customers";
+
+        let rows = rocket_db_pools::sqlx::query(query)
+            .fetch_all(&mut **db)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+        let customers: Vec<Customer> = rows
+            .into_iter()
+            .filter_map(|row| {
+                let cust_id: Option<i32> = row.get("cust_id");
+                let name: String = row.get("name");
+                let address_str: String = row.get("address");
+                let email: String = row.get("email");
+                let phone_number: Option<String> = row.get("phone_number");
+
+                 // Parse the formatted address string
+                let address_parts: Vec<&str> = address_str.split(',').collect();
+                if address_parts.len() == 5 {
+                    let address = Address {
+                        address_line_1: address_parts[0].trim().to_string(),
+                        admin_area_2: address_parts[1].trim().to_string(),
+                        admin_area_1: address_parts[2].trim().to_string(),
+                        postal_code: address_parts[3].trim().to_string(),
+                        country_code: address_parts[4].trim().to_string(),
+                    };
+
+                    Some(Customer {
+                        cust_id,
+                        name,
+                        address,
+                        email,
+                        phone_number,
+                    })
+
+                }else{ None }
+            })
+            .collect();
+
+        Ok(Json(customers))
+    }
+
+    // Fetch orders for a specific customer
+    #[allow(private_interfaces)]
+    #[get("/getcustomerorders/<cust_id>")]
+    pub(super) async fn get_customer_orders(
+        cust_id: i32,
+        mut db: Connection<RoboDatabase>,
+        _admin: Authorized<OrdersRead>,
+    ) -> Result<Json<Vec<Order>>, Status> {
+        let query = "SELECT order_id, total_cents FROM orders WHERE cust_id = ?";
+        let rows = rocket_db_pools::sqlx::query(query)
+            .bind(cust_id)
+            .fetch_all(&mut **db)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+        let mut orders = Vec::new();
+        for row in rows {
+            let order_id: i32 = row.get("order_id");
+            let total = Money::from_cents(row.get("total_cents"));
+
+            // Fetch ordered items for the current order
+            let products_query =
+                "SELECT product_id, var_id, quantity, unit_price_cents FROM ordered_products WHERE order_id = ?";
+            let products_rows = rocket_db_pools::sqlx::query(products_query)
+                .bind(order_id)
+                .fetch_all(&mut **db)
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+
+            let products: Vec<OrderedItem> = products_rows
+                .into_iter()
+                .map(|row| {
+                    let product_id: i32 = row.get("product_id");
+                    let variant: Option<i32> = row.get("var_id");
+                    let quantity: u32 = row.get("quantity");
+                    let unit_price = Money::from_cents(row.get("unit_price_cents"));
+
+                    OrderedItem {
+                        product_id,
+                        variant,
+                        quantity,
+                        unit_price,
+                    }
+                })
+                .collect();
+
+            orders.push(Order {
+                order_id,
+                products,
+                total,
+            });
+        }
+
+        Ok(Json(orders))
+    }
+
+    /// Who an order is for. What they're buying is whatever is in their cart.
+    #[derive(Deserialize)]
+    struct OrderRequest {
+        customer: Customer,
+    }
+
+    #[allow(private_interfaces)]
+    #[post("/create_order", data = "<order_data>")]
+    pub(super) async fn create_order(
+        pot: &CookieJar<'_>,
+        order_data: Json<OrderRequest>,
+        mut db: Connection<RoboDatabase>,
+    ) -> Result<Json<i32>, CartError> {
+        let customer = &order_data.customer;
+        let formatted_address = format!(
+            "{}, {}, {}, {}, {}",
+            customer.address.address_line_1,
+            customer.address.admin_area_2,
+            customer.address.admin_area_1,
+            customer.address.postal_code,
+            customer.address.country_code
+        );
+        let empty = || failure(Status::BadRequest, "The cart is empty.");
+
+        let mut tx = (&mut **db).begin().await.map_err(db_error)?;
+
+        // Step 1: Order what's in the visitor's cart, priced from the database
+        let cart_id = current_cart(&mut tx, pot)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(empty)?;
+        let lines = rocket_db_pools::sqlx::query(
+            "SELECT product_id, var_id, quantity FROM cart_items WHERE cart_id = ? ORDER BY item_id",
+        )
+        .bind(cart_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        if lines.is_empty() {
+            return Err(empty().into());
+        }
+        let mut items = Vec::with_capacity(lines.len());
+        for line in &lines {
+            let product_id: i32 = line.get("product_id");
+            let variant: Option<i32> = match line.get("var_id") {
+                Some(var_id) => Some(var_id),
+                // Only a product without variants can be ordered without one,
+                // but it may have gained some since it was put in the cart
+                None => {
+                    let variants: i64 = rocket_db_pools::sqlx::query(
+                        "SELECT COUNT(*) AS n FROM product_variants WHERE product_id = ?",
+                    )
+                    .bind(product_id)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(db_error)?
+                    .get("n");
+                    if variants > 0 {
+                        return Err(failure(
+                            Status::Conflict,
+                            format!("Product {product_id} comes in variants now; choose one in the cart."),
+                        )
+                        .into());
+                    }
+                    None
+                }
+            };
+            let product = rocket_db_pools::sqlx::query(
+                "SELECT price_cents, archived_at FROM products WHERE product_id = ?",
+            )
+            .bind(product_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| failure(Status::NotFound, format!("Product {product_id} doesn't exist.")))?;
+            if product.get::<Option<String>, _>("archived_at").is_some() {
+                return Err(failure(Status::Conflict, format!("Product {product_id} is no longer sold.")).into());
+            }
+            let mut price_cents: i64 = product.get("price_cents");
+            if let Some(var_id) = variant {
+                let variant = rocket_db_pools::sqlx::query(
+                    "SELECT price_cents FROM product_variants WHERE var_id = ? AND product_id = ?",
+                )
+                .bind(var_id)
+                .bind(product_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(db_error)?
+                .ok_or_else(|| {
+                    failure(
+                        Status::NotFound,
+                        format!("Variant {var_id} isn't a variant of product {product_id}."),
+                    )
+                })?;
+                // A variant without its own price costs what the product does
+                if let Some(variant_price) = variant.get::<Option<i64>, _>("price_cents") {
+                    price_cents = variant_price;
+                }
+            }
+            items.push(OrderedItem {
+                product_id,
+                variant,
+                quantity: line.get("quantity"),
+                unit_price: Money::from_cents(price_cents),
+            });
+        }
+        let total: Money = items.iter().map(|item| item.unit_price * item.quantity).sum();
+
+        // Step 2: Make sure there's still enough of everything, counting what
+        // this cart holds as its own, and take it out of stock
+        for item in &items {
+            // Both ids came from the database, so they aren't negative
+            let var_id = item.variant.map(|id| id as u32);
+            check_stock(&mut tx, item.product_id.into(), var_id, Some(cart_id), item.quantity).await?;
+            let query = match var_id {
+                Some(var_id) => rocket_db_pools::sqlx::query(
+                    "UPDATE product_variants SET quantity = quantity - ? WHERE var_id = ?",
+                )
+                .bind(item.quantity)
+                .bind(var_id),
+                None => rocket_db_pools::sqlx::query("UPDATE products SET quantity = quantity - ? WHERE product_id = ?")
+                    .bind(item.quantity)
+                    .bind(item.product_id),
+            };
+            query
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        // The order replaces the cart, so it's emptied along with the order
+        // being placed rather than by a later request that might not come
+        release_holds(&mut tx, cart_id)
+            .await
+            .map_err(db_error)?;
+        rocket_db_pools::sqlx::query("DELETE FROM cart_items WHERE cart_id = ?")
+            .bind(cart_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        // Step 3: Insert customer information and get the generated `cust_id`
+        let cust_id: i32 = rocket_db_pools::sqlx::query(
+            r#"
+            INSERT INTO customers (name, address, email, phone_number)
+            VALUES ($1, $2, $3, $4)
+            RETURNING cust_id
+            "#,
+        )
+        .bind(&customer.name)
+        .bind(&formatted_address)
+        .bind(&customer.email)
+        .bind(&customer.phone_number)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| failure(Status::InternalServerError, format!("Failed to insert customer: {e}")))?
+        .try_get("cust_id")
+        .map_err(|e| failure(Status::InternalServerError, format!("Failed to get cust_id: {e}")))?;
+
+        // Step 4: Insert a new order and get the generated `order_id`
+        let order_id: i32 = rocket_db_pools::sqlx::query(
+            r#"
+            INSERT INTO orders (cust_id, total_cents)
+            VALUES ($1, $2)
+            RETURNING order_id
+            "#,
+        )
+        .bind(cust_id)
+        .bind(total.cents())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| failure(Status::InternalServerError, format!("Failed to insert order: {e}")))?
+        .try_get("order_id")
+        .map_err(|e| failure(Status::InternalServerError, format!("Failed to get order_id: {e}")))?;
+
+        // Step 5: Insert ordered products
+        for item in &items {
+            rocket_db_pools::sqlx::query(
+                r#"
+                INSERT INTO ordered_products (product_id, var_id, order_id, quantity, unit_price_cents)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+            )
+            .bind(item.product_id)
+            .bind(item.variant) // This can be NULL
+            .bind(order_id)
+            .bind(item.quantity)
+            .bind(item.unit_price.cents())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| failure(Status::InternalServerError, format!("Failed to insert ordered product: {e}")))?;
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        // Step 6: Return the order_id
+        Ok(Json(order_id)) // Return the generated order_id
+    }
+
+    /// Deprecated: use `GET /api/products/<id>`.
+    #[allow(private_interfaces)]
+    #[get("/get_product_details?<name>")]
+    pub(super) async fn get_product_details(
+        mut db: Connection<RoboDatabase>,
+        name: String,
+    ) -> Deprecated<Result<Json<Product>, Custom<Json<ResponseData>>>> {
+        let product_id = match product_id_by_name(&mut db, &name).await {
+            Ok(product_id) => product_id,
+            Err(error) => return Deprecated::unlinked(Err(error)),
+        };
+        let result = product_by_id(&mut db, product_id).await.map(Json);
+        Deprecated::new(result, format!("/api/products/{product_id}"))
+    }
+
+    #[allow(private_interfaces)]
+    #[get("/get_variant_details?<name>")]
+    pub(super) async fn get_variant_details(
+        mut db: Connection<RoboDatabase>,
+        name: String,
+    ) -> Result<Json<ProductVariant>, Custom<Json<ResponseData>>> {
+        // Despite its name, `name` is the variant's id
+        let row = rocket_db_pools::sqlx::query(
+            "SELECT * FROM product_variants WHERE var_id = $1"
+        )
+        .bind(name)
+        .fetch_optional(&mut **db)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| failure(Status::NotFound, "Variant not found."))?;
+
+        let mut product = ProductVariant::try_from(row)
+            .map_err(|e| failure(Status::InternalServerError, e))?;
+        load_variant_attributes(&mut db, &mut product)
+            .await
+            .map_err(db_error)?;
+        load_variant_images(&mut db, &mut product)
+            .await
+            .map_err(db_error)?;
+
+        // If the query succeeds, return the product variant in JSON format
+        Ok(Json(product))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rocket::http::Header;
+        use rocket::local::asynchronous::{Client, LocalResponse};
+
+        #[test]
+        fn new_hashes_are_argon2id_and_verify() {
+            let (salt, hash) = hash_password("correct horse").unwrap();
+            assert!(hash.starts_with("$argon2id$"));
+            assert!(hash.contains(&salt));
+            assert_eq!(verify_password("correct horse", &hash, &salt), PasswordCheck::Valid);
+            assert_eq!(verify_password("wrong horse", &hash, &salt), PasswordCheck::Invalid);
+        }
+
+        #[test]
+        fn legacy_sha256_hashes_verify_and_need_rehash() {
+            let salt = "QsGeak8O7UBYl4yx";
+            let legacy = sha256_hex(&format!("hunter2{salt}"));
+            assert_eq!(legacy.len(), 64);
+            assert_eq!(verify_password("hunter2", &legacy, salt), PasswordCheck::NeedsRehash);
+            assert_eq!(verify_password("hunter3", &legacy, salt), PasswordCheck::Invalid);
+            assert_eq!(verify_password("hunter2", &legacy, "other salt"), PasswordCheck::Invalid);
+        }
+
+        #[test]
+        fn weaker_argon2_parameters_need_rehash() {
+            let salt = SaltString::generate(&mut OsRng);
+            let weak = Argon2::new(
+                argon2::Algorithm::Argon2id,
+                argon2::Version::V0x13,
+                Params::new(1024, 1, 1, None).unwrap(),
+            )
+            .hash_password(b"pw", &salt)
+            .unwrap()
+            .to_string();
+            assert_eq!(verify_password("pw", &weak, salt.as_str()), PasswordCheck::NeedsRehash);
+        }
+
+        #[test]
+        fn usernames_back_off_and_addresses_only_lock_out() {
+            let seconds = |failures| block_duration("username", failures).map(|d| d.num_seconds());
+            assert_eq!((1..MAX_USERNAME_FAILURES).map(seconds).collect::<Vec<_>>(), [Some(1), Some(2), Some(4), Some(8)]);
+            assert_eq!(block_duration("username", MAX_USERNAME_FAILURES), Some(LOCKOUT_DURATION));
+            assert_eq!(block_duration("ip", MAX_IP_FAILURES - 1), None);
+            assert_eq!(block_duration("ip", MAX_IP_FAILURES), Some(LOCKOUT_DURATION));
+        }
+
+        #[test]
+        fn expiration_must_be_a_future_date() {
+            let today = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+            assert_eq!(
+                parse_expiration("2025-05-15", today),
+                Ok(NaiveDate::from_ymd_opt(2025, 5, 15).unwrap())
+            );
+            assert!(parse_expiration("2024-12-01", today).is_err());
+            assert!(parse_expiration("2024-11-30", today).is_err());
+            assert!(parse_expiration("2025-02-30", today).is_err());
+            assert!(parse_expiration("next tuesday", today).is_err());
+        }
+
+        #[test]
+        fn expiry_status_follows_the_warning_window() {
+            let expiration = NaiveDate::from_ymd_opt(2025, 5, 15).unwrap();
+            let day = |d| NaiveDate::from_ymd_opt(2025, 5, 1).unwrap() + Duration::days(d);
+            assert_eq!(expiry_status(expiration, day(-1)), "active");
+            assert_eq!(expiry_status(expiration, day(0)), "expiring");
+            assert_eq!(expiry_status(expiration, day(14)), "expiring");
+            assert_eq!(expiry_status(expiration, day(15)), "disabled");
+        }
+
+        #[rocket::async_test]
+        async fn expiry_sweep_only_moves_statuses_forward() {
+            let pool = rocket_db_pools::sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            run_migrations(&pool).await.unwrap();
+            let today = Utc::now().date_naive();
+            let date = |days| (today + Duration::days(days)).format("%Y-%m-%d").to_string();
+            for (username, expiration, status) in [
+                ("soon", date(5), "active"),
+                ("gone", date(-1), "expiring"),
+                ("banned", date(300), "disabled"),
+                ("extended", date(300), "expiring"),
+            ] {
+                rocket_db_pools::sqlx::query(
+                    "INSERT INTO admins (username, salt, password, expiration, status) VALUES (?1, ?1, '', ?2, ?3)",
+                )
+                .bind(username)
+                .bind(expiration)
+                .bind(status)
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO sessions (username, token_hash, created_at, last_seen, expires_at)
+                VALUES ('gone', 'hash', '', '', '2999-01-01T00:00:00Z')",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            sweep_admin_expiry(&pool).await.unwrap();
+            let statuses: Vec<(String, String)> = rocket_db_pools::sqlx::query("SELECT username, status FROM admins ORDER BY username")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| (row.get("username"), row.get("status")))
+                .collect();
+            let expected = [("banned", "disabled"), ("extended", "active"), ("gone", "disabled"), ("soon", "expiring")];
+            assert_eq!(statuses, expected.map(|(username, status)| (username.to_string(), status.to_string())));
+            let sessions: i64 = rocket_db_pools::sqlx::query("SELECT COUNT(*) AS n FROM sessions")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get("n");
+            assert_eq!(sessions, 0);
+
+            // Each change is audited, and a second sweep has nothing to do
+            let audited = || async {
+                rocket_db_pools::sqlx::query("SELECT target FROM audit_log WHERE actor = 'expiry-sweep' ORDER BY target")
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|row| row.get::<String, _>("target"))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(audited().await, ["extended", "gone", "soon"]);
+            sweep_admin_expiry(&pool).await.unwrap();
+            assert_eq!(audited().await.len(), 3);
+        }
+
+        #[rocket::async_test]
+        async fn migrations_build_a_fresh_database_once() {
+            let pool = rocket_db_pools::sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+
+            let applied = run_migrations(&pool).await.unwrap();
+            assert_eq!(applied, MIGRATIONS.iter().map(|(v, ..)| *v).collect::<Vec<_>>());
+            assert!(run_migrations(&pool).await.unwrap().is_empty());
+
+            let tables: Vec<String> = rocket_db_pools::sqlx::query(
+                "SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+            for table in ["admins", "audit_log", "products", "schema_version", "sessions"] {
+                assert!(tables.iter().any(|t| t == table), "missing table {table}");
+            }
+        }
+
+        #[test]
+        fn bootstrap_args() {
+            let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            assert_eq!(
+                parse_bootstrap_args(&args(&["--username", "alex", "--expiration=2030-05-01"])),
+                Ok(BootstrapOptions {
+                    username: "alex".into(),
+                    expiration: Some("2030-05-01".into()),
+                })
+            );
+            assert!(parse_bootstrap_args(&args(&[])).is_err());
+            assert!(parse_bootstrap_args(&args(&["--username"])).is_err());
+            assert!(parse_bootstrap_args(&args(&["--username", "alex", "--admin"])).is_err());
+        }
+
+        #[test]
+        fn slugs_are_url_friendly() {
+            assert_eq!(slugify("T Shirt"), "t-shirt");
+            assert_eq!(slugify("  Robot's Hat / Cap!  "), "robots-hat-cap");
+            assert_eq!(slugify("Nuts & Bolts"), "nuts-and-bolts");
+            assert_eq!(slugify("2025"), "product-2025");
+            assert_eq!(slugify("???"), "product");
+        }
+
+        #[test]
+        fn attribute_values_list_everything_rejected() {
+            let values = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            assert_eq!(
+                clean_attribute_values(&values(&[" 12V ", "24V"]), &values(&["5V"])).ok().unwrap(),
+                ["12V", "24V"]
+            );
+
+            let error = clean_attribute_values(&values(&["5v", "", "12V", "12v"]), &values(&["5V"]))
+                .err()
+                .unwrap();
+            assert_eq!(error.0, Status::BadRequest);
+            assert_eq!(
+                error.1 .0.message,
+                "Rejected values: `5v` is already allowed; a blank value; `12v` is listed twice."
+            );
+        }
+
+        #[test]
+        fn variants_resolve_to_exactly_one() {
+            let map = |pairs: &[(&str, &str)]| {
+                pairs
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect::<BTreeMap<_, _>>()
+            };
+            let shirts = [
+                (1, map(&[("Size", "large"), ("Color", "white")])),
+                (2, map(&[("Size", "xlarge"), ("Color", "white")])),
+                (3, map(&[("Size", "large"), ("Color", "black")])),
+            ];
+            let status = |result: Result<Option<u32>, Custom<Json<ResponseData>>>| result.err().unwrap().0;
+
+            assert_eq!(pick_variant(&shirts, &map(&[("size", "Large"), ("Color", "white")])).ok(), Some(Some(1)));
+            assert_eq!(status(pick_variant(&shirts, &map(&[("Size", "xlarge")]))), Status::NotFound);
+            assert_eq!(status(pick_variant(&shirts, &map(&[("Size", "small")]))), Status::NotFound);
+            assert_eq!(status(pick_variant(&shirts, &map(&[("Material", "cotton")]))), Status::BadRequest);
+            assert_eq!(status(pick_variant(&shirts, &map(&[("Size", "large"), ("size", "large")]))), Status::BadRequest);
+
+            let error = pick_variant(&shirts, &map(&[("Size", "large")])).err().unwrap();
+            assert_eq!(error.0, Status::Conflict);
+            assert_eq!(error.1 .0.message, "2 variants match. Also choose: Color.");
+
+            // Products with a single attribute, or none at all
+            let lamps = [(7, map(&[("Voltage", "12V")]))];
+            assert_eq!(pick_variant(&lamps, &map(&[("Voltage", "12v")])).ok(), Some(Some(7)));
+            assert_eq!(status(pick_variant(&lamps, &map(&[]))), Status::NotFound);
+            assert_eq!(pick_variant(&[], &map(&[])).ok(), Some(None));
+            let stickers = [(8, map(&[]))];
+            assert_eq!(pick_variant(&stickers, &map(&[])).ok(), Some(Some(8)));
+
+            // Only the full set of attributes picks a variant
+            let mugs = [(9, map(&[("Size", "S"), ("Color", "Red")]))];
+            let error = pick_variant(&mugs, &map(&[("Size", "S")])).err().unwrap();
+            assert_eq!(error.0, Status::NotFound);
+            assert_eq!(error.1 .0.message, "No variant of this product has exactly Size=S. Also choose: Color.");
+            assert_eq!(pick_variant(&mugs, &map(&[("size", "s"), ("color", "red")])).ok(), Some(Some(9)));
+            assert_eq!(status(pick_variant(&[], &map(&[("Size", "large")]))), Status::NotFound);
+        }
+
+        /// A 1x1 PNG.
+        const TINY_PNG: &[u8] = &[
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90, 0x77, 0x53,
+            0xde, 0x00, 0x00, 0x00, 0x0c, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x38, 0xc3, 0xc0, 0x00,
+            0x00, 0x02, 0x68, 0x00, 0xcd, 0x25, 0xe2, 0x9c, 0x6e, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e,
+            0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+
+        /// A directory of a test's own, deleted when dropped, so it goes even
+        /// when an assertion fails.
+        struct TempDir(PathBuf);
+
+        impl TempDir {
+            fn new(name: &str) -> Self {
+                let dir = std::env::temp_dir().join(format!("{name}-{}", Uuid::new_v4()));
+                std::fs::create_dir_all(&dir).unwrap();
+                TempDir(dir)
+            }
+        }
+
+        impl std::ops::Deref for TempDir {
+            type Target = std::path::Path;
+
+            fn deref(&self) -> &std::path::Path {
+                &self.0
+            }
+        }
+
+        impl Drop for TempDir {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_dir_all(&self.0);
+            }
+        }
+
+        /// The whole app, served from a fresh database and image directory.
+        struct TestApp {
+            client: Client,
+            /// The CSRF header to send with changes.
+            csrf: Header<'static>,
+            // Declared last, so the app is shut down before its files are deleted
+            dir: TempDir,
+        }
+
+        impl TestApp {
+            async fn new(name: &str) -> Self {
+                let dir = TempDir::new(name);
+                let (client, csrf) = Self::connect(&dir).await;
+                TestApp { client, csrf, dir }
+            }
+
+            /// A client of the app in `dir`, with its CSRF header.
+            async fn connect(dir: &std::path::Path) -> (Client, Header<'static>) {
+                let path = dir.join("db.sqlite");
+                std::fs::OpenOptions::new().create(true).append(true).open(&path).unwrap();
+                let figment = rocket::Config::figment()
+                    .merge(("databases.db.url", path.to_str().unwrap()))
+                    .merge(("image_dir", dir.join("images")))
+                    // Tests run the sweeps themselves, so none races their own writes
+                    .merge(("expiry_sweep", false))
+                    .merge(("log_level", "off"));
+                let client = Client::tracked(crate::rocket().configure(figment)).await.unwrap();
+
+                // The first request hands out the CSRF cookie
+                client.get("/api/get_items").dispatch().await;
+                let token = client.cookies().get(CSRF_COOKIE).unwrap().value().to_string();
+                (client, Header::new(CSRF_HEADER, token))
+            }
+
+            /// Another browser on the same database, with cookies of its own.
+            async fn other_client(&self) -> (Client, Header<'static>) {
+                Self::connect(&self.dir).await
+            }
+
+            fn pool(&self) -> &SqlitePool {
+                RoboDatabase::fetch(self.client.rocket()).unwrap()
+            }
+
+            /// Sends `body` as JSON, with the CSRF header.
+            async fn send(&self, method: Method, uri: impl fmt::Display, body: Value) -> LocalResponse<'_> {
+                self.client
+                    .req(method, uri.to_string())
+                    .header(self.csrf.clone())
+                    .json(&body)
+                    .dispatch()
+                    .await
+            }
+
+            async fn post(&self, uri: impl fmt::Display, body: Value) -> LocalResponse<'_> {
+                self.send(Method::Post, uri, body).await
+            }
+
+            /// Reads the JSON at `uri`.
+            async fn get<T: serde::de::DeserializeOwned + Send + 'static>(&self, uri: impl fmt::Display) -> T {
+                self.client.get(uri.to_string()).dispatch().await.into_json().await.unwrap()
+            }
+
+            /// Uploads raw bytes, as `/api/images` takes them.
+            async fn upload(&self, uri: impl fmt::Display, body: impl AsRef<[u8]>) -> LocalResponse<'_> {
+                self.client.post(uri.to_string()).header(self.csrf.clone()).body(body).dispatch().await
+            }
+
+            async fn add_admin(&self, username: &str, password: &str, permissions: &[&str]) {
+                let (salt, hash) = hash_password(password).unwrap();
+                rocket_db_pools::sqlx::query(
+                    "INSERT INTO admins (username, salt, password, expiration) VALUES (?, ?, ?, '2999-12-31')",
+                )
+                .bind(username)
+                .bind(salt)
+                .bind(hash)
+                .execute(self.pool())
+                .await
+                .unwrap();
+                for permission in permissions {
+                    rocket_db_pools::sqlx::query("INSERT INTO permissions (username, permission) VALUES (?, ?)")
+                        .bind(username)
+                        .bind(permission)
+                        .execute(self.pool())
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+
+        /// Posts a form, as the admin pages do.
+        async fn submit<'c>(
+            client: &'c Client,
+            csrf: &Header<'static>,
+            uri: &'static str,
+            fields: &[(&str, &str)],
+        ) -> LocalResponse<'c> {
+            let body = fields
+                .iter()
+                .map(|(name, value)| format!("{name}={}", RawStr::new(value).percent_encode()))
+                .collect::<Vec<_>>()
+                .join("&");
+            client
+                .post(uri)
+                .header(csrf.clone())
+                .header(rocket::http::ContentType::Form)
+                .body(body)
+                .dispatch()
+                .await
+        }
+
+        /// Logs `client` in with a password.
+        async fn log_in<'c>(
+            client: &'c Client,
+            csrf: &Header<'static>,
+            username: &str,
+            password: &str,
+        ) -> LocalResponse<'c> {
+            submit(client, csrf, "/api/login", &[("username", username), ("password", password)]).await
+        }
+
+        /// The app, logged in as an admin who can edit the inventory.
+        async fn inventory_client(name: &str) -> TestApp {
+            let app = TestApp::new(name).await;
+            app.add_admin("tester", "round trip", &["inventory.write"]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "tester", "round trip").await.status(), Status::Ok);
+            app
+        }
+
+        /// A `/api/create_order` body, as checkout sends it.
+        fn order_body() -> Value {
+            serde_json::json!({
+                "customer": {
+                    "cust_id": null, "name": "Flash", "email": "flash@kent.edu", "phone_number": null,
+                    "address": {
+                        "address_line_1": "800 E Summit St", "admin_area_2": "Kent",
+                        "admin_area_1": "OH", "postal_code": "44242", "country_code": "US",
+                    },
+                },
+            })
+        }
+
+        #[rocket::async_test]
+        async fn routes_require_their_permission() {
+            let app = &inventory_client("permissions").await;
+            let manage = |method: Method| app.send(method, "/api/admins/tester/permissions/admins.manage", Value::Null);
+
+            // Logged in, but without the permission
+            let response = app.client.get("/api/admins/tester/permissions").dispatch().await;
+            assert_eq!(response.status(), Status::Forbidden);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "You do not have permission to do that.");
+            assert_eq!(manage(Method::Post).await.status(), Status::Forbidden);
+            // Not logged in at all
+            let (stranger, _) = app.other_client().await;
+            let response = stranger.get("/api/admins/tester/permissions").dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+
+            // Permissions are read on every request, so a grant takes effect at once
+            rocket_db_pools::sqlx::query("INSERT INTO permissions (username, permission) VALUES ('tester', 'admins.manage')")
+                .execute(app.pool())
+                .await
+                .unwrap();
+            let held: Vec<String> = app.get("/api/admins/tester/permissions").await;
+            assert_eq!(held, ["admins.manage", "inventory.write"]);
+
+            // The only manager can't give it up, but can once there's another
+            assert_eq!(manage(Method::Delete).await.status(), Status::Conflict);
+            app.add_admin("boss", "big boss", &["admins.manage"]).await;
+            // ...who has to be able to log in: not disabled, and not expired ahead of the sweep
+            for change in [
+                "UPDATE admins SET status = 'disabled' WHERE username = 'boss'",
+                "UPDATE admins SET status = 'active', expiration = '2000-01-01' WHERE username = 'boss'",
+            ] {
+                rocket_db_pools::sqlx::query(change).execute(app.pool()).await.unwrap();
+                assert_eq!(manage(Method::Delete).await.status(), Status::Conflict);
+            }
+            rocket_db_pools::sqlx::query("UPDATE admins SET expiration = '2999-12-31' WHERE username = 'boss'")
+                .execute(app.pool())
+                .await
+                .unwrap();
+            assert_eq!(manage(Method::Delete).await.status(), Status::Ok);
+            assert_eq!(manage(Method::Post).await.status(), Status::Forbidden);
+        }
 
-        let rows = rocket_db_pools::sqlx::query(query)
-            .fetch_all(&mut **db)
+        #[rocket::async_test]
+        async fn admins_are_created_and_deleted_safely() {
+            let app = &TestApp::new("admins").await;
+            app.add_admin("boss", "big boss", &["admins.manage"]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "boss", "big boss").await.status(), Status::Ok);
+            let delete = |username: &str| app.send(Method::Delete, format!("/api/delete_admin/{username}"), Value::Null);
+            let alex = |expiration| [
+                ("username", "alex"), ("password", "long enough"),
+                ("expiration", expiration), ("permissions", "admins.manage"),
+            ];
+
+            let response = submit(&app.client, &app.csrf, "/api/create_admin", &alex("2000-01-01")).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Expiration date must be in the future.");
+            assert_eq!(delete("alex").await.status(), Status::NotFound);
+
+            // The only manager can't delete themselves
+            let response = delete("boss").await;
+            assert_eq!(response.status(), Status::Conflict);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Can't delete the last admin who can manage admins.");
+
+            // With another manager around, deleting yourself is still refused
+            let response = submit(&app.client, &app.csrf, "/api/create_admin", &alex("2999-12-31")).await;
+            assert_eq!(response.status(), Status::Ok);
+            let response = delete("boss").await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "You can't delete your own account.");
+
+            assert_eq!(delete("alex").await.status(), Status::Ok);
+            assert_eq!(delete("alex").await.status(), Status::NotFound);
+            assert_eq!(delete("boss").await.status(), Status::Conflict);
+        }
+
+        #[rocket::async_test]
+        async fn extending_a_disabled_admin_lets_them_back_in() {
+            let app = &TestApp::new("extend").await;
+            app.add_admin("boss", "big boss", &["admins.manage"]).await;
+            app.add_admin("ada", "lovelace", &["inventory.write"]).await;
+            let yesterday = (Utc::now().date_naive() - Duration::days(1)).format("%Y-%m-%d").to_string();
+            rocket_db_pools::sqlx::query("UPDATE admins SET expiration = ?, status = 'disabled' WHERE username = 'ada'")
+                .bind(&yesterday)
+                .execute(app.pool())
+                .await
+                .unwrap();
+            let (ada, ada_csrf) = app.other_client().await;
+            let response = log_in(&ada, &ada_csrf, "ada", "lovelace").await;
+            assert_eq!(response.status(), Status::Unauthorized);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], format!("Admin account expired on {yesterday}. Ask an admin to extend it."));
+
+            assert_eq!(log_in(&app.client, &app.csrf, "boss", "big boss").await.status(), Status::Ok);
+            let statuses = || async {
+                app.get::<Vec<Value>>("/api/admins/expiring")
+                    .await
+                    .iter()
+                    .map(|admin| (admin["username"].as_str().unwrap().to_string(), admin["status"].as_str().unwrap().to_string()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(statuses().await, [("ada".to_string(), "disabled".to_string())]);
+
+            // Nothing changes unless every admin is found and the date is in the future
+            let extend = |usernames: &[&'static str], expiration: &'static str| {
+                let mut fields: Vec<(&str, &str)> = usernames.iter().map(|username| ("usernames", *username)).collect();
+                fields.push(("expiration", expiration));
+                async move { submit(&app.client, &app.csrf, "/api/admins/extend", &fields).await.status() }
+            };
+            assert_eq!(extend(&["ada", "nobody"], "2999-12-31").await, Status::NotFound);
+            assert_eq!(extend(&["ada"], "2000-01-01").await, Status::BadRequest);
+            assert_eq!(statuses().await, [("ada".to_string(), "disabled".to_string())]);
+
+            assert_eq!(extend(&["ada"], "2999-12-31").await, Status::Ok);
+            assert!(statuses().await.is_empty());
+            let audited: i64 = rocket_db_pools::sqlx::query(
+                "SELECT COUNT(*) AS n FROM audit_log WHERE action = 'admin.extend' AND target = 'ada'",
+            )
+            .fetch_one(app.pool())
             .await
-            .map_err(|_| Status::InternalServerError)?;
+            .unwrap()
+            .get("n");
+            assert_eq!(audited, 1);
 
-        let customers: Vec<Customer> = rows
-            .into_iter()
-            .filter_map(|row| {
-                let cust_id: Option<i32> = row.get("cust_id");
-                let name: String = row.get("name");
-                let address_str: String = row.get("address");
-                let email: String = row.get("email");
-                let phone_number: Option<String> = row.get("phone_number");
+            // They can log in again, and the session works
+            assert_eq!(log_in(&ada, &ada_csrf, "ada", "lovelace").await.status(), Status::Ok);
+            assert_eq!(ada.get("/api/sessions").dispatch().await.status(), Status::Ok);
+        }
 
-                 // Parse the formatted address string
-                let address_parts: Vec<&str> = address_str.split(',').collect();
-                if address_parts.len() == 5 {
-                    let address = Address {
-                        address_line_1: address_parts[0].trim().to_string(),
-                        admin_area_2: address_parts[1].trim().to_string(),
-                        admin_area_1: address_parts[2].trim().to_string(),
-                        postal_code: address_parts[3].trim().to_string(),
-                        country_code: address_parts[4].trim().to_string(),
-                    };
+        #[rocket::async_test]
+        async fn changes_need_the_csrf_token() {
+            let app = &inventory_client("csrf").await;
+            let body = serde_json::json!({"name": "Pin", "desc": "", "price": 1, "quantity": 1});
+            let add = |csrf: Option<Header<'static>>| {
+                let mut request = app.client.post("/api/add_product").json(&body);
+                if let Some(csrf) = csrf {
+                    request = request.header(csrf);
+                }
+                request.dispatch()
+            };
+
+            let response = add(None).await;
+            assert_eq!(response.status(), Status::Forbidden);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Missing or invalid CSRF token.");
+            let forged = Header::new(CSRF_HEADER, "not the cookie");
+            assert_eq!(add(Some(forged)).await.status(), Status::Forbidden);
+            assert!(app.get::<Vec<Value>>("/api/get_items").await.is_empty());
+
+            assert_eq!(add(Some(app.csrf.clone())).await.status(), Status::Ok);
+            assert_eq!(app.get::<Vec<Value>>("/api/get_items").await.len(), 1);
+
+            // Every change is a POST, so a link or image on another page can't make one
+            assert_eq!(app.client.get("/api/additem/Badge").dispatch().await.status(), Status::NotFound);
+            assert_eq!(app.client.post("/api/additem/Badge").dispatch().await.status(), Status::Forbidden);
+            assert_eq!(app.post("/api/additem/Badge", Value::Null).await.status(), Status::Ok);
+            assert_eq!(app.get::<Vec<Value>>("/api/get_items").await.len(), 2);
+
+            // Only the API is checked, so elsewhere a request gets the usual answer
+            let response = app.client.post("/products.html").dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
 
-                    Some(Customer {
-                        cust_id,
-                        name,
-                        address,
-                        email,
-                        phone_number,
-                    })
+        #[rocket::async_test]
+        async fn failed_logins_back_off_then_lock_out() {
+            let app = &TestApp::new("lockout").await;
+            app.add_admin("boss", "big boss!", &["admins.manage"]).await;
+            app.add_admin("alex", "correct horse", &[]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "boss", "big boss!").await.status(), Status::Ok);
+            let (other, csrf) = app.other_client().await;
+            let attempt = |username: &'static str, password: &'static str| {
+                let (other, csrf) = (&other, &csrf);
+                async move {
+                    let response = log_in(other, csrf, username, password).await;
+                    (response.status(), response.into_json::<Value>().await.unwrap())
+                }
+            };
+            // Stands in for waiting out the backoff between attempts
+            let wait = || {
+                rocket_db_pools::sqlx::query("UPDATE login_attempts SET locked_until = NULL WHERE failures < ?")
+                    .bind(MAX_USERNAME_FAILURES)
+                    .execute(app.pool())
+            };
+
+            // A wrong password looks just like an unknown username
+            let unknown = attempt("nobody", "correct horse").await;
+            let wrong = attempt("alex", "wrong horse").await;
+            assert_eq!(unknown, wrong);
+            assert_eq!(wrong.0, Status::Unauthorized);
+            assert_eq!(wrong.1["message"], "Invalid username or password.");
+
+            // Each failure blocks the username for a while, even for the right password
+            assert_eq!(attempt("alex", "correct horse").await.0, Status::TooManyRequests);
+            for _ in 1..MAX_USERNAME_FAILURES {
+                wait().await.unwrap();
+                assert_eq!(attempt("alex", "wrong horse").await.0, Status::Unauthorized);
+            }
+            wait().await.unwrap();
+            let (status, error) = attempt("alex", "correct horse").await;
+            assert_eq!(status, Status::TooManyRequests);
+            assert!(error["message"].as_str().unwrap().starts_with("Too many failed login attempts."));
+            let lockouts: Vec<Value> = app.get("/api/lockouts").await;
+            let alex = lockouts.iter().find(|lockout| lockout["key"] == "alex").unwrap();
+            assert_eq!((alex["failures"].as_i64(), &alex["locked"]), (Some(MAX_USERNAME_FAILURES), &Value::Bool(true)));
+
+            // A manager can clear the lockout
+            assert_eq!(app.send(Method::Delete, "/api/lockouts/username/Alex", Value::Null).await.status(), Status::Ok);
+            assert_eq!(attempt("alex", "correct horse").await.0, Status::Ok);
+            assert_eq!(app.send(Method::Delete, "/api/lockouts/username/alex", Value::Null).await.status(), Status::NotFound);
+        }
 
-                }else{ None }
-            })
+        #[rocket::async_test]
+        async fn passwords_change_with_the_old_one_or_a_single_use_token() {
+            let app = &TestApp::new("passwords").await;
+            app.add_admin("boss", "big boss!", &["admins.manage"]).await;
+            app.add_admin("alex", "correct horse", &[]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "boss", "big boss!").await.status(), Status::Ok);
+            let (alex, csrf) = &app.other_client().await;
+            assert_eq!(log_in(alex, csrf, "alex", "correct horse").await.status(), Status::Ok);
+            let change = |current: &'static str, new: &'static str| async move {
+                submit(alex, csrf, "/api/change_password", &[("current_password", current), ("new_password", new)]).await
+            };
+            let reset = |token: String| async move {
+                let fields = [("token", token.as_str()), ("new_password", "battery staple")];
+                submit(alex, csrf, "/api/reset_password", &fields).await.status()
+            };
+            let issue = || async {
+                let response = app.post("/api/admins/alex/password_reset", Value::Null).await;
+                response.into_json::<Value>().await.unwrap()["token"].as_str().unwrap().to_string()
+            };
+
+            // Changing a password takes the current one
+            let response = change("wrong horse", "battery staple").await;
+            assert_eq!(response.status(), Status::Unauthorized);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Current password is incorrect.");
+            // A wrong guess counts as a failed login; this waits out its backoff
+            let forget_failures = || rocket_db_pools::sqlx::query("DELETE FROM login_attempts").execute(app.pool());
+            forget_failures().await.unwrap();
+            assert_eq!(change("correct horse", "short").await.status(), Status::BadRequest);
+            assert_eq!(change("correct horse", "horse correct").await.status(), Status::Ok);
+            assert_eq!(log_in(alex, csrf, "alex", "correct horse").await.status(), Status::Unauthorized);
+            forget_failures().await.unwrap();
+
+            // A newer token replaces an older one, and each works once
+            let stale = issue().await;
+            let token = issue().await;
+            assert_eq!(reset(stale).await, Status::BadRequest);
+            assert_eq!(reset(token.clone()).await, Status::Ok);
+            assert_eq!(reset(token).await, Status::BadRequest);
+            // Resetting logs the admin out everywhere
+            assert_eq!(alex.get("/api/sessions").dispatch().await.status(), Status::Unauthorized);
+            assert_eq!(log_in(alex, csrf, "alex", "battery staple").await.status(), Status::Ok);
+            let used: Vec<String> = rocket_db_pools::sqlx::query(
+                "SELECT actor FROM audit_log WHERE action = 'admin.password_reset_used' AND target = 'alex'",
+            )
+            .fetch_all(app.pool())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("actor"))
             .collect();
+            assert_eq!(used, ["alex"]);
 
-        Ok(Json(customers))
-    }
+            // Tokens expire
+            let token = issue().await;
+            rocket_db_pools::sqlx::query("UPDATE password_resets SET expires_at = '2000-01-01T00:00:00Z'")
+                .execute(app.pool())
+                .await
+                .unwrap();
+            assert_eq!(reset(token).await, Status::BadRequest);
+        }
 
-    // Fetch orders for a specific customer
-    #[allow(private_interfaces)]
-    #[get("/getcustomerorders/<cust_id>")]
-    pub(super) async fn get_customer_orders(
-        cust_id: i32,
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<Vec<Order>>, Status> {
-        let query = "SELECT order_id FROM orders WHERE cust_id = ?";
-        let rows = rocket_db_pools::sqlx::query(query)
-            .bind(cust_id)
-            .fetch_all(&mut **db)
-            .await
-            .map_err(|_| Status::InternalServerError)?;
+        #[rocket::async_test]
+        async fn audit_log_pages_filters_and_cannot_be_rewritten() {
+            let app = &TestApp::new("audit").await;
+            app.add_admin("auditor", "read it all", &["audit.read"]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "auditor", "read it all").await.status(), Status::Ok);
+            for (actor, action, target, created_at) in [
+                ("alex", "product.create", "mug", "2024-03-01T09:00:00Z"),
+                ("alex", "product.delete", "mug", "2024-03-02T09:00:00Z"),
+                ("sam", "product.create", "lamp", "2024-03-02T23:59:59Z"),
+                ("sam", "admin.create", "alex", "2024-03-03T00:00:00Z"),
+                ("alex", "productive.day", "lamp", "2024-03-04T09:00:00Z"),
+            ] {
+                rocket_db_pools::sqlx::query(
+                    "INSERT INTO audit_log (actor, action, target, created_at) VALUES (?, ?, ?, ?)",
+                )
+                .bind(actor)
+                .bind(action)
+                .bind(target)
+                .bind(created_at)
+                .execute(app.pool())
+                .await
+                .unwrap();
+            }
+            let targets = |uri: &'static str| async move {
+                let page: Value = app.get(uri).await;
+                let entries = page["entries"].as_array().unwrap().iter();
+                (page["total"].as_i64().unwrap(), entries.map(|e| e["target"].as_str().unwrap().to_string()).collect::<Vec<_>>())
+            };
+
+            // Newest first, a page at a time
+            assert_eq!(targets("/api/audit?per_page=2").await, (5, vec!["lamp".into(), "alex".into()]));
+            assert_eq!(targets("/api/audit?per_page=2&page=2").await, (5, vec!["lamp".into(), "mug".into()]));
+            assert_eq!(targets("/api/audit?per_page=2&page=4").await, (5, vec![]));
+
+            // Filters combine, and an action group doesn't match a longer prefix
+            assert_eq!(targets("/api/audit?actor=alex&target=mug").await.0, 2);
+            assert_eq!(targets("/api/audit?action=product").await.0, 3);
+            assert_eq!(targets("/api/audit?action=product.create").await.0, 2);
+            assert_eq!(targets("/api/audit?since=2024-03-02&until=2024-03-02").await.0, 2);
+            assert_eq!(targets("/api/audit?since=2024-03-03").await.0, 2);
+            let response = app.client.get("/api/audit?since=March").dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest);
+
+            // Entries can't be edited or removed, even directly in the database
+            let update = rocket_db_pools::sqlx::query("UPDATE audit_log SET actor = 'nobody'").execute(app.pool()).await;
+            assert!(update.unwrap_err().to_string().contains("audit_log is append-only"));
+            let delete = rocket_db_pools::sqlx::query("DELETE FROM audit_log").execute(app.pool()).await;
+            assert!(delete.unwrap_err().to_string().contains("audit_log is append-only"));
+            assert_eq!(targets("/api/audit?actor=alex").await.0, 3);
+        }
 
-        let mut orders = Vec::new();
-        for row in rows {
-            let order_id: i32 = row.get("order_id");
+        #[rocket::async_test]
+        async fn sessions_slide_and_can_be_ended_everywhere() {
+            let app = &inventory_client("sessions").await;
+            let (laptop, laptop_csrf) = app.other_client().await;
+            assert_eq!(log_in(&laptop, &laptop_csrf, "tester", "round trip").await.status(), Status::Ok);
+            let sessions = || app.get::<Vec<Value>>("/api/sessions");
+            let laptop_status = || async { laptop.get("/api/sessions").dispatch().await.status() };
+
+            // Each device has a session of its own
+            let listed = sessions().await;
+            assert_eq!(listed.len(), 2);
+            assert_eq!(listed.iter().filter(|session| session["current"] == true).count(), 1);
+
+            // Using a session pushes its expiry forward, up to its maximum lifetime
+            let soon = timestamp(Utc::now() + Duration::minutes(1));
+            rocket_db_pools::sqlx::query("UPDATE sessions SET expires_at = ?")
+                .bind(&soon)
+                .execute(app.pool())
+                .await
+                .unwrap();
+            let current = sessions().await.into_iter().find(|session| session["current"] == true).unwrap();
+            assert!(current["expires_at"].as_str().unwrap() > soon.as_str());
+            rocket_db_pools::sqlx::query("UPDATE sessions SET created_at = ? WHERE session_id != ?")
+                .bind(timestamp(Utc::now() - SESSION_MAX_LIFETIME))
+                .bind(current["session_id"].as_i64())
+                .execute(app.pool())
+                .await
+                .unwrap();
+            assert_eq!(laptop_status().await, Status::Ok);
+            assert_eq!(laptop_status().await, Status::Unauthorized);
+            assert_eq!(log_in(&laptop, &laptop_csrf, "tester", "round trip").await.status(), Status::Ok);
+
+            // One device can end another's session
+            let laptop_session = sessions().await.into_iter().find(|session| session["current"] == false).unwrap();
+            let uri = format!("/api/sessions/{}", laptop_session["session_id"]);
+            assert_eq!(app.send(Method::Delete, &uri, Value::Null).await.status(), Status::Ok);
+            assert_eq!(laptop_status().await, Status::Unauthorized);
+            assert_eq!(app.send(Method::Delete, &uri, Value::Null).await.status(), Status::NotFound);
+
+            // Logging out of all devices ends every session, including this one
+            assert_eq!(log_in(&laptop, &laptop_csrf, "tester", "round trip").await.status(), Status::Ok);
+            let response = laptop.post("/api/logout_all").header(laptop_csrf.clone()).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(laptop_status().await, Status::Unauthorized);
+            let response = app.client.get("/api/sessions").dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
 
-            // Fetch ordered items for the current order
-            let products_query = "SELECT product_id, var_id, quantity FROM ordered_products WHERE order_id = ?";
-            let products_rows = rocket_db_pools::sqlx::query(products_query)
+        #[rocket::async_test]
+        async fn deprecated_routes_fail_with_json_too() {
+            let app = &inventory_client("deprecated").await;
+            let product: i64 = app
+                .post("/api/add_product", serde_json::json!({"name": "Mug", "desc": "", "price": 8, "quantity": 1}))
+                .await
+                .into_json()
+                .await
+                .unwrap();
+
+            let response = app.client.get("/api/get_product_details?name=Mug").dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+            let link = format!("</api/products/{product}>; rel=\"successor-version\"");
+            assert_eq!(response.headers().get_one("Link"), Some(link.as_str()));
+
+            // Old clients get the same JSON errors as the routes that replace these
+            let response = app.client.get("/api/get_product_details?name=Cup").dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+            assert_eq!(response.headers().get_one("Link"), None);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "No product named `Cup`.");
+            let response = app.send(Method::Delete, "/api/remove_product/Cup", Value::Null).await;
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+            let response = app.client.get(format!("/api/get_variant_id?product_id={product}&tag_name=Large")).dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+            assert_eq!(response.into_json::<Value>().await.unwrap()["success"], false);
+
+            let upload = "--X\r\nContent-Disposition: form-data; name=\"image\"; filename=\"mug.png\"\r\n\r\nnot a png\r\n--X--\r\n";
+            let response = app
+                .client
+                .post("/api/makeimage")
+                .header(rocket::http::ContentType::new("multipart", "form-data").with_params(("boundary", "X")))
+                .header(app.csrf.clone())
+                .body(upload)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::UnsupportedMediaType);
+            assert_eq!(response.headers().get_one("Link"), Some("</api/images>; rel=\"successor-version\""));
+            assert_eq!(response.into_json::<Value>().await.unwrap()["success"], false);
+
+            let body = serde_json::json!({"name": "Mug", "desc": "", "price": -1, "quantity": 1});
+            let response = app.post("/api/update_product", body).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+            assert_eq!(response.into_json::<Value>().await.unwrap()["success"], false);
+        }
+
+        #[rocket::async_test]
+        async fn variants_read_back_can_be_written_back() {
+            let app = &inventory_client("variant-round-trip").await;
+            let variants = |product: i64| app.get::<Vec<Value>>(format!("/api/products/{product}/variants"));
+
+            let product: i64 = app
+                .post("/api/add_product", serde_json::json!({"name": "Robot Kit", "desc": "", "price": 40, "quantity": 0}))
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            let response = app.post("/api/attributes", serde_json::json!({"name": "Voltage", "values": ["5V", "12V"]})).await;
+            assert_eq!(response.status(), Status::Ok);
+            let response = app.post(
+                "/api/add_variant",
+                serde_json::json!({
+                    "product": product, "quantity": 3, "price": "45.50", "sku": " KIT-5V ",
+                    "attributes": {"Voltage": "5V"}, "image": STANDARD.encode(TINY_PNG),
+                    "image_alt": "Robot kit with the 5V motor",
+                }),
+            )
+            .await;
+            assert_eq!(response.status(), Status::Ok);
+
+            // The list and detail routes agree on the shape
+            let read = variants(product).await.remove(0);
+            let detail: Value = app.get(format!("/api/get_variant_details?name={}", read["varid"])).await;
+            assert_eq!(detail, read);
+            assert_eq!(read["attributes"], serde_json::json!({"Voltage": "5V"}));
+            assert_eq!(read["tag_name"], "5V");
+            assert_eq!(read["price"], 45.5);
+            assert_eq!(read["sku"], "KIT-5V");
+            assert_eq!(read["image_alt"], "Robot kit with the 5V motor");
+            let image_url = read["image_url"].as_str().unwrap().to_string();
+            let served = app.client.get(image_url.clone()).dispatch().await.into_bytes().await.unwrap();
+            assert_eq!(served, TINY_PNG);
+
+            // Writing back exactly what was read changes nothing
+            assert_eq!(app.post("/api/modify_variant", read.clone()).await.status(), Status::Ok);
+            assert_eq!(variants(product).await, std::slice::from_ref(&read));
+
+            // An edited copy is accepted too, and leaving out the image keeps it
+            let mut edited = read.clone();
+            edited["quantity"] = 7.into();
+            edited["attributes"] = serde_json::json!({"Voltage": "12V"});
+            edited["price"] = Value::Null;
+            edited["sku"] = "KIT-12V".into();
+            assert_eq!(app.post("/api/modify_variant", edited).await.status(), Status::Ok);
+            let modified = variants(product).await.remove(0);
+            assert_eq!(modified["quantity"], 7);
+            assert_eq!(modified["price"], Value::Null);
+            assert_eq!(modified["sku"], "KIT-12V");
+            assert_eq!(modified["tag_name"], "12V");
+            assert_eq!(modified["image_url"], image_url.as_str());
+
+            // add_variant takes the same shape, ignoring the id and display name,
+            // but SKUs stay unique, whatever their case
+            let mut copy = read.clone();
+            copy["sku"] = "kit-12v".into();
+            assert_eq!(app.post("/api/add_variant", copy).await.status(), Status::Conflict);
+            assert_eq!(app.post("/api/add_variant", read).await.status(), Status::Ok);
+            assert_eq!(variants(product).await.len(), 2);
+
+            // Attribute sets conflict only when they're equal, and one variant may have none
+            let response = app.post("/api/attributes", serde_json::json!({"name": "Finish", "values": ["Matte"]})).await;
+            assert_eq!(response.status(), Status::Ok);
+            let add = |attributes: Value| app.post("/api/add_variant", serde_json::json!({"product": product, "attributes": attributes}));
+            assert_eq!(add(serde_json::json!({"Voltage": "5V", "Finish": "Matte"})).await.status(), Status::Ok);
+            assert_eq!(add(serde_json::json!({"Finish": "Matte"})).await.status(), Status::Ok);
+            assert_eq!(add(serde_json::json!({})).await.status(), Status::Ok);
+            assert_eq!(add(serde_json::json!({})).await.status(), Status::Conflict);
+            let mut cleared = variants(product).await.remove(0);
+            cleared["attributes"] = serde_json::json!({});
+            assert_eq!(app.post("/api/modify_variant", cleared).await.status(), Status::Conflict);
+            assert_eq!(variants(product).await.len(), 5);
+        }
+
+        #[rocket::async_test]
+        async fn orders_charge_the_variant_price() {
+            let app = &inventory_client("variant-price").await;
+            let create = |uri: &'static str, body: Value| async move { app.post(uri, body).await.into_json::<i64>().await.unwrap() };
+
+            let shirt = create("/api/add_product", serde_json::json!({"name": "Shirt", "desc": "", "price": 20, "quantity": 0})).await;
+            let mug = create("/api/add_product", serde_json::json!({"name": "Mug", "desc": "", "price": 8, "quantity": 0})).await;
+            let medium = create("/api/add_variant", serde_json::json!({"product": shirt, "quantity": 5, "attributes": {"Size": "medium"}})).await;
+            let body = serde_json::json!({"product": shirt, "quantity": 5, "price": 23.5, "attributes": {"Size": "large"}});
+            let large = create("/api/add_variant", body).await;
+
+            let order = || app.post("/api/create_order", order_body());
+            let response = order().await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "The cart is empty.");
+
+            for (variant, quantity) in [(medium, 1), (large, 2)] {
+                let body = serde_json::json!({"product": shirt, "var_id": variant, "quantity": quantity});
+                assert_eq!(app.post("/api/addcart", body).await.status(), Status::Ok);
+            }
+            // Items sent with the order are ignored; it's made from the cart
+            let mut body = order_body();
+            body["items"] = serde_json::json!([{"product_id": mug, "variant": null, "quantity": 1}]);
+            let order_id: i64 = app.post("/api/create_order", body).await.into_json().await.unwrap();
+
+            let total: i64 = rocket_db_pools::sqlx::query("SELECT total_cents FROM orders WHERE order_id = ?")
                 .bind(order_id)
-                .fetch_all(&mut **db)
+                .fetch_one(app.pool())
                 .await
-                .map_err(|_| Status::InternalServerError)?;
+                .unwrap()
+                .get("total_cents");
+            assert_eq!(total, 20_00 + 2 * 23_50);
+            let lines: Vec<(i64, Option<i64>, i64)> = rocket_db_pools::sqlx::query_as(
+                "SELECT product_id, var_id, quantity FROM ordered_products WHERE order_id = ? ORDER BY var_id",
+            )
+            .bind(order_id)
+            .fetch_all(app.pool())
+            .await
+            .unwrap();
+            assert_eq!(lines, [(shirt, Some(medium), 1), (shirt, Some(large), 2)]);
+            // The cart was emptied by the order, so it can't be placed twice
+            assert_eq!(order().await.status(), Status::BadRequest);
+
+            // A product that gained variants after it was put in the cart
+            // can't be ordered without choosing one
+            let hat = create("/api/add_product", serde_json::json!({"name": "Hat", "desc": "", "price": 12, "quantity": 4})).await;
+            assert_eq!(app.post("/api/addcart", serde_json::json!({"product": hat})).await.status(), Status::Ok);
+            create("/api/add_variant", serde_json::json!({"product": hat, "quantity": 2, "attributes": {"Size": "large"}})).await;
+            let response = order().await;
+            assert_eq!(response.status(), Status::Conflict);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], format!("Product {hat} comes in variants now; choose one in the cart."));
+            let stock: i64 = rocket_db_pools::sqlx::query("SELECT quantity FROM products WHERE product_id = ?")
+                .bind(hat)
+                .fetch_one(app.pool())
+                .await
+                .unwrap()
+                .get("quantity");
+            assert_eq!(stock, 4);
+            assert_eq!(app.get::<Vec<Value>>("/api/getcart").await.len(), 1);
+        }
 
-            let products: Vec<OrderedItem> = products_rows
-                .into_iter()
-                .filter_map(|row| {
-                    let product_id: i32 = row.get("product_id");
-                    let variant: Option<i32> = row.get("var_id");
-                    let quantity: i32 = row.get("quantity");
+        #[rocket::async_test]
+        async fn archived_products_leave_the_shop_but_not_past_orders() {
+            let app = &inventory_client("archive").await;
+            rocket_db_pools::sqlx::query("INSERT INTO permissions (username, permission) VALUES ('tester', 'orders.read')")
+                .execute(app.pool())
+                .await
+                .unwrap();
+            let shop = || async { app.get::<Vec<Value>>("/api/get_items").await.iter().map(|p| p["id"].clone()).collect::<Vec<_>>() };
+
+            let body = serde_json::json!({"name": "Mug", "desc": "", "price": 8, "quantity": 10});
+            let mug: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+            let body = serde_json::json!({"name": "Pin", "desc": "", "price": 1, "quantity": 10});
+            let pin: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+            assert_eq!(app.post("/api/addcart", serde_json::json!({"product": mug, "quantity": 2})).await.status(), Status::Ok);
+            let order_id: i64 = app.post("/api/create_order", order_body()).await.into_json().await.unwrap();
+            let cust_id: i64 = rocket_db_pools::sqlx::query("SELECT cust_id FROM orders WHERE order_id = ?")
+                .bind(order_id)
+                .fetch_one(app.pool())
+                .await
+                .unwrap()
+                .get("cust_id");
+
+            // An ordered product can't be deleted, only archived
+            let response = app.send(Method::Delete, format!("/api/products/{mug}"), Value::Null).await;
+            assert_eq!(response.status(), Status::Conflict);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], format!("Product {mug} appears in past orders, so it can't be deleted. Archive it instead."));
+            let archived: Value = app.post(format!("/api/products/{mug}/archive"), Value::Null).await.into_json().await.unwrap();
+            assert!(archived["archived_at"].is_string());
+
+            // Gone from the shop and its carts, but listed for restoring
+            assert_eq!(shop().await, [Value::from(pin)]);
+            let response = app.post("/api/addcart", serde_json::json!({"product": mug})).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let listed: Vec<Value> = app.get("/api/archived_products").await;
+            assert_eq!(listed.iter().map(|p| &p["id"]).collect::<Vec<_>>(), [&Value::from(mug)]);
+
+            // Past orders still find it
+            let orders: Vec<Value> = app.get(format!("/api/getcustomerorders/{cust_id}")).await;
+            assert_eq!(orders[0]["products"][0]["product_id"], mug);
+            let ordered: Value = app.get(format!("/api/products/{mug}")).await;
+            assert_eq!(ordered["name"], "Mug");
+
+            // Restoring puts it back on sale
+            let restored: Value = app.post(format!("/api/products/{mug}/unarchive"), Value::Null).await.into_json().await.unwrap();
+            assert_eq!(restored["archived_at"], Value::Null);
+            assert_eq!(shop().await, [Value::from(mug), Value::from(pin)]);
+            assert!(app.get::<Vec<Value>>("/api/archived_products").await.is_empty());
+
+            // A product nobody ordered can still be deleted
+            let response = app.send(Method::Delete, format!("/api/products/{pin}"), Value::Null).await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(shop().await, [Value::from(mug)]);
+        }
 
-                    Some(OrderedItem {
-                        product_id,
-                        variant,
-                        quantity,
-                    })
+        #[rocket::async_test]
+        async fn carts_are_kept_in_the_database_and_priced_from_it() {
+            let app = &inventory_client("cart").await;
+            let client = &app.client;
+            let cart = || app.get::<Vec<Value>>("/api/getcart");
+            let add = |body: Value| async move {
+                let response = app.post("/api/addcart", body).await;
+                assert_eq!(response.status(), Status::Ok);
+                response.into_json::<u32>().await.unwrap()
+            };
+
+            let body = serde_json::json!({"name": "Shirt", "desc": "", "price": 20, "quantity": 0});
+            let shirt: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+            let body = serde_json::json!({"name": "Mug", "desc": "", "price": 8, "quantity": 10});
+            let mug: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+            let body = serde_json::json!({"product": shirt, "quantity": 5, "price": 23.5, "attributes": {"Size": "large"}});
+            let large: i64 = app.post("/api/add_variant", body).await.into_json().await.unwrap();
+
+            // Names and prices sent with an item are ignored
+            assert_eq!(add(serde_json::json!({"product": shirt, "var_id": large, "name": "Free shirt", "price": 0.01})).await, 1);
+            // A variant can be picked by its attributes too, and adding it again adds up
+            assert_eq!(add(serde_json::json!({"product": shirt, "attributes": {"Size": "large"}, "quantity": 2})).await, 3);
+            assert_eq!(add(serde_json::json!({"product": mug})).await, 4);
+            let response = app.post("/api/addcart", serde_json::json!({"product": mug, "var_id": large})).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let response = app.post("/api/addcart", serde_json::json!({"product": 999})).await;
+            assert_eq!(response.status(), Status::NotFound);
+
+            // The browser only holds a token
+            let token = client.cookies().get_private(CART_COOKIE).unwrap().value().to_string();
+            assert_eq!(token.len(), 32);
+            assert!(client.cookies().get_private(CART_COOKIE).unwrap().max_age().is_some());
+
+            let lines = cart().await;
+            assert_eq!(lines.len(), 2);
+            assert_eq!((&lines[0]["name"], &lines[0]["variant"]), (&"Shirt".into(), &"Large".into()));
+            assert_eq!((&lines[0]["quantity"], &lines[0]["price"]), (&3.into(), &23.5.into()));
+            assert_eq!((&lines[1]["variant"], &lines[1]["price"]), (&"N/A".into(), &8.0.into()));
+
+            // Prices follow the database
+            let body = serde_json::json!({"name": "Mug", "desc": "", "price": 9, "quantity": 10});
+            assert_eq!(app.send(Method::Put, format!("/api/products/{mug}"), body).await.status(), Status::Ok);
+            assert_eq!(cart().await[1]["price"], 9.0);
+
+            // Lines are removed by id, or by name and variant as older pages do
+            let response = app.post(format!("/api/removecart?item={}", lines[1]["id"]), Value::Null).await;
+            assert_eq!(response.into_json::<usize>().await, Some(1));
+            let response = app.post("/api/removecart?name=Shirt&variant=Large", Value::Null).await;
+            assert_eq!(response.into_json::<usize>().await, Some(0));
+
+            // Clearing the cart forgets it altogether
+            add(serde_json::json!({"product": mug})).await;
+            let response = app.post("/api/clearcart", Value::Null).await;
+            assert_eq!(response.into_json::<usize>().await, Some(1));
+            assert!(cart().await.is_empty());
+            let carts: i64 = rocket_db_pools::sqlx::query("SELECT COUNT(*) AS n FROM carts")
+                .fetch_one(app.pool())
+                .await
+                .unwrap()
+                .get("n");
+            assert_eq!(carts, 0);
+
+            // Expired carts are left behind, then swept away
+            add(serde_json::json!({"product": mug})).await;
+            let pool = app.pool();
+            rocket_db_pools::sqlx::query("UPDATE carts SET expires_at = '2000-01-01T00:00:00Z'")
+                .execute(pool)
+                .await
+                .unwrap();
+            assert!(cart().await.is_empty());
+            assert_eq!(app.get::<u32>("/api/get_cart_count").await, 0);
+            assert_eq!(sweep_expired_carts(pool).await.unwrap(), 1);
+            // A new cart starts with a new token
+            assert_eq!(add(serde_json::json!({"product": mug})).await, 1);
+            assert_ne!(client.cookies().get_private(CART_COOKIE).unwrap().value(), token);
+        }
+
+        #[rocket::async_test]
+        async fn carts_are_limited_to_stock_and_checkout_holds_it() {
+            let app = &inventory_client("stock-hold").await;
+            let pool = app.pool();
+            let stock = |product: i64| async move {
+                rocket_db_pools::sqlx::query("SELECT quantity FROM products WHERE product_id = ?")
+                    .bind(product)
+                    .fetch_one(pool)
+                    .await
+                    .unwrap()
+                    .get::<i64, _>("quantity")
+            };
+
+            let body = serde_json::json!({"name": "Mug", "desc": "", "price": 8, "quantity": 3});
+            let mug: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+
+            // Asking for more than there is says how many are left
+            let response = app.post("/api/addcart", serde_json::json!({"product": mug, "quantity": 4})).await;
+            assert_eq!(response.status(), Status::Conflict);
+            let shortage: Value = response.into_json().await.unwrap();
+            assert_eq!(shortage["available"], 3);
+            assert_eq!(shortage["message"], "Only 3 Mug are left.");
+            assert!(app.client.cookies().get_private(CART_COOKIE).is_none());
+            let response = app.post("/api/addcart", serde_json::json!({"product": mug, "quantity": 2})).await;
+            assert_eq!(response.into_json::<u32>().await, Some(2));
+
+            // Someone else in checkout holds the rest
+            let later = timestamp(Utc::now() + Duration::minutes(10));
+            let other: i64 = rocket_db_pools::sqlx::query(
+                "INSERT INTO carts (token_hash, created_at, updated_at, expires_at) VALUES ('other', ?1, ?1, ?1) RETURNING cart_id",
+            )
+            .bind(&later)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get("cart_id");
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO stock_holds (cart_id, product_id, quantity, expires_at) VALUES (?, ?, 2, ?)",
+            )
+            .bind(other)
+            .bind(mug)
+            .bind(&later)
+            .execute(pool)
+            .await
+            .unwrap();
+            let lines = app.get::<Vec<Value>>("/api/getcart").await;
+            assert_eq!(lines[0]["available"], 1);
+            let line = &lines[0]["id"];
+            let response = app.post(format!("/api/updatecart?item={line}&quantity=2"), Value::Null).await;
+            assert_eq!(response.status(), Status::Conflict);
+            let response = app.post("/api/checkout/hold", Value::Null).await;
+            assert_eq!(response.status(), Status::Conflict);
+            assert_eq!(response.into_json::<Value>().await.unwrap()["message"], "Only 1 Mug is left.");
+            let response = app.post(format!("/api/updatecart?item={line}&quantity=1"), Value::Null).await;
+            assert_eq!(response.into_json::<Vec<Value>>().await.unwrap()[0]["quantity"], 1);
+
+            // Once their hold lapses it stops counting, and this cart can hold its own
+            rocket_db_pools::sqlx::query("UPDATE stock_holds SET expires_at = '2000-01-01T00:00:00Z'")
+                .execute(pool)
+                .await
+                .unwrap();
+            let response = app.post(format!("/api/updatecart?item={line}&quantity=3"), Value::Null).await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(sweep_expired_holds(pool).await.unwrap(), 1);
+            let response = app.post("/api/checkout/hold", Value::Null).await;
+            assert_eq!(response.status(), Status::Ok);
+            let hold: Value = response.into_json().await.unwrap();
+            assert_eq!(hold["total"], 24.0);
+            assert!(hold["expires_at"].as_str().unwrap() > timestamp(Utc::now()).as_str());
+            // Nobody else can take it meanwhile
+            let mut conn = pool.acquire().await.unwrap();
+            assert_eq!(available_stock(&mut conn, mug, None, Some(other)).await.unwrap(), 0);
+            drop(conn);
+
+            // Ordering takes it out of stock, lets go of the hold and empties the cart
+            let order = || app.post("/api/create_order", order_body());
+            assert_eq!(order().await.status(), Status::Ok);
+            assert_eq!(stock(mug).await, 0);
+            assert!(app.get::<Vec<Value>>("/api/getcart").await.is_empty());
+            let holds: i64 = rocket_db_pools::sqlx::query("SELECT COUNT(*) AS holds FROM stock_holds")
+                .fetch_one(pool)
+                .await
+                .unwrap()
+                .get("holds");
+            assert_eq!(holds, 0);
+
+            // Stock that went elsewhere after it was put in the cart can't be ordered
+            rocket_db_pools::sqlx::query("UPDATE products SET quantity = 1 WHERE product_id = ?")
+                .bind(mug)
+                .execute(pool)
+                .await
+                .unwrap();
+            let response = app.post("/api/addcart", serde_json::json!({"product": mug})).await;
+            assert_eq!(response.status(), Status::Ok);
+            rocket_db_pools::sqlx::query("UPDATE products SET quantity = 0 WHERE product_id = ?")
+                .bind(mug)
+                .execute(pool)
+                .await
+                .unwrap();
+            let response = order().await;
+            assert_eq!(response.status(), Status::Conflict);
+            let shortage: Value = response.into_json().await.unwrap();
+            assert_eq!((shortage["message"].as_str(), shortage["available"].as_u64()), (Some("Mug is sold out."), Some(0)));
+            assert_eq!(stock(mug).await, 0);
+        }
+
+        #[test]
+        fn renditions_never_scale_up() {
+            let wide = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                600,
+                300,
+                image::Rgba([0, 0, 0, 0]),
+            ));
+            let mut png = Vec::new();
+            wide.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+            let rendered = render(&png).unwrap();
+            assert_eq!((rendered.width, rendered.height), (600, 300));
+            let sizes: Vec<(u32, u32)> = rendered
+                .renditions
+                .iter()
+                .map(|rendition| {
+                    let webp = image::load_from_memory(&rendition.webp).unwrap();
+                    let jpeg = image::load_from_memory(&rendition.jpeg).unwrap();
+                    assert_eq!((webp.width(), webp.height()), (jpeg.width(), jpeg.height()));
+                    (jpeg.width(), jpeg.height())
                 })
                 .collect();
+            assert_eq!(sizes, [(160, 80), (480, 240), (600, 300)]);
 
-            orders.push(Order {
-                order_id,
-                products,
-            });
-        }
+            // WebP keeps the transparency; in JPEG it turns white, not black
+            let webp = image::load_from_memory(&rendered.renditions[0].webp).unwrap().to_rgba8();
+            assert_eq!(webp.get_pixel(10, 10).0[3], 0);
+            let jpeg = image::load_from_memory(&rendered.renditions[0].jpeg).unwrap().to_rgb8();
+            assert!(jpeg.get_pixel(10, 10).0.iter().all(|&c| c > 250));
 
-        Ok(Json(orders))
-    }
+            assert!(render(&TINY_PNG[..40]).is_err());
+        }
 
-    #[derive(Deserialize)]
-    struct OrderRequest {
-        customer: Customer,
-        items: Vec<OrderedItem>,
-    }
+        #[test]
+        fn images_are_recognized_by_their_contents() {
+            assert_eq!(ImageFormat::sniff(TINY_PNG), Some(ImageFormat::Png));
+            assert_eq!(ImageFormat::sniff(&[0xff, 0xd8, 0xff, 0xe0, 0, 0x10]), Some(ImageFormat::Jpeg));
+            assert_eq!(ImageFormat::sniff(b"GIF89a\x01\x00"), Some(ImageFormat::Gif));
+            assert_eq!(ImageFormat::sniff(b"RIFF\x24\x00\x00\x00WEBPVP8 "), Some(ImageFormat::Webp));
+            assert_eq!(ImageFormat::sniff(b"RIFF\x24\x00\x00\x00WAVEfmt "), None);
+            assert_eq!(ImageFormat::sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), None);
+            assert_eq!(ImageFormat::sniff(&TINY_PNG[..4]), None);
+        }
 
-    #[allow(private_interfaces)]
-    #[post("/create_order", data = "<order_data>")]
-    pub(super) async fn create_order(
-        order_data: Json<OrderRequest>,
-        mut db: Connection<RoboDatabase>,
-    ) -> Result<Json<i32>, String> {
-        let customer = &order_data.customer;
-        let formatted_address = format!(
-            "{}, {}, {}, {}, {}",
-            customer.address.address_line_1,
-            customer.address.admin_area_2,
-            customer.address.admin_area_1,
-            customer.address.postal_code,
-            customer.address.country_code
-        );
-        let items = &order_data.items;
+        #[rocket::async_test]
+        async fn uploaded_images_are_stored_once_and_attached() {
+            let app = &inventory_client("image-upload").await;
+            let client = &app.client;
+
+            let body = serde_json::json!({"name": "Sticker", "desc": "", "price": 2, "quantity": 10});
+            let product: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+
+            let response = app.upload("/api/images", b"GIF? no, just text").await;
+            assert_eq!(response.status(), Status::UnsupportedMediaType);
+            let response = app.upload("/api/images?product=999&alt=Sticker", TINY_PNG).await;
+            assert_eq!(response.status(), Status::NotFound);
+            // Attaching an image needs alt text
+            let response = app.upload(format!("/api/images?product={product}&alt=%20"), TINY_PNG).await;
+            assert_eq!(response.status(), Status::BadRequest);
+
+            let first: Value = app.upload("/api/images", TINY_PNG).await.into_json().await.unwrap();
+            assert_eq!(first["gallery_image"], Value::Null);
+            let uri = format!("/api/images?product={product}&alt=Round%20sticker");
+            let attached: Value = app.upload(uri, TINY_PNG).await.into_json().await.unwrap();
+            assert_eq!(first["file"], attached["file"]);
+            assert_eq!(attached["gallery_image"]["alt"], "Round sticker");
+            assert_eq!(attached["gallery_image"]["primary"], true);
+            let file = attached["file"].as_str().unwrap();
+            assert_eq!(file, format!("{}.png", hex::encode(Sha256::digest(TINY_PNG))));
+            // The original and a WebP and JPEG copy at each width
+            assert_eq!(std::fs::read_dir(app.dir.join("images")).unwrap().count(), 1 + 2 * RENDITION_WIDTHS.len());
+
+            let read: Value = app.get(format!("/api/products/{product}")).await;
+            assert_eq!(read["image_url"], attached["url"]);
+            assert_eq!(read["image_alt"], "Round sticker");
+            assert_eq!(read["image_sizes"]["thumbnail"], attached["sizes"]["thumbnail"]);
+            let served = client.get(format!("/product_images/{file}")).dispatch().await;
+            assert_eq!(served.content_type(), Some(rocket::http::ContentType::PNG));
+            assert_eq!(served.into_bytes().await.unwrap(), TINY_PNG);
+
+            // The copies are real images of the right kind, and cache by ETag
+            let thumbnail = read["image_sizes"]["thumbnail"].as_str().unwrap().to_string();
+            let served = client.get(thumbnail.clone()).dispatch().await;
+            assert_eq!(served.content_type(), Some(rocket::http::ContentType::JPEG));
+            let etag = served.headers().get_one("ETag").unwrap().to_string();
+            assert!(served.headers().get_one("Cache-Control").unwrap().contains("immutable"));
+            let bytes = served.into_bytes().await.unwrap();
+            assert_eq!(ImageFormat::sniff(&bytes), Some(ImageFormat::Jpeg));
+            let cached = client
+                .get(thumbnail)
+                .header(rocket::http::Header::new("If-None-Match", etag.clone()))
+                .dispatch()
+                .await;
+            assert_eq!(cached.status(), Status::NotModified);
+            assert_eq!(cached.headers().get_one("ETag"), Some(etag.as_str()));
+            for (srcset, format) in [("webp_srcset", ImageFormat::Webp), ("jpeg_srcset", ImageFormat::Jpeg)] {
+                let srcset = read["image_sizes"][srcset].as_str().unwrap();
+                let largest = srcset.rsplit(", ").next().unwrap().split(' ').next().unwrap();
+                let bytes = client.get(largest.to_string()).dispatch().await.into_bytes().await.unwrap();
+                assert_eq!(ImageFormat::sniff(&bytes), Some(format));
+            }
+            assert_eq!(client.get("/product_images/missing.png").dispatch().await.status(), Status::NotFound);
+        }
 
-        // Step 1: Insert customer information and get the generated `cust_id`
-        let cust_id: i32 = rocket_db_pools::sqlx::query(
-            r#"
-            INSERT INTO customers (name, address, email, phone_number)
-            VALUES ($1, $2, $3, $4)
-            RETURNING cust_id
-            "#,
-        )
-        .bind(&customer.name)
-        .bind(&formatted_address)
-        .bind(&customer.email)
-        .bind(&customer.phone_number)
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| format!("Failed to insert customer: {}", e))?
-        .try_get("cust_id")
-        .map_err(|e| format!("Failed to get cust_id: {}", e))?;
+        #[rocket::async_test]
+        async fn galleries_keep_their_order_primary_image_and_alt_text() {
+            let app = &inventory_client("image-gallery").await;
+            let read = |uri: String| app.get::<Value>(uri);
+            let alts = |images: &Value| {
+                images
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|image| image["alt"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            };
+            use rocket::http::Method::{Delete, Put};
+
+            let body = serde_json::json!({"name": "Patch", "desc": "", "price": 5, "quantity": 1});
+            let product: i64 = app.post("/api/add_product", body).await.into_json().await.unwrap();
+            let images = format!("/api/products/{product}/images");
+
+            let mut files = vec![];
+            for shade in [0, 128, 255] {
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([shade; 3])))
+                    .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                    .unwrap();
+                let stored: Value = app.upload("/api/images", png).await.into_json().await.unwrap();
+                files.push(stored["file"].as_str().unwrap().to_string());
+            }
 
-        // Step 2: Insert a new order and get the generated `order_id`
-        let order_id: i32 = rocket_db_pools::sqlx::query(
-            r#"
-            INSERT INTO orders (cust_id)
-            VALUES ($1)
-            RETURNING order_id
-            "#,
-        )
-        .bind(cust_id)
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| format!("Failed to insert order: {}", e))?
-        .try_get("order_id")
-        .map_err(|e| format!("Failed to get order_id: {}", e))?;
+            let response = app.post(&images, serde_json::json!({"file": files[0], "alt": " "})).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let response = app.post(&images, serde_json::json!({"file": "missing.png", "alt": "Nothing"})).await;
+            assert_eq!(response.status(), Status::NotFound);
+            let response = app.post(&images, serde_json::json!({"file": files[0], "alt": "Black", "variant": 999})).await;
+            assert_eq!(response.status(), Status::NotFound);
+
+            let mut ids = vec![];
+            for (file, alt) in files.iter().zip(["Black", "Grey", "White"]) {
+                let response = app.post(&images, serde_json::json!({"file": file, "alt": alt})).await;
+                let added: Value = response.into_json().await.unwrap();
+                ids.push(added["id"].as_i64().unwrap());
+            }
+            // The first image added is the primary one
+            let gallery = read(images.clone()).await;
+            assert_eq!(alts(&gallery), ["Black", "Grey", "White"]);
+            assert_eq!(gallery[0]["primary"], true);
+
+            // A new order lists every image once
+            let response = app.send(Put, &images, serde_json::json!([ids[2], ids[0]])).await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let response = app.send(Put, &images, serde_json::json!([ids[2], ids[0], ids[1]])).await;
+            assert_eq!(alts(&response.into_json().await.unwrap()), ["White", "Black", "Grey"]);
+
+            // Making another image primary takes over from the old one
+            let uri = format!("{images}/{}", ids[2]);
+            let response = app.send(Put, &uri, serde_json::json!({"alt": "White logo", "primary": true})).await;
+            assert_eq!(response.status(), Status::Ok);
+            let product_read = read(format!("/api/products/{product}")).await;
+            assert_eq!(product_read["image_alt"], "White logo");
+            assert_eq!(product_read["image_url"], image_url(&files[2]).as_str());
+            let primaries = product_read["images"].as_array().unwrap().iter().filter(|image| image["primary"] == true);
+            assert_eq!(primaries.count(), 1);
+
+            // Removing the primary image leaves the first remaining one primary
+            let remaining: Value = app.send(Delete, uri, Value::Null).await.into_json().await.unwrap();
+            assert_eq!(alts(&remaining), ["Black", "Grey"]);
+            assert_eq!(remaining[0]["primary"], true);
+
+            // Variants have galleries of their own
+            let body = serde_json::json!({"name": "Finish", "values": ["Embroidered"]});
+            assert_eq!(app.post("/api/attributes", body).await.status(), Status::Ok);
+            let body = serde_json::json!({"product": product, "quantity": 1, "attributes": {"Finish": "Embroidered"}});
+            let var_id: i64 = app.post("/api/add_variant", body).await.into_json().await.unwrap();
+            let body = serde_json::json!({"file": files[1], "alt": "Grey, embroidered", "variant": var_id});
+            assert_eq!(app.post(&images, body).await.status(), Status::Ok);
+            assert_eq!(alts(&read(format!("{images}?variant={var_id}")).await), ["Grey, embroidered"]);
+            assert_eq!(alts(&read(images).await), ["Black", "Grey"]);
+            let variant = read(format!("/api/get_variant_details?name={var_id}")).await;
+            assert_eq!(variant["image_alt"], "Grey, embroidered");
+        }
 
-        // Step 3: Insert ordered products
-        for item in items {
+        #[rocket::async_test]
+        async fn legacy_blobs_move_into_the_image_store() {
+            let pool = rocket_db_pools::sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            run_migrations(&pool).await.unwrap();
             rocket_db_pools::sqlx::query(
-                r#"
-                INSERT INTO ordered_products (product_id, var_id, order_id, quantity)
-                VALUES ($1, $2, $3, $4)
-                "#,
+                "INSERT INTO products (product_id, name, image) VALUES (1, 'Shirt', ?), (2, 'Mug', ?)",
             )
-            .bind(item.product_id)
-            .bind(item.variant) // This can be NULL
-            .bind(order_id)
-            .bind(item.quantity)
-            .execute(&mut **db)
+            .bind(TINY_PNG)
+            .bind(b"not an image".as_slice())
+            .execute(&pool)
             .await
-            .map_err(|e| format!("Failed to insert ordered product: {}", e))?;
+            .unwrap();
+
+            let dir = TempDir::new("image-import");
+            let store = ImageStore { dir: dir.to_path_buf() };
+            assert_eq!(import_legacy_images(&pool, &store).await.unwrap(), 1);
+            // Nothing is left to move the second time
+            assert_eq!(import_legacy_images(&pool, &store).await.unwrap(), 0);
+            assert_eq!(render_missing_renditions(&pool, &store).await.unwrap(), 1);
+            assert_eq!(render_missing_renditions(&pool, &store).await.unwrap(), 0);
+
+            let rows = rocket_db_pools::sqlx::query("SELECT image FROM products ORDER BY product_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            assert_eq!(rows[0].get::<Option<Vec<u8>>, _>("image"), None);
+            // What isn't an image stays where it was
+            assert_eq!(rows[1].get::<Option<Vec<u8>>, _>("image").as_deref(), Some(&b"not an image"[..]));
+
+            // The moved image is the primary image of the product's gallery,
+            // described by the product's name
+            let mut conn = pool.acquire().await.unwrap();
+            let images = gallery(&mut conn, 1, None).await.unwrap();
+            assert_eq!(images.len(), 1);
+            assert_eq!((images[0].alt.as_str(), images[0].primary), ("Shirt", true));
+            assert_eq!(std::fs::read(dir.join(&images[0].file)).unwrap(), TINY_PNG);
+            assert!(dir.join(rendition_file(&images[0].file, 480, "webp")).exists());
+            assert!(dir.join(rendition_file(&images[0].file, 480, "jpg")).exists());
+            assert!(gallery(&mut conn, 2, None).await.unwrap().is_empty());
         }
 
-        // Step 4: Return the order_id
-        Ok(Json(order_id)) // Return the generated order_id
-    }
+        #[test]
+        fn money_is_exact_to_the_cent() {
+            assert_eq!("12".parse(), Ok(Money(1200)));
+            assert_eq!("$12.5".parse(), Ok(Money(1250)));
+            assert_eq!(".05".parse(), Ok(Money(5)));
+            assert_eq!("-0.10".parse(), Ok(Money(-10)));
+            assert!("12.345".parse::<Money>().is_err());
+            assert!("1e3".parse::<Money>().is_err());
+            assert!(".".parse::<Money>().is_err());
+
+            assert_eq!(Money(1999).to_string(), "19.99");
+            assert_eq!(Money(-5).to_string(), "-0.05");
+
+            // Ten dimes are a dollar, which doesn't hold for f32
+            assert_eq!(std::iter::repeat_n(Money(10), 10).sum::<Money>(), Money(100));
+            assert_eq!(Money(1999) * 3, Money(5997));
+
+            let parsed: Vec<Money> = serde_json::from_str(r#"[19.99, 8, "0.10", 0.1]"#).unwrap();
+            assert_eq!(parsed, [Money(1999), Money(800), Money(10), Money(10)]);
+            assert_eq!(serde_json::to_string(&Money(1250)).unwrap(), "12.5");
+        }
 
-    #[allow(private_interfaces)]
-    #[get("/get_product_details?<name>")]
-    pub(super) async fn get_product_details(
-        mut db: Connection<RoboDatabase>,
-        name: String,
-    ) -> Result<Json<Product>, String> {
-        // Query the database for the product by ID
-        let row = rocket_db_pools::sqlx::query(
-            "SELECT * FROM products WHERE name = $1"
-        )
-        .bind(name)
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| format!("Error fetching product: {e}"))?;
-
-        // Manually map the row to a Product struct
-        let product = Product {
-            id: row.try_get("product_id").ok(),
-            name: row.try_get("name").unwrap_or_default(),
-            desc: row.try_get("desc").unwrap_or_default(),
-            price: row.try_get("price").unwrap_or_default(),
-            image: row.try_get("image").ok(),
-            quantity: row.try_get("quantity").unwrap_or_default(),
-        };
+        #[test]
+        fn migrations_are_in_order() {
+            assert!(MIGRATIONS.windows(2).all(|w| w[0].0 < w[1].0));
+        }
 
-        // If the query succeeds, return the product in JSON format
-        Ok(Json(product))
-    }
+        // Test vectors from RFC 6238 appendix B, truncated to six digits
+        const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+        #[test]
+        fn totp_matches_rfc_6238_vectors() {
+            for (time, code) in [
+                (59, 287082),
+                (1111111109, 81804),
+                (1111111111, 50471),
+                (1234567890, 5924),
+                (2000000000, 279037),
+                (20000000000, 353130),
+            ] {
+                assert_eq!(totp_code(RFC_SECRET, time / TOTP_STEP), code, "at time {time}");
+            }
+        }
 
-    #[allow(private_interfaces)]
-    #[get("/get_variant_details?<name>")]
-    pub(super) async fn get_variant_details(
-        mut db: Connection<RoboDatabase>,
-        name: String,
-    ) -> Result<Json<ProductVariant>, String> {
-        // Query the database for the product variant by name
-        let row = rocket_db_pools::sqlx::query(
-            "SELECT * FROM product_variants WHERE var_id = $1"
-        )
-        .bind(name)
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| format!("Error fetching product variant: {e}"))?;
+        #[test]
+        fn totp_allows_one_step_of_drift() {
+            let now = 1111111109;
+            assert_eq!(verify_totp(RFC_SECRET, "081804", now, None), Some(now / TOTP_STEP));
+            assert!(verify_totp(RFC_SECRET, "081804", now + TOTP_STEP, None).is_some());
+            assert!(verify_totp(RFC_SECRET, "081804", now - TOTP_STEP, None).is_some());
+            assert!(verify_totp(RFC_SECRET, "081804", now + 3 * TOTP_STEP, None).is_none());
+            assert!(verify_totp(RFC_SECRET, "81804", now, None).is_none());
+            assert!(verify_totp(RFC_SECRET, "abcdef", now, None).is_none());
+        }
 
-        // Deserialize the tag_name field if it exists
-        let tag: Vec<VarTag> = match row.try_get::<Option<String>, _>("tag_name") {
-            Ok(Some(value)) => serde_json::from_str(&value).unwrap_or_default(),
-            Ok(None) => Vec::new(),
-            Err(_) => return Err("Failed to parse tag_name".to_string()),
-        };
+        #[test]
+        fn totp_codes_cannot_be_replayed() {
+            let now = 1111111109;
+            let step = verify_totp(RFC_SECRET, "081804", now, None).unwrap();
+            assert!(verify_totp(RFC_SECRET, "081804", now, Some(step)).is_none());
+        }
 
-        // Manually map the row to a ProductVariant struct
-        let product = ProductVariant {
-            tag_name: tag,
-            product: row.try_get("product_id").unwrap_or_default(),
-            varid: row.try_get("var_id").unwrap_or_default(),
-            image: row.try_get("image").ok(),
-            quantity: row.try_get("quantity").unwrap_or_default(),
-        };
+        #[test]
+        fn totp_uri_escapes_account_name() {
+            let uri = totp_uri("jane doe", "JBSWY3DPEHPK3PXP");
+            assert_eq!(
+                uri,
+                "otpauth://totp/Kent%20State%20Robotics:jane%20doe?secret=JBSWY3DPEHPK3PXP\
+                 &issuer=Kent%20State%20Robotics&algorithm=SHA1&digits=6&period=30"
+            );
+        }
 
-        // If the query succeeds, return the product variant in JSON format
-        Ok(Json(product))
+        #[test]
+        fn recovery_codes_ignore_formatting() {
+            let codes = generate_recovery_codes();
+            assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+            let code = &codes[0];
+            assert_eq!(hash_recovery_code(code), hash_recovery_code(&code.to_uppercase()));
+            assert_eq!(hash_recovery_code(code), hash_recovery_code(&code.replace('-', "")));
+            assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+        }
+
+        #[rocket::async_test]
+        async fn second_factors_are_checked_as_of_the_given_time() {
+            let pool = rocket_db_pools::sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            run_migrations(&pool).await.unwrap();
+            rocket_db_pools::sqlx::query(
+                "INSERT INTO admins (username, salt, password, expiration) VALUES ('ada', 'ada', '', '2999-12-31')",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            rocket_db_pools::sqlx::query("INSERT INTO admin_totp (username, secret, enabled) VALUES ('ada', ?, 1)")
+                .bind(base32::encode(base32::Alphabet::RFC4648 { padding: false }, RFC_SECRET))
+                .execute(&pool)
+                .await
+                .unwrap();
+            rocket_db_pools::sqlx::query("INSERT INTO recovery_codes (username, code_hash) VALUES ('ada', ?)")
+                .bind(hash_recovery_code("abcd-efgh-ijkl"))
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let mut conn = pool.acquire().await.unwrap();
+            for (code, time, matches) in [
+                // The RFC's code for 1111111109 is only good within a step of it, and only once
+                ("081804", 1111111109 + 3 * TOTP_STEP as i64, false),
+                ("081804", 1111111109, true),
+                ("081804", 1111111109, false),
+                // A recovery code works once, whatever the time
+                ("ABCD-EFGH-IJKL", 0, true),
+                ("abcd-efgh-ijkl", 0, false),
+            ] {
+                let now = DateTime::from_timestamp(time, 0).unwrap();
+                assert_eq!(check_second_factor(&mut conn, "ada", code, now).await.unwrap(), matches, "{code} at {time}");
+            }
+        }
+
+        #[rocket::async_test]
+        async fn logins_with_two_factors_start_a_session_after_the_code() {
+            let app = &TestApp::new("totp").await;
+            app.add_admin("ada", "lovelace", &[]).await;
+            assert_eq!(log_in(&app.client, &app.csrf, "ada", "lovelace").await.status(), Status::Ok);
+            let enrollment: Value = app.post("/api/totp/enroll", Value::Null).await.into_json().await.unwrap();
+            let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, enrollment["secret"].as_str().unwrap()).unwrap();
+            let code = |steps_ahead: u64| {
+                let step = Utc::now().timestamp() as u64 / TOTP_STEP + steps_ahead;
+                format!("{:06}", totp_code(&secret, step))
+            };
+            let response = submit(&app.client, &app.csrf, "/api/totp/confirm", &[("code", &code(0))]).await;
+            assert_eq!(response.status(), Status::Ok);
+            let confirmed: Value = response.into_json().await.unwrap();
+            let recovery_codes: Vec<String> = serde_json::from_value(confirmed["recovery_codes"].clone()).unwrap();
+
+            // The password alone doesn't start a session
+            let (browser, csrf) = app.other_client().await;
+            let response = log_in(&browser, &csrf, "ada", "lovelace").await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_json::<Value>().await.unwrap()["totp_required"], true);
+            assert_eq!(browser.get("/api/sessions").dispatch().await.status(), Status::Unauthorized);
+
+            // The code used to confirm can't be used again, but the next one can
+            let response = submit(&browser, &csrf, "/api/login/totp", &[("code", &code(0))]).await;
+            assert_eq!(response.status(), Status::Unauthorized);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Invalid authentication code.");
+            // A wrong code holds the username back for a second; skip the wait
+            rocket_db_pools::sqlx::query("UPDATE login_attempts SET locked_until = NULL")
+                .execute(app.pool())
+                .await
+                .unwrap();
+            let response = submit(&browser, &csrf, "/api/login/totp", &[("code", &code(1))]).await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(browser.get("/api/sessions").dispatch().await.status(), Status::Ok);
+            // The pending login is used up with it
+            let response = submit(&browser, &csrf, "/api/login/totp", &[("code", &code(1))]).await;
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["message"], "Login expired. Enter your password again.");
+
+            // A recovery code stands in for a lost phone, once
+            for (expected, recovery_code) in [(Status::Ok, &recovery_codes[0]), (Status::Unauthorized, &recovery_codes[0])] {
+                let (browser, csrf) = app.other_client().await;
+                assert_eq!(log_in(&browser, &csrf, "ada", "lovelace").await.status(), Status::Ok);
+                let response = submit(&browser, &csrf, "/api/login/totp", &[("code", recovery_code)]).await;
+                assert_eq!(response.status(), expected);
+                let session = browser.get("/api/sessions").dispatch().await.status();
+                assert_eq!(session, if expected == Status::Ok { Status::Ok } else { Status::Unauthorized });
+            }
+        }
     }
 }
 
@@ -1465,44 +7925,109 @@ async fn homepage() -> Option<NamedFile> {
     NamedFile::open("./pages/homepage.html").await.ok()
 }
 
-#[launch]
-async fn rocket() -> _ {
-    let mut rootroutes = [
-        Route::new(Method::Get, "/<path..>", FileServer::from("./pages")),
-        Route::new(Method::Get, "/<path..>", FileServer::from("./product_images")),
-    ];
-    rootroutes[0].rank = -2;
-    rootroutes[1].rank = -1;
+#[rocket::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("bootstrap-admin") {
+        match api::bootstrap_admin(&args[1..]).await {
+            Ok(message) => println!("{message}"),
+            Err(message) => {
+                eprintln!("{message}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = rocket().launch().await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn rocket() -> Rocket<Build> {
+    let mut pages = Route::new(Method::Get, "/<path..>", FileServer::from("./pages"));
+    pages.rank = -2;
     rocket::build()
         .attach(api::RoboDatabase::init())
+        .attach(api::migrations())
+        .attach(api::image_storage())
+        .attach(api::Csrf)
+        .attach(api::expiry_sweeper())
+        .attach(rocket::fairing::AdHoc::config::<api::CartConfig>())
+        .register("/api", catchers![api::unauthorized, api::forbidden])
         .mount("/", routes![homepage])
-        .mount("/", rootroutes)
+        .mount("/", [pages])
         .mount(
             "/api",
             routes![
                 api::get_items,
+                api::csrf_rejected,
                 api::add_item,
                 api::get_websiteinfo,
                 api::update_websiteinfo,
                 api::create_admin,
+                api::expiring_admins,
+                api::get_audit,
+                api::extend_admins,
                 api::login,
+                api::login_totp,
+                api::change_password,
+                api::create_password_reset,
+                api::reset_password,
+                api::totp_enroll,
+                api::totp_confirm,
+                api::totp_disable,
                 api::logout,
+                api::logout_all,
+                api::get_lockouts,
+                api::clear_lockout,
+                api::get_sessions,
+                api::revoke_session,
+                api::get_admin_sessions,
+                api::revoke_admin_sessions,
                 api::admin_menu,
                 api::current_user,
                 api::get_product_variants,
                 api::get_variant_id,
+                api::find_variant,
                 api::modify_variant,
                 api::add_product_variant,
                 api::make_image,
+                api::upload_image,
+                api::get_gallery,
+                api::add_gallery_image,
+                api::update_gallery_image,
+                api::reorder_gallery,
+                api::remove_gallery_image,
                 api::add_cart,
                 api::get_cart,
                 api::get_cart_count,
                 api::remove_cart,
+                api::update_cart,
+                api::hold_cart,
                 api::get_admins,
                 api::delete_admin,
+                api::list_permissions,
+                api::get_admin_permissions,
+                api::grant_permission,
+                api::revoke_permission,
                 api::add_product,
                 api::update_product,
                 api::remove_product,
+                api::get_product,
+                api::get_product_by_slug,
+                api::get_variants_for_product,
+                api::put_product,
+                api::delete_product,
+                api::archive_product,
+                api::unarchive_product,
+                api::get_archived_products,
+                api::get_attributes,
+                api::create_attribute,
+                api::add_attribute_values,
+                api::delete_attribute_value,
                 api::create_order,
                 api::clear_cart,
                 api::get_product_details,
@@ -1511,4 +8036,4 @@ async fn rocket() -> _ {
                 api::get_all_customers,
             ],
         )
-}
\ No newline at end of file
+}